@@ -1,7 +1,12 @@
+#![cfg_attr(feature = "diagnostics", feature(proc_macro_diagnostic, proc_macro_tracked_env))]
+
 use std::fmt::Write;
 
 use proc_macro::{Delimiter, Spacing, TokenTree};
 
+mod sources;
+use sources::{parse_source, SOURCES};
+
 /// Duplicates the given code and substitutes specific identifiers for different code snippets in each duplicate.
 ///
 /// ## Usage
@@ -15,7 +20,6 @@ use proc_macro::{Delimiter, Spacing, TokenTree};
 ///     let &var = ['a', 'b'];
 ///     println!("{}", *var);
 /// }
-
 /// ```
 /// Will get copied 2 times, because the variable `&var` has 2 values.
 ///
@@ -89,6 +93,21 @@ use proc_macro::{Delimiter, Spacing, TokenTree};
 /// }
 /// ```
 ///
+/// Values are separated by the commas outside of generics, so types like `HashMap<K, V>` can be listed as they are, like `let &ty = [Vec<u8>, HashMap<K, V>, (A, B)];`.
+/// Comparisons like `1 < 2` are still read as such, and any other value can be enclosed in brackets `{...}`.
+///
+/// Variables can use other variables in their values, even the ones declared after them, so declarations can be in any order.
+/// A variable that uses itself, directly or through the values of others, is an error naming the whole chain:
+/// ```compile_fail
+/// # use akin::akin;
+/// akin! {
+///     let &a = [*b];
+///     let &b = [*a]; // '&b' references itself through '&b -> &a -> &b'
+///     println!("{}", *b);
+/// }
+/// ```
+/// Declaring a variable again can still use its previous values, like `let &a = [*a + 1];`.
+///
 /// ## Range syntax
 /// As a matter of convenience, the range syntax is also accepted, when declaring a variable,
 /// e.g. `0..3` and `0..=3`, which are equivalent to `[0,1,2]` and `[0,1,2,3]` respectively.
@@ -99,8 +118,21 @@ use proc_macro::{Delimiter, Spacing, TokenTree};
 /// let &b = 4..=6;
 /// ```
 ///
-/// Presently, only unsigned integers that can fit in `u64` are supported in ranges, i.e. ranges
-/// like `-10..-1` or `'a'..'c'`, which are fine in regular Rust, aren't accepted by `akin`.
+/// Ranges of chars are accepted too, like `'a'..='z'`, and give one char literal for each value.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &c = 'a'..='c';
+///     v.push(*c);
+/// }
+/// # assert_eq!(v, ['a', 'b', 'c']);
+/// ```
+///
+/// Integer ranges can also be negative, like `-10..=10`, and keep the type suffix of their start, like `0u8..3` giving `0u8, 1u8, 2u8`.
+/// Float ranges need a step, like `0.0..=1.0 step 0.25`, and give float literals with as many decimals as the bounds and step.
+/// The end of an integer range can be the length of another variable, like `0..len(&types)`, so both always stay in sync.
+/// Adding `as hex` writes the values as hex literals of the same width, like `0x00..=0xFF as hex` giving `0x00, 0x01, ..., 0xFF`.
 ///
 /// ## NONE
 /// `NONE` is the way you can tell `akin` to simply skip that value and not write anything.
@@ -120,456 +152,4411 @@ use proc_macro::{Delimiter, Spacing, TokenTree};
 ///
 ///     println!("*num^2 = {}", *num~u32*code);
 ///     // *num~u32 is necessary to ensure the type is written correctly (it would be "1 u32" without it)
-///     # writeln!(&mut out, "*num^2 = *numu32*code");
+///     # writeln!(&mut out, "*num^2 = *num~u32*code");
 /// }
-/// # assert_eq!(out, "1^2 = 1u32\n2^2 = 2u32 . pow( 2)\n3^2 = 3u32 . pow( 2)\n");
+/// # assert_eq!(out, "1^2 = 1u32\n2^2 = 2u32.pow(2)\n3^2 = 3u32.pow(2)\n");
 /// ```
 ///
-/// ## Joint modifier
-/// By default, `akin` places a space between all identifiers.
-/// Sometimes, this is not desirable, for example, if trying to interpolate between a function name
-/// ```compile_fail
+/// A value repeated `N` times can be written like an array, `[value; N]`, which is handy to line up lists with `NONE`.
+/// ```
 /// # use akin::akin;
+/// # let mut v = Vec::new();
 /// akin! {
-///     let &name = [1];
-///     fn _*name()
-///     # {}
+///     let &name = [a, b, c];
+///     let &suffix = [NONE; 3];
+///     v.push("*name*suffix");
 /// }
+/// # assert_eq!(v, ["a", "b", "c"]);
 /// ```
-/// Will get wrongly expanded because '_' is an identifier
-/// ```compile_fail
-/// fn _ 1()
+///
+/// `SKIP` drops the whole copy it's used in, instead of writing nothing in its place.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &num = [1, 2, 3];
+///     let &name = [one, SKIP, three];
+///     v.push((*num, "*name"));
+/// }
+/// # assert_eq!(v, [(1, "one"), (3, "three")]);
 /// ```
-/// To avoid it, use the joint modifier `~`, making the next identifier not to be separated.
+///
+/// A value can be gated by `#[cfg(...)]`, which is added to every copy that uses it.
 /// ```
 /// # use akin::akin;
+/// trait Bits { const BITS: u32; }
 /// akin! {
-///     let &name = [1];
-///     fn _~*name() // *name is affected by the modifier
-/// # {}
+///     let &int = [u8, u16, #[cfg(feature = "wide")] u128];
+///     impl Bits for *int {
+///         const BITS: u32 = *int::BITS;
+///     }
 /// }
-/// # _1();
+/// # assert_eq!(<u16 as Bits>::BITS, 16);
 /// ```
-/// Will get correctly expanded to
+///
+/// ## Records
+/// Values can also be records with named fields, which are referenced with `*var.field`.
 /// ```
-/// fn _1()
-/// # {}
+/// # use akin::akin;
+/// akin! {
+///     let &method = [
+///         {name: into_bool, ty: bool, value: true},
+///         {name: into_vec, ty: Vec<u8>, value: { vec![1, 2] }}
+///     ];
+///
+///     fn *method.name() -> *method.ty {
+///         *method.value
+///     }
+/// }
+/// # assert_eq!(into_vec(), [1, 2]);
 /// ```
-/// Inside string literals `"..."` it is not necessary to use the modifier, as Rust does not count them as identifiers.
+/// Fields that are missing in a value, or are `NONE`, are left empty.
+/// Field values can be enclosed in brackets `{...}` if they contain commas.
 ///
-/// This is a limitation on proc_macro parsing, so I doubt it'll be fixed soon.
+/// ## Value sources
+/// Values can also be read from a data file with `file(path, key)`, where `path` is relative to the crate root.
+/// JSON and TOML files are supported, and `key` is a dotted path to the values, which goes through every element of arrays.
+/// Tables are read as records.
+/// ```
+/// # use akin::akin;
+/// // tests/data/opcodes.toml:
+/// // [[opcodes]]
+/// // name = "NOP"
+/// // code = 0x00
+/// // ...
+/// akin! {
+///     let &op = file("tests/data/opcodes.toml", "opcodes");
+///     const *op.name: u8 = *op.code;
+/// }
+/// # assert_eq!(ADD, 0x10);
+/// ```
+/// Elements that lack the key, and `null` values, are left empty.
 ///
-/// ## More examples
+/// `csv(path)` reads the rows of a CSV file, and can be declared as a tuple with one variable per column.
+/// `columns = [...]` selects the columns by index, or by name with `header = true`, which skips the first row.
 /// ```
-/// trait Sqrt {
-///     fn dumb_sqrt(self) -> Result<f64, &'static str>;
+/// # use akin::akin;
+/// // tests/data/errors.csv:
+/// // name,message,code
+/// // NotFound,not found,404
+/// // ...
+/// akin! {
+///     let &(name, code) = csv("tests/data/errors.csv", columns = [0, 2], header = true);
+///     const *name: u16 = *code;
 /// }
+/// # assert_eq!(NotFound, 404);
+/// ```
+/// Declared as a single variable, every value is a row, with the columns as fields if the file has a header.
 ///
+/// `env(name)` reads the value of an environment variable while expanding, split into several values with `split = "sep"`.
+/// If the variable isn't set, `default = "..."` is used instead.
+/// ```
 /// # use akin::akin;
+/// # let mut v = Vec::new();
 /// akin! {
-///     let &int_type = [i64, u64];
-///     let &negative_check = [
-///         {
-///             if self < 0 {
-///                 return Err("Sqrt of negative number")
-///             }
-///         },
-///         NONE
-///     ];
+///     let &feature = env("MY_FEATURES", split = ",", default = "json, toml");
+///     v.push(*feature:str);
+/// }
+/// # assert_eq!(v, ["json", "toml"]);
+/// ```
 ///
-///     let &num = [1,     2,    3,  4];
-///     let &res = [1., 1.41, 1.73,  2.];
-///     let &branch = {
-///         *num => Ok(*res),
-///     };
+/// `lines(path)` reads a text file relative to the current file, with one value for each line that isn't empty.
+/// ```ignore
+/// akin! {
+///     let &keyword = lines("keywords.txt");
+///     const *keyword:upper: &str = *keyword:str;
+/// }
+/// ```
 ///
-///     impl Sqrt for *int_type {
-///         fn dumb_sqrt(self) -> Result<f64, &'static str> {
-///             *negative_check
+/// The files read are tracked, so editing them expands the macro again.
 ///
-///             match self {
-///                 *branch
-///                 _ => Err("Sqrt of num not in [1, 4]")
-///             }
+/// `variants_of { enum ... }` reads the variants of an enum, with a pattern that matches each of them in `*var.pattern`.
+/// With `variants_of(emit)`, the enum is also added to the output.
+/// ```
+/// # use akin::akin;
+/// akin! {
+///     let &variant = variants_of(emit) {
+///         enum Shape { Point, Circle(f32), Rect { w: f32, h: f32 } }
+///     };
+///     let &arms = { Shape::*variant.pattern => *variant:lower:str, };
+///
+///     fn name(shape: Shape) -> &'static str {
+///         match shape {
+///             *arms
 ///         }
 ///     }
 /// }
-///
-/// # assert_eq!(10i64.dumb_sqrt(), Err("Sqrt of num not in [1, 4]"));
-/// # assert_eq!(15u64.dumb_sqrt(), Err("Sqrt of num not in [1, 4]"));
-/// # assert_eq!(2u64.dumb_sqrt(), Ok(1.41));
-/// # assert_eq!(3i64.dumb_sqrt(), Ok(1.73));
-/// # assert_eq!((-5i64).dumb_sqrt(), Err("Sqrt of negative number"));
+/// # assert_eq!(name(Shape::Circle(1.0)), "circle");
 /// ```
-/// Is expanded to:
-/// ```
-/// trait Sqrt {
-///     fn dumb_sqrt(self) -> Result<f64, &'static str>;
-/// }
 ///
-/// impl Sqrt for i64 {
-///     fn dumb_sqrt(self) -> Result<f64, &'static str> {
-///         if self < 0 {
-///             return Err("Sqrt of negative number")
+/// `fields_of { struct ... }` reads the fields of a struct, with their names and types in `*var.name` and `*var.ty`.
+/// Like other sources with several values for each element, it can also be declared as a tuple.
+/// ```
+/// # use akin::akin;
+/// akin! {
+///     let &(field, ty) = fields_of(emit) {
+///         #[derive(Default)]
+///         struct Config { name: String, retries: u8 }
+///     };
+///     let &getters = {
+///         fn *field(&self) -> &*ty {
+///             &self.*field
 ///         }
+///     };
 ///
-///         match self {
-///             1 => Ok(1.),
-///             2 => Ok(1.41),
-///             3 => Ok(1.73),
-///             4 => Ok(2.),
-///             _ => Err("Sqrt of num not in [1, 4]")
-///         }
+///     impl Config {
+///         *getters
 ///     }
 /// }
+/// # assert_eq!(Config::default().retries(), &0);
+/// ```
 ///
-/// impl Sqrt for u64 {
-///     fn dumb_sqrt(self) -> Result<f64, &'static str> {
-///         match self {
-///             1 => Ok(1.),
-///             2 => Ok(1.41),
-///             3 => Ok(1.73),
-///             4 => Ok(2.),
-///             _ => Err("Sqrt of num not in [1, 4]")
+/// `methods_of { trait ... }` reads the methods of a trait, which is useful to delegate them.
+/// Each method has its `*var.name`, `*var.generics`, `*var.params`, return type `*var.ret` and `*var.where` clause,
+/// and `*var.args` are the parameters without `self` and their types, to call another implementation with.
+/// ```
+/// # use akin::akin;
+/// # struct Wrapper(Vec<u8>);
+/// akin! {
+///     let &method = methods_of(emit) {
+///         trait Len {
+///             fn len(&self) -> usize;
+///             fn is_longer(&self, than: usize) -> bool;
+///         }
+///     };
+///     let &delegated = {
+///         fn *method.name *method.generics(*method.params) -> *method.ret *method.where {
+///             <Vec<u8> as Len>::*method.name(&self.0, *method.args)
 ///         }
+///     };
+///
+///     impl Len for Vec<u8> {
+///         fn len(&self) -> usize { Vec::len(self) }
+///         fn is_longer(&self, than: usize) -> bool { Vec::len(self) > than }
+///     }
+///     impl Len for Wrapper {
+///         *delegated
 ///     }
 /// }
+/// # assert!(Wrapper(vec![1, 2]).is_longer(1));
 /// ```
-#[proc_macro]
-pub fn akin(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
-    let mut vars: Map<String, Vec<String>> = Map::new();
-    //panic!("Tokens: {input:#?}");
-    let mut tokens: Lookahead = input.into_iter().into();
-
-    while let Some((name, values)) = parse_var(&mut tokens, &vars) {
-        vars.insert(name, values);
-    }
-
-    let mut prev = None;
-    let mut out_raw = String::new();
-    for tt in tokens {
-        fold_tt(&mut out_raw, tt, &mut prev);
-    }
-
-    let out = duplicate(&out_raw, &vars);
-
-    //let tokens = format!("proc_macro: {:#?}", input.into_iter().collect::<Vec<_>>());
-    //let tokens = format!("vars: {:#?}", vars);
-    //panic!("\nVars: {vars:#?}\nRaw: {out_raw}\nOut: {out}\n");
-
-    out.parse().unwrap()
-}
-
-struct Lookahead {
-    queue: [Option<TokenTree>; 2],
-    iter: proc_macro::token_stream::IntoIter,
-}
-
-impl Lookahead {
-    fn queue_pop(&mut self) -> Option<TokenTree> {
-        if let Some(tt) = self.queue[0].take() {
-            self.queue[0] = self.queue[1].take();
-            return Some(tt);
-        }
-        None
-    }
-
-    fn queue_push(&mut self, elem: TokenTree) {
-        if self.queue[0].is_none() {
-            self.queue[0] = Some(elem)
-        } else if self.queue[1].is_none() {
-            self.queue[1] = Some(elem)
-        } else {
-            panic!("akin: internal bug, lookahead buffer size exceeded")
-        }
-    }
-
-    fn peek_nth(&mut self, i: usize) -> Option<&TokenTree> {
-        assert!(i < self.queue.len(), "akin: internal bug, lookahead buffer index too big: {}", i);
-        while self.queue[i].is_none() {
-            if let Some(elem) = self.iter.next() {
-                self.queue_push(elem);
-            } else {
-                return None;
-            }
-        }
-        self.queue[i].as_ref()
-    }
-}
-
-impl Iterator for Lookahead {
-    type Item = <proc_macro::token_stream::IntoIter as Iterator>::Item;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        self.queue_pop().or_else(|| self.iter.next())
-    }
-}
-
-impl From<proc_macro::token_stream::IntoIter> for Lookahead {
-    fn from(iter: proc_macro::token_stream::IntoIter) -> Self {
-        Lookahead { queue: Default::default(), iter }
-    }
-}
-
-fn parse_var(
-    tokens: &mut Lookahead,
-    vars: &Map<String, Vec<String>>,
-) -> Option<(String, Vec<String>)> {
-    if !matches!(tokens.peek_nth(0), Some(TokenTree::Ident(id)) if id.to_string() == "let") {
-        return None;
-    }
-
-    if !matches!(tokens.peek_nth(1), Some(TokenTree::Punct(p)) if p.as_char() == '&') {
-        return None;
-    }
-
-    tokens.next();
-    tokens.next();
-
-    let name = format!(
-        "*{}",
-        tokens.next().expect("akin: expected variable name after 'let &'")
-    );
-
-    if !matches!(tokens.next(), Some(TokenTree::Punct(p)) if p.as_char() == '=') {
-        panic!( "akin: expected '=' after variable name '&{}'", &name[1..]);
-    }
-
-    let group = match tokens.next() {
-        Some(TokenTree::Group(g)) => g,
-        Some(l @ TokenTree::Literal(_)) => {
-            tokens.queue_push(l);
-            let values = parse_range_expr(&name[1..], tokens);
-            return Some((name, values));
-        },
-        tt => panic!("akin: expected bracketed/braced group or range expression after '&{}=', got {:?}", &name[1..], tt),
-    };
-
-    let mut values: Vec<String> = Vec::new();
-
-    if group.delimiter() == Delimiter::Bracket {
-        let mut stream = group.stream().into_iter();
-
-        while let Some(mut var) = stream.next() {
-            let mut new = String::new();
-            while !matches!(&var, TokenTree::Punct(p) if p.as_char() == ',') {
-                match &var {
-                    TokenTree::Group(g) if g.delimiter() == Delimiter::Brace => {
-                        let mut prev = None;
-                        for tt in g.stream() {
-                            fold_tt(&mut new, tt, &mut prev)
-                        }
-                    },
-                    _ => write!(&mut new, "{var}").unwrap(),
-                };
-
-                if let Some(v) = stream.next() {
-                    var = v;
-                } else {
-                    break;
-                }
-            }
-
-            if new == "NONE" {
-                values.push(String::new())
-            } else {
-                values.push(duplicate(&new, vars));
-            }
-        }
-    } else {
-        let mut fold = String::new();
-        let mut prev = None;
-        for tt in group.stream() {
-            fold_tt(&mut fold, tt, &mut prev)
-        }
-        values.push(duplicate(&fold, vars));
-    }
-
-    if !matches!(tokens.next(), Some(TokenTree::Punct(p)) if p.as_char() == ';') {
-        panic!( "akin: expected ';' on end of '&{}' declaration", &name[1..]);
-    }
-
-    Some((name, values))
-}
+///
+/// ## Aliases
+/// `let &b = &a;` declares `b` with the same values and fields as `a`, which is useful to give a long list a shorter name.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &supported_integer_types = [u8, u16, u32];
+///     let &int = &supported_integer_types;
+///     v.push(*int::MAX as u64);
+/// }
+/// # assert_eq!(v, [255, 65535, 4294967295]);
+/// ```
+///
+/// A slice of a variable is declared like in Rust, `let &b = &a[1..3];`, and its `*b.next` and `*b.prev` are the values around it.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &reg = [r0, r1, r2, r3];
+///     let &arg = &reg[1..];
+///     v.push(stringify!(*arg));
+/// }
+/// # assert_eq!(v, ["r1", "r2", "r3"]);
+/// ```
+///
+/// Values can be added to a declared variable with `let &a += ...;`, which takes the same values as a declaration.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &ty = [u8, u16];
+///     let &ty += [u32];
+///     v.push(*ty::BITS);
+/// }
+/// # assert_eq!(v, [8, 16, 32]);
+/// ```
+///
+/// ## List operations
+/// Variables can be declared from other variables, written `&var`, or lists `[...]`.
+///
+/// `concat(...)` joins all the values of several lists, one after another.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &signed = [i8, i16];
+///     let &unsigned = [u8, u16];
+///     let &int = concat(&signed, &unsigned, [i128]);
+///     v.push(*int::BITS);
+/// }
+/// # assert_eq!(v, [8, 16, 8, 16, 128]);
+/// ```
+///
+/// `idents(prefix, range)` generates numbered identifiers, like `T1, T2, T3` for `idents(T, 1..=3)`.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &T = cumulative idents(T, 1..=3);
+///     v.push("*T");
+/// }
+/// # assert_eq!(v, ["T1", "T1, T2", "T1, T2, T3"]);
+/// ```
+///
+/// `split(text, sep)` splits a string into values, on commas if `sep` isn't given.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &perm = split("read, write, execute");
+///     v.push(*perm:str);
+/// }
+/// # assert_eq!(v, ["read", "write", "execute"]);
+/// ```
+///
+/// `rev(list)` gives the values of a list in reverse order.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &forward = [1, 2, 3];
+///     let &back = rev(&forward);
+///     v.push((*forward, *back));
+/// }
+/// # assert_eq!(v, [(1, 3), (2, 2), (3, 1)]);
+/// ```
+///
+/// `sort(list)` sorts the values of a list, numerically if they are numbers, so `8` goes before `16`.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &size = sort([64, 8, 16, 0x20]);
+///     v.push(*size);
+/// }
+/// # assert_eq!(v, [8, 16, 32, 64]);
+/// ```
+///
+/// `dedup(list)` removes the repeated values of a list, keeping the first one.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &ty = dedup([u8, u16, u8, u32, u16]);
+///     v.push(*ty::BITS);
+/// }
+/// # assert_eq!(v, [8, 16, 32]);
+/// ```
+///
+/// `filter(list, predicates...)` keeps the values that match all the predicates, `starts_with`, `ends_with` and `contains`.
+/// With `field = "name"`, they test the values of that field of records.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &ty = filter([u8, i16, u32, u64], starts_with = "u", contains = "3");
+///     v.push(*ty::BITS);
+/// }
+/// # assert_eq!(v, [32]);
+/// ```
+///
+/// `map(list, modifiers)` applies [modifiers](#modifiers) to the values of a list, like `map(&name, :upper)`.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &name = [http_get, http_post];
+///     let &Name = map(&name, :strip_prefix("http_"):camel);
+///     v.push(stringify!(*Name));
+/// }
+/// # assert_eq!(v, ["Get", "Post"]);
+/// ```
+///
+/// `chunks(list, n)` and `windows(list, n)` group the values of a list like the slice methods,
+/// each group separated by commas and with the fields `0`, `1`, ... for its values.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &lanes = chunks([1, 2, 3, 4, 5], 2);
+///     let &pair = windows([1, 2, 3], 2);
+///     v.push(([*lanes].len(), *pair.0 + *pair.1));
+/// }
+/// # assert_eq!(v, [(2, 3), (2, 5), (1, 5)]);
+/// ```
+///
+/// `union(lists...)`, `intersect(lists...)` and `difference(lists...)` combine lists like sets, keeping the order of their values.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &int = [u8, u16, u32, u64];
+///     let &manual = [u16, u64];
+///     let &generated = difference(&int, &manual);
+///     v.push(*generated::BITS);
+/// }
+/// # assert_eq!(v, [8, 32]);
+/// ```
+///
+/// `zip(lists...)` binds lists of the same length together, declared as a tuple of variables, or as a variable with the tuples of their values.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &(name, code) = zip([ok, not_found], [200, 404]);
+///     let &pair = zip(&name, &code);
+///     v.push((stringify!(*name), *code, stringify!(*pair), *pair.1));
+/// }
+/// # assert_eq!(v, [("ok", 200, "(ok, 200)", 200), ("not_found", 404, "(not_found, 404)", 404)]);
+/// ```
+///
+/// `cross(lists...)` is like `zip`, but goes through every combination of their values.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &(from, to) = cross([u8, u16], [u32, u64]);
+///     v.push((*from::BITS, *to::BITS));
+/// }
+/// # assert_eq!(v, [(8, 32), (8, 64), (16, 32), (16, 64)]);
+/// ```
+///
+/// `enumerate(list)` pairs each value of a list with its index.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &(i, ty) = enumerate([u8, u16, u32]);
+///     v.push((*i, *ty::BITS));
+/// }
+/// # assert_eq!(v, [(0, 8), (1, 16), (2, 32)]);
+/// ```
+///
+/// `permutations(list, k)` and `combinations(list, k)` go through every way to pick `k` values of a list, declared like `zip`.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &(a, b) = combinations([1, 2, 3], 2);
+///     v.push((*a, *b));
+/// }
+/// # assert_eq!(v, [(1, 2), (1, 3), (2, 3)]);
+/// ```
+///
+/// ## Stride
+/// With `step(n)` at the end of the declaration, only one every `n` values is kept, starting with the first.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &n = 0..10 step(3);
+///     v.push(*n);
+/// }
+/// # assert_eq!(v, [0, 3, 6, 9]);
+/// ```
+/// It can also be written like a step in a range, `0..=64 step 8` or `0..=64.step_by(8)`.
+///
+/// ## Cumulative values
+/// With `cumulative` before its values, a variable expands on each copy to all its values up to the current one, separated by commas.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &T = cumulative [a, b, c];
+///     v.push("*T");
+/// }
+/// # assert_eq!(v, ["a", "a, b", "a, b, c"]);
+/// ```
+///
+/// ## Pairs
+/// `let &(a, b) = pairs [...];` declares two variables that go through every pair of values `(i, j)` with `i <= j`.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &(a, b) = pairs [x, y, z];
+///     v.push("*a*b");
+/// }
+/// # assert_eq!(v, ["xx", "xy", "xz", "yy", "yz", "zz"]);
+/// ```
+///
+/// ## Next and previous values
+/// `*var.next` and `*var.prev` are the values of `var` in the following and previous copy.
+/// They're empty when there is no such copy, unless another value is given with `next` or `prev` at the end of the declaration.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &state = [Idle, Running, Done] next Idle prev Start;
+///     v.push(("*state.prev", "*state", "*state.next"));
+/// }
+/// # assert_eq!(v, [("Start", "Idle", "Running"), ("Idle", "Running", "Done"), ("Running", "Done", "Idle")]);
+/// ```
+///
+/// ## Number of values
+/// `*var.len` is the number of values of `var`, which doesn't change between copies.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &ty = [u8, u16, u32];
+///     v.push(("*ty", *ty.len));
+/// }
+/// # assert_eq!(v, [("u8", 3), ("u16", 3), ("u32", 3)]);
+/// ```
+///
+/// ## Indexed access
+/// `*var.[i]` is the `i`-th value of `var`, starting at `0`, whatever the current copy.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &n = [10, 20, 30];
+///     v.push((*n, *n.[0]));
+/// }
+/// # assert_eq!(v, [(10, 10), (20, 10), (30, 10)]);
+/// ```
+/// `*var[i]` is still the value of the current copy indexed by `i`, like `A[0]` for `&var = [A]`.
+/// `*var.first` and `*var.last` are the first and last values of `var`.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &n = [10, 20, 30];
+///     v.push((*n.first, *n, *n.last));
+/// }
+/// # assert_eq!(v, [(10, 10, 30), (10, 20, 30), (10, 30, 30)]);
+/// ```
+/// Followed by `(`, these are method calls on the value instead, like `*v.len()` or `*iter.next()`.
+///
+/// ## Nested loops
+/// `for &var in [...] {...}` copies its body once per value of `var`, nesting it inside the outer repetition.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &ty = [u8, u16];
+///     for &op in [min, max] {
+///         v.push(*ty::*op(1, 2) as u32);
+///     }
+/// }
+/// # assert_eq!(v, [1, 2, 1, 2]);
+/// ```
+/// The braces of the loop are not written to the output.
+///
+/// ## Scoped blocks
+/// A block `{...}` that starts with variable declarations opens a scope: its variables are only visible inside,
+/// and can shadow outer ones. The block is copied independently of the outer variables, and its brackets are removed.
+///
+/// Only declarations whose values can't be Rust code, like `[...]` or `csv(...)`, open a scope,
+/// so a Rust block like `{ let &x = r; ... }` is written as is.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &t = [a, b];
+///     {
+///         let &t = [x, y];
+///         v.push("*t");
+///     }
+///     v.push("*t");
+/// }
+/// # assert_eq!(v, ["x", "y", "a", "x", "y", "b"]);
+/// ```
+///
+/// ## Expansion-time match
+/// `@match *var { pattern | pattern => {...}, _ => {...} }` writes the body of the first arm whose pattern is equal to the value of `*var`
+/// in the current copy, or nothing if no arm matches.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &ty = [u8, u16, i32];
+///     v.push(@match *ty {
+///         u8 | u16 => { "small" },
+///         _ => "other",
+///     });
+/// }
+/// # assert_eq!(v, ["small", "small", "other"]);
+/// ```
+///
+/// ## Directives
+/// Directives are written as `#!directive` at the start of the invocation, before any variable.
+///
+/// `#!product` copies the code once per combination of values, instead of advancing all variables in lockstep.
+/// The variable used first changes the slowest.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     #!product
+///     let &a = [1, 2];
+///     let &b = [x, y];
+///     v.push("*a*b");
+/// }
+/// # assert_eq!(v, ["1x", "1y", "2x", "2y"]);
+/// ```
+///
+/// Some combinations can be left out with `#!product except [...]`, in the same order as the variables are used.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     #!product except [(1, x), (2, y)]
+///     let &a = [1, 2];
+///     let &b = [x, y];
+///     v.push("*a*b");
+/// }
+/// # assert_eq!(v, ["1y", "2x"]);
+/// ```
+///
+/// `#!repeat(n)` copies the code exactly `n` times, whatever the number of values of its variables.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     #!repeat(3)
+///     v.push(*INDEX);
+/// }
+/// # assert_eq!(v, [0, 1, 2]);
+/// ```
+///
+/// `#!sigil(c)` references variables with `c` instead of `*`, so a real `*` is always written as is.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     #!sigil($)
+///     let &x = [1, 2];
+///     let y = &3;
+///     v.push($x * *y);
+/// }
+/// # assert_eq!(v, [3, 6]);
+/// ```
+///
+/// An invocation can make at most 100,000 copies, taking at most 64 MiB, so a product of long lists fails right away
+/// instead of hanging the compiler. `#!max_copies(n)` and `#!max_bytes(n)` raise or lower these limits.
+/// ```compile_fail
+/// # use akin::akin;
+/// akin! {
+///     #!product
+///     let &a = 0..1024;
+///     let &b = 0..1024;
+///     let _ = *a + *b; // the code would be copied 1,048,576 times, over the limit of 100,000
+/// }
+/// ```
+///
+/// The values of a variable are checked against the same limit while they're made, so a range or a `cross(...)` that's too long
+/// fails before any of them is written.
+/// ```compile_fail
+/// # use akin::akin;
+/// akin! {
+///     let &x = 0.0..=1.0 step 1e-12; // '&x' would have 1,000,000,000,001 values, over the limit of 100,000
+///     let _ = *x;
+/// }
+/// ```
+/// ```compile_fail
+/// # use akin::akin;
+/// akin! {
+///     let &a = 0..1024;
+///     let &(x, y) = cross(&a, &a); // '&x, y' would have 1,048,576 values, over the limit of 100,000
+///     let _ = *x + *y;
+/// }
+/// ```
+/// ```compile_fail
+/// # use akin::akin;
+/// akin! {
+///     let &a = 0..64;
+///     let &(x, y, z, w) = permutations(&a, 4); // '&x, y, z, w' would have 15,249,024 values, over the limit of 100,000
+///     let _ = *x + *y + *z + *w;
+/// }
+/// ```
+///
+/// A variable that's never used, in the code nor in the values of another one, is warned about, as it's almost always a mistake,
+/// like a leftover or a misspelled name. `#!deny(unused)` makes it an error, and `#!allow(unused)` silences it.
+/// ```compile_fail
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     #!deny(unused)
+///     let &x = [1, 2];
+///     let &stale = [3, 4, 5];
+///     v.push(*x);
+/// }
+/// ```
+///
+/// `#!warn(ambiguous)` or `#!deny(ambiguous)` also check that no name starts with another one, like `&foo` and `&foobar`,
+/// where `*foobar` could be mistaken for `*foo~bar`.
+/// It's allowed by default, as names are only matched as a whole and pairs like `&field` and `&fields` are usually on purpose.
+/// ```compile_fail
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     #!deny(ambiguous)
+///     let &foo = [1, 2];
+///     let &foobar = [3, 4];
+///     v.push(*foo + *foobar);
+/// }
+/// ```
+///
+/// `misspelled` is warned about by default too, when a name that isn't declared looks like a misspelling of a variable, like `*nmae` for `&name`.
+///
+/// On a nightly compiler, the `diagnostics` feature shows these warnings with the compiler's own diagnostics,
+/// and adds a note when a variable reuses its last value because it's used with a longer one.
+///
+/// `#!strict` makes it an error to use variables with a different number of values together,
+/// instead of reusing the last value of the shorter ones. Variables with a single value are still allowed.
+/// ```compile_fail
+/// # use akin::akin;
+/// akin! {
+///     #!strict
+///     let &a = [1, 2, 3];
+///     let &b = [4, 5];
+///     println!("*a *b");
+/// }
+/// ```
+///
+/// `#!mismatch(policy)` chooses what the shorter variables do instead: `last` reuses their last value, which is the default,
+/// `cycle` starts again from their first value, `pad` writes nothing, like `NONE`, and `error` is the same as `#!strict`.
+/// A `mismatch(policy)` clause at the end of a declaration chooses it only for that variable.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     #!mismatch(cycle)
+///     let &lane = [0, 1, 2, 3, 4, 5];
+///     let &reg = [x, y];
+///     let &suffix = [_a] mismatch(pad);
+///     v.push((*lane, stringify!(*reg~*suffix)));
+/// }
+/// # assert_eq!(v, [(0, "x_a"), (1, "y"), (2, "x"), (3, "y"), (4, "x"), (5, "y")]);
+/// ```
+///
+/// ## Built-in variables
+/// These variables are always available, and refer to the copies of the innermost value, loop or code being duplicated:
+/// - `*INDEX`: index of the current copy, starting at `0`.
+/// - `*RINDEX`: index of the current copy counting from the end, down to `0` in the last one.
+/// - `*COUNT`: total number of copies.
+/// - `*FIRST` and `*LAST`: `true` on the first and last copy respectively, `false` otherwise.
+/// - `*GENSYM`: an identifier unique to the copy, which no other copy or invocation uses, like `__akin_5e0c12a4_0`.
+///   It only depends on the invocation, where it is and what it contains, so it is the same in every build.
+///   `*GENSYM(tag)` adds `tag` at its end, so a copy can have more than one.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &name = [a, b, c];
+///     v.push((*INDEX, *COUNT, "*name"));
+/// }
+/// # assert_eq!(v, [(0, 3, "a"), (1, 3, "b"), (2, 3, "c")]);
+/// ```
+/// A declared variable with the same name takes precedence.
+///
+/// ## Doc comments
+/// Variables are also substituted inside doc comments, where their values are escaped as needed.
+/// ```
+/// # use akin::akin;
+/// akin! {
+///     let &ty = [u8, u16];
+///     let &example = ["0", "1"];
+///     /// Wrapper around `*ty`, like `W(*example)`.
+///     pub struct W~*ty(pub *ty);
+/// }
+/// ```
+///
+/// ## Modifiers
+/// A variable followed by `:modifier`, without spaces, expands to its value transformed by the modifier:
+/// - `*var:str`: the value as a string literal.
+/// - `*var:pad(n)`: the value padded with zeros on the left up to `n` characters, like `007`.
+/// - `*var:join(sep)`: all the values of the variable at once, separated by `sep`, in every copy.
+/// - `*var:strip_prefix(p)` and `*var:strip_suffix(s)`: the value without the prefix `p` or suffix `s`, if it has it.
+/// - `*var:hex` and `*var:bin`: the integer value in hexadecimal or binary, like `0x1F` and `0b11111`.
+///   `hex(n)` and `bin(n)` pad it with zeros up to `n` digits.
+/// - `*var:upper` and `*var:lower`: the value in uppercase or lowercase.
+/// - `*var:snake` and `*var:camel`: the value in `snake_case` or `CamelCase`.
+/// - `*var:capitalize`: the value with its first character in uppercase, leaving the rest as is.
+/// - `*var:ordinal` and `*var:words`: the integer value as an ordinal, like `2nd`, or in words, like `two`.
+///   They aren't valid Rust tokens on their own, so they are usually followed by `:str`.
+/// - `*var:hash`: a short hash of the value, like `29623686` for `+`, which is the same across compilations.
+///   Useful to build unique identifiers from values that can't be part of one, like `fn check_~*op:hash()`.
+///
+/// Modifiers can be chained, and are applied from left to right.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &ty = [RawHttpClient, RawFileServer];
+///     v.push(*ty:strip_prefix(Raw):snake:upper:str);
+/// }
+/// # assert_eq!(v, ["HTTP_CLIENT", "FILE_SERVER"]);
+/// ```
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &ty = [u8, Vec<u8>];
+///     v.push(*ty:str);
+/// }
+/// # assert_eq!(v, ["u8", "Vec<u8>"]);
+/// ```
+///
+/// ## Arithmetic
+/// `*{...}` evaluates an integer expression during the expansion, in which variables are written without `*`.
+/// Any variable can be used, including fields and accessors like `rec.field` or `n.next`.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &n = [1, 2, 3];
+///     v.push(*{(n + 1) * 10 + INDEX});
+/// }
+/// # assert_eq!(v, [20, 31, 42]);
+/// ```
+///
+/// ## Joint modifier
+/// By default, `akin` places a space between all identifiers.
+/// Sometimes, this is not desirable, for example, if trying to interpolate between a function name
+/// ```compile_fail
+/// # use akin::akin;
+/// akin! {
+///     let &name = [1];
+///     fn _*name()
+///     # {}
+/// }
+/// ```
+/// Will get wrongly expanded because '_' is an identifier
+/// ```compile_fail
+/// fn _ 1()
+/// ```
+/// To avoid it, use the joint modifier `~`, which glues the tokens on both of its sides.
+/// ```
+/// # use akin::akin;
+/// akin! {
+///     let &name = [1];
+///     fn _~*name() // *name is affected by the modifier
+/// # {}
+/// }
+/// # _1();
+/// ```
+/// Will get correctly expanded to
+/// ```
+/// fn _1()
+/// # {}
+/// ```
+/// It also marks where a variable ends, so suffixes can be pasted after it: with `&name = [foo]`,
+/// `*name~_impl` is expanded to `foo_impl`, even if a variable `&name_impl` exists.
+///
+/// Punctuation is spaced like rustfmt would, so a value like `{ .pow(2) }` is written as `.pow(2)`, and `a, b` or `u32::MAX` keep their spacing.
+///
+/// Raw identifiers like `r#type` are kept as they are, and lose their `r#` when glued after another identifier, so `get_~*field` is expanded to `get_type`.
+/// Case modifiers like `:upper` drop it too, unless the result is still a keyword.
+///
+/// For the same reason, `*~ident` writes `*ident` untouched, even if `&ident` is a variable.
+/// ```
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// akin! {
+///     let &x = [1, 2];
+///     let y = &10;
+///     v.push(*x + *~y);
+/// }
+/// # assert_eq!(v, [11, 12]);
+/// ```
+///
+/// A name that isn't declared but looks like a misspelling of a variable, like `*nmae` for `&name`, is warned about by the `misspelled` lint,
+/// and is still written as is, as it can be a dereference like `*iter` next to `&item`.
+/// `#!deny(misspelled)` makes it an error, and `#!allow(misspelled)` or writing it `*~nmae` silences it:
+/// ```compile_fail
+/// # use akin::akin;
+/// # let mut v = Vec::new();
+/// # let nmae = &3;
+/// akin! {
+///     #!deny(misspelled)
+///     let &name = [1, 2];
+///     v.push(*nmae);
+/// }
+/// ```
+/// ```
+/// # use akin::akin;
+/// # let mut out = Vec::new();
+/// # let v = [10, 20];
+/// for iter in v.iter() {
+///     akin! {
+///         #!allow(misspelled)
+///         let &item = [1, 2];
+///         out.push(*iter + *item);
+///     }
+/// }
+/// # assert_eq!(out, [11, 12, 21, 22]);
+/// ```
+/// Any other name is left untouched, as it's most likely a dereference.
+///
+/// A variable name only matches a whole identifier, so `*name` is never substituted inside `*name_extra` or `*name2`, not even in string literals `"..."`.
+///
+/// Inside string literals, `~` can still be written right after a variable to paste a suffix, like `"*name~_impl"`, and is removed along with the name.
+///
+/// This is a limitation on proc_macro parsing, so I doubt it'll be fixed soon.
+///
+/// ## Expansion metrics
+/// Setting the `AKIN_METRICS` environment variable at build time makes every invocation report its location,
+/// the number of copies generated, the number of variables declared and the size of its output.
+///
+/// Use `AKIN_METRICS=1` to print the report to stderr, or `AKIN_METRICS=<path>` to append it to a file.
+/// ```text
+/// akin: src/ops.rs:12: 6 copies, 3 variables, 1184 bytes
+/// ```
+///
+/// ## More examples
+/// ```
+/// trait Sqrt {
+///     fn dumb_sqrt(self) -> Result<f64, &'static str>;
+/// }
+///
+/// # use akin::akin;
+/// akin! {
+///     let &int_type = [i64, u64];
+///     let &negative_check = [
+///         {
+///             if self < 0 {
+///                 return Err("Sqrt of negative number")
+///             }
+///         },
+///         NONE
+///     ];
+///
+///     let &num = [1,     2,    3,  4];
+///     let &res = [1., 1.41, 1.73,  2.];
+///     let &branch = {
+///         *num => Ok(*res),
+///     };
+///
+///     impl Sqrt for *int_type {
+///         fn dumb_sqrt(self) -> Result<f64, &'static str> {
+///             *negative_check
+///
+///             match self {
+///                 *branch
+///                 _ => Err("Sqrt of num not in [1, 4]")
+///             }
+///         }
+///     }
+/// }
+///
+/// # assert_eq!(10i64.dumb_sqrt(), Err("Sqrt of num not in [1, 4]"));
+/// # assert_eq!(15u64.dumb_sqrt(), Err("Sqrt of num not in [1, 4]"));
+/// # assert_eq!(2u64.dumb_sqrt(), Ok(1.41));
+/// # assert_eq!(3i64.dumb_sqrt(), Ok(1.73));
+/// # assert_eq!((-5i64).dumb_sqrt(), Err("Sqrt of negative number"));
+/// ```
+/// Is expanded to:
+/// ```
+/// trait Sqrt {
+///     fn dumb_sqrt(self) -> Result<f64, &'static str>;
+/// }
+///
+/// impl Sqrt for i64 {
+///     fn dumb_sqrt(self) -> Result<f64, &'static str> {
+///         if self < 0 {
+///             return Err("Sqrt of negative number")
+///         }
+///
+///         match self {
+///             1 => Ok(1.),
+///             2 => Ok(1.41),
+///             3 => Ok(1.73),
+///             4 => Ok(2.),
+///             _ => Err("Sqrt of num not in [1, 4]")
+///         }
+///     }
+/// }
+///
+/// impl Sqrt for u64 {
+///     fn dumb_sqrt(self) -> Result<f64, &'static str> {
+///         match self {
+///             1 => Ok(1.),
+///             2 => Ok(1.41),
+///             3 => Ok(1.73),
+///             4 => Ok(2.),
+///             _ => Err("Sqrt of num not in [1, 4]")
+///         }
+///     }
+/// }
+/// ```
+#[proc_macro]
+pub fn akin(input: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    // Left over if a previous invocation failed
+    READ_FILES.with(|files| files.borrow_mut().clear());
+    EMITTED.with(|emitted| emitted.borrow_mut().clear());
+    DUPLICATED.with(|cache| cache.borrow_mut().clear());
+    MARKED_SPANS.with(|spans| spans.borrow_mut().clear());
+    // Depends only on the invocation, so the names are the same whatever was expanded before it
+    let site = proc_macro::Span::call_site();
+    let prefix = stable_hash(&format!("{}:{}:{} {}", site.file(), site.line(), site.column(), input));
+    GENSYM.with(|gensym| *gensym.borrow_mut() = (prefix, 0));
+    #[cfg(feature = "diagnostics")]
+    MISMATCHED.with(|mismatched| mismatched.borrow_mut().clear());
+
+    // Errors are returned at the token that caused them, as a `compile_error!`
+    match expand(input) {
+        Ok(tokens) => tokens,
+        Err(error) => compile_error(&error.message, error.span),
+    }
+}
+
+fn expand(input: proc_macro::TokenStream) -> Result<proc_macro::TokenStream, Error> {
+    let mut vars: Map<String, Vec<Value>> = Map::new();
+    //panic!("Tokens: {input:#?}");
+    let mut tokens: Lookahead = unwrap_none_groups(input).into_iter().into();
+
+    let opts = parse_options(&mut tokens)?;
+    let declared = parse_vars(&mut tokens, &mut vars, &opts)?;
+    let mut warnings = lint_ambiguous(&declared, &opts)?;
+    let mut unused: Vec<_> = declared.iter().filter(|(_, _, used)| !used).map(|(name, span, _)| (name.clone(), *span)).collect();
+
+    let template: Vec<TokenTree> = tokens.collect();
+    let mut out = proc_macro::TokenStream::new();
+    let mut bytes = 0;
+    let copies = if opts.repeat.is_none() && !uses_akin(&template, opts.sigil()) {
+        // Nothing to substitute, so the code is given back as is, keeping its spans
+        out.extend(template);
+        bytes = out.to_string().len();
+        1
+    } else {
+        let mut prev = None;
+        let mut out_raw = String::new();
+        fold_stream(&mut out_raw, template.iter().cloned(), &mut prev, &vars, &opts)?;
+        // Values are all expanded by now
+        DUPLICATED.with(|cache| cache.take());
+        warnings.extend(lint_misspelled(&out_raw, &vars, &template, &opts)?);
+        // Looked for in the code as written, as `:join`, indices like `*name.[0]` and expressions are gone once it's folded
+        let mut used = Vec::new();
+        references(template.iter().cloned().collect(), opts.sigil(), false, &mut used);
+        unused.retain(|(name, _)| !used.contains(name));
+
+        // Each copy is turned into tokens as soon as it's made, so the whole output is never held as text
+        let mut spans = Spans::new(template);
+        let site = proc_macro::Span::call_site();
+        for_each_copy(&out_raw, &vars, &opts, site, opts.repeat, Some(eval_copy), |copy| {
+            bytes += copy.len();
+            let tokens = copy.parse().map_err(|e| {
+                Error::new(site, format!("akin: the generated code isn't valid Rust, {}, near: {}", e, excerpt(copy, lex_error_at(copy))))
+            })?;
+            out.extend(spans.restore_copy(tokens));
+            Ok(())
+        })?
+    };
+    let emitted = EMITTED.with(|emitted| emitted.take());
+    bytes += emitted.len();
+    warnings.extend(lint_unused(&unused, &opts)?);
+    #[cfg(feature = "diagnostics")]
+    note_mismatches(&declared);
+
+    //let tokens = format!("proc_macro: {:#?}", input.into_iter().collect::<Vec<_>>());
+    //let tokens = format!("vars: {:#?}", vars);
+    //panic!("\nVars: {vars:#?}\nRaw: {out_raw}\nOut: {out}\n");
+
+    if let Some(dest) = std::env::var_os("AKIN_METRICS") {
+        report_metrics(&dest, copies, vars.len(), bytes);
+    }
+
+    let mut tokens: proc_macro::TokenStream = emitted.parse().unwrap();
+    tokens.extend(out);
+    warnings.extend(track_files());
+    Ok(add_items(tokens, warnings))
+}
+
+/// Evaluates what's left in a copy once its values are written: `#[cfg]` gates, joints, modifiers and matches.
+///
+/// It's only text, so it's done by the threads writing the copies, unlike turning them into tokens.
+fn eval_copy(copy: &str) -> Result<String, EvalError> {
+    let mut copy = strip_cfgs(copy);
+    copy.retain(|c| c != VAR_END);
+    let copy = eval_joints(&copy).replace(STAR, "*");
+    eval_matches(&eval_modifiers(&copy)?)
+}
+
+/// An error in the input, like a wrong declaration, shown at `span`, the token that caused it.
+struct Error {
+    message: String,
+    span: proc_macro::Span,
+}
+
+impl Error {
+    fn new(span: proc_macro::Span, message: impl Into<String>) -> Self {
+        Error { message: message.into(), span }
+    }
+}
+
+/// An error found while evaluating the text of a copy, like a modifier applied to a value it can't take.
+///
+/// Copies can be evaluated by other threads, where spans can't be used,
+/// so it holds the number of the marker it was found in, given by [`mark_span`], if any.
+struct EvalError {
+    message: String,
+    marker: Option<usize>,
+}
+
+impl EvalError {
+    /// Turns it into an [`Error`] shown at the span of its marker, or at `span` if it has none.
+    fn into_error(self, span: proc_macro::Span) -> Error {
+        let marked = self.marker.and_then(|i| MARKED_SPANS.with(|spans| spans.borrow().get(i).copied()));
+        Error::new(marked.unwrap_or(span), self.message)
+    }
+}
+
+/// Keeps the span of a modifier or expression until its marker is evaluated, returning the number written in the marker.
+fn mark_span(span: proc_macro::Span) -> usize {
+    MARKED_SPANS.with(|spans| {
+        let mut spans = spans.borrow_mut();
+        spans.push(span);
+        spans.len() - 1
+    })
+}
+
+/// Returns where `code` can't be split into tokens, like an unclosed or mismatched delimiter,
+/// an unterminated string or a character that can't be in Rust code.
+///
+/// It's only an estimate, so the start of `code` is returned if nothing is found.
+fn lex_error_at(code: &str) -> usize {
+    let mut open: Vec<(char, usize)> = Vec::new();
+    let mut chars = code.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => {
+                let mut escaped = false;
+                let closed = chars.by_ref().any(|(_, c)| {
+                    let end = c == '"' && !escaped;
+                    escaped = c == '\\' && !escaped;
+                    end
+                });
+                if !closed {
+                    return i;
+                }
+            },
+            // A char literal, which may be a delimiter, unlike a lifetime
+            '\'' => {
+                let rest = &code[i + 1..];
+                let len = match rest.strip_prefix('\\') {
+                    Some(escape) => escape.get(1..).and_then(|e| e.find('\'')).map_or(0, |end| end + 2),
+                    None => rest.chars().next().map_or(0, char::len_utf8),
+                };
+                if len > 0 && rest[len..].starts_with('\'') {
+                    while chars.next_if(|&(j, _)| j <= i + 1 + len).is_some() {}
+                }
+            },
+            '(' | '[' | '{' => open.push((c, i)),
+            ')' | ']' | '}' => match open.pop() {
+                Some(('(', _)) if c == ')' => {},
+                Some(('[', _)) if c == ']' => {},
+                Some(('{', _)) if c == '}' => {},
+                _ => return i,
+            },
+            c if c.is_control() && !c.is_whitespace() => return i,
+            _ => {},
+        }
+    }
+    open.last().map_or(0, |&(_, i)| i)
+}
+
+/// Returns the text of `code` around `at`, with `...` where it's cut.
+fn excerpt(code: &str, at: usize) -> String {
+    const CONTEXT: usize = 40;
+    let mut start = at.saturating_sub(CONTEXT);
+    while !code.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut end = (at + CONTEXT).min(code.len());
+    while !code.is_char_boundary(end) {
+        end += 1;
+    }
+    // Markers left in the code are shown as escapes, like `\u{6}`
+    let mut excerpt: String = code[start..end]
+        .trim()
+        .chars()
+        .map(|c| if c.is_control() { c.escape_unicode().to_string() } else { c.to_string() })
+        .collect();
+    if start > 0 {
+        excerpt.insert_str(0, "...");
+    }
+    if end < code.len() {
+        excerpt.push_str("...");
+    }
+    format!("'{excerpt}'")
+}
+
+/// Checks the `unused` lint for the variables in `unused`, which aren't used by the template nor by other variables.
+/// Returns the warnings to add to the output, or fails if it's denied.
+fn lint_unused(unused: &[(String, Option<proc_macro::Span>)], opts: &Options) -> Result<proc_macro::TokenStream, Error> {
+    let level = opts.lint("unused");
+    let mut warnings = proc_macro::TokenStream::new();
+    for (name, span) in unused.iter().filter(|_| level != Level::Allow) {
+        let message = format!("akin: '&{name}' is never used, in the code nor in the values of another variable");
+        let help = "remove it or allow it with '#!allow(unused)'";
+        let span = span.unwrap_or_else(proc_macro::Span::call_site);
+        if level == Level::Deny {
+            return Err(Error::new(span, format!("{message}; {help}")));
+        }
+        warnings.extend(warning(&message, help, span));
+    }
+    Ok(warnings)
+}
+
+/// Checks the `ambiguous` lint for the `declared` variables, which is raised when a name starts with another one, like `&foo` and `&foobar`.
+/// Returns the warnings to add to the output, or fails if it's denied.
+fn lint_ambiguous(declared: &[(String, Option<proc_macro::Span>, bool)], opts: &Options) -> Result<proc_macro::TokenStream, Error> {
+    let level = opts.lint("ambiguous");
+    let mut warnings = proc_macro::TokenStream::new();
+    for (long, span, _) in declared.iter().filter(|_| level != Level::Allow) {
+        let Some((short, _, _)) = declared.iter().find(|(short, ..)| short.len() < long.len() && long.starts_with(short.as_str())) else {
+            continue;
+        };
+        let rest = &long[short.len()..];
+        let message = format!(
+            "akin: '&{short}' is the start of '&{long}', so '*{long}' is easily mistaken for '*{short}' followed by '{rest}', which is written '*{short}~{rest}'"
+        );
+        let help = "renaming one of them avoids it";
+        let span = span.unwrap_or_else(proc_macro::Span::call_site);
+        if level == Level::Deny {
+            return Err(Error::new(span, format!("{message}; {help}")));
+        }
+        warnings.extend(warning(&message, help, span));
+    }
+    Ok(warnings)
+}
+
+/// Shows a note at the declaration of each variable that reused its last value because it was used with a longer one,
+/// which is the default `mismatch(last)` policy but can also be a mistake.
+#[cfg(feature = "diagnostics")]
+fn note_mismatches(declared: &[(String, Option<proc_macro::Span>, bool)]) {
+    for (short, len, long, long_len) in MISMATCHED.with(|mismatched| mismatched.take()) {
+        let span = declared.iter().find(|(name, ..)| *name == short).and_then(|(_, span, _)| *span);
+        span.unwrap_or_else(proc_macro::Span::call_site)
+            .note(format!("akin: '&{short}' has {len} values but '&{long}' has {long_len}, so its last value is reused in the remaining copies"))
+            .help("give them the same length, or choose what to do with '#!strict' or 'mismatch(policy)'")
+            .emit();
+    }
+}
+
+/// Shows a warning with `message` and `help` at `span`, using the compiler's diagnostics with the `diagnostics` feature.
+#[cfg(feature = "diagnostics")]
+fn warning(message: &str, help: &str, span: proc_macro::Span) -> proc_macro::TokenStream {
+    span.warning(message).help(help).emit();
+    proc_macro::TokenStream::new()
+}
+
+/// Makes a warning with `message` and `help` at `span`, as the use of a deprecated item.
+///
+/// It's the only way to show one in stable Rust, and it's an item, so it's added to the output with [`add_items`].
+#[cfg(not(feature = "diagnostics"))]
+fn warning(message: &str, help: &str, span: proc_macro::Span) -> proc_macro::TokenStream {
+    fn respan(stream: proc_macro::TokenStream, span: proc_macro::Span) -> proc_macro::TokenStream {
+        stream
+            .into_iter()
+            .map(|mut tt| {
+                if let TokenTree::Group(g) = &tt {
+                    tt = TokenTree::Group(proc_macro::Group::new(g.delimiter(), respan(g.stream(), span)));
+                }
+                tt.set_span(span);
+                tt
+            })
+            .collect()
+    }
+
+    let note = format!("{message}; {help}");
+    let code = format!("const _: () = {{ #[deprecated(note = {note:?})] struct AkinWarning; let _ = AkinWarning; }};");
+    respan(code.parse().unwrap(), proc_macro::Span::call_site().located_at(span))
+}
+
+/// Checks the `misspelled` lint for the names used in `stream` that aren't declared but look like a misspelling of a variable,
+/// like `*nmae` for `&name`, pointing at where they're written in `template`.
+/// Returns the warnings to add to the output, or fails if it's denied.
+///
+/// The names are written as is either way, as they can be a dereference in the Rust code, like `*iter` next to `&item`.
+fn lint_misspelled(stream: &str, vars: &Map<String, Vec<Value>>, template: &[TokenTree], opts: &Options) -> Result<proc_macro::TokenStream, Error> {
+    let level = opts.lint("misspelled");
+    let mut warnings = proc_macro::TokenStream::new();
+    if level == Level::Allow {
+        return Ok(warnings);
+    }
+    let sigil = opts.sigil();
+    let names: Vec<&str> = vars.keys().filter_map(|k| k.strip_prefix('*')?.split('.').next()).collect();
+    let mut seen = Vec::new();
+    for name in var_names(stream) {
+        if names.contains(&name) || BUILTINS.iter().any(|builtin| builtin[1..] == *name) || seen.contains(&name) {
+            continue;
+        }
+        seen.push(name);
+        let Some(similar) = names.iter().find(|known| is_misspelling(name, known)) else {
+            continue;
+        };
+        let message = format!("akin: '{sigil}{name}' isn't a declared variable, but looks like a misspelling of '{sigil}{similar}'");
+        let help = format!("write it as is with '{sigil}~{name}', or allow it with '#!allow(misspelled)'");
+        let span = find_var(template, name, sigil).unwrap_or_else(proc_macro::Span::call_site);
+        if level == Level::Deny {
+            return Err(Error::new(span, format!("{message}; {help}")));
+        }
+        warnings.extend(warning(&message, &help, span));
+    }
+    Ok(warnings)
+}
+
+/// Returns `true` if `name` differs from `known` in a single character, or two if it's longer than 5,
+/// counting two swapped characters as one.
+///
+/// Names shorter than 3 characters never match, as `*x` is more likely to be a dereference than a misspelling of `*y`,
+/// and neither do names that start with the other, as `*name2` is a different name written on purpose.
+fn is_misspelling(name: &str, known: &str) -> bool {
+    let (a, b): (Vec<char>, Vec<char>) = (name.chars().collect(), known.chars().collect());
+    if a.len().min(b.len()) < 3 || a.starts_with(&b) || b.starts_with(&a) {
+        return false;
+    }
+    let max = if b.len() > 5 { 2 } else { 1 };
+    if a.len().abs_diff(b.len()) > max {
+        return false;
+    }
+    // Optimal string alignment distance
+    let mut d = vec![vec![0; b.len() + 1]; a.len() + 1];
+    for i in 0..=a.len() {
+        for j in 0..=b.len() {
+            d[i][j] = match (i, j) {
+                (0, j) => j,
+                (i, 0) => i,
+                (i, j) => {
+                    let cost = (a[i - 1] != b[j - 1]) as usize;
+                    let mut dist = (d[i - 1][j] + 1).min(d[i][j - 1] + 1).min(d[i - 1][j - 1] + cost);
+                    if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                        dist = dist.min(d[i - 2][j - 2] + 1);
+                    }
+                    dist
+                },
+            };
+        }
+    }
+    d[a.len()][b.len()] <= max
+}
+
+/// Returns the span of the first `*name` written in `tts`, or of the string literal it's written in.
+fn find_var(tts: &[TokenTree], name: &str, sigil: char) -> Option<proc_macro::Span> {
+    tts.iter().enumerate().find_map(|(i, tt)| match tt {
+        TokenTree::Punct(p) if p.as_char() == sigil => match tts.get(i + 1) {
+            Some(TokenTree::Ident(id)) if id.to_string() == name => Some(id.span()),
+            _ => None,
+        },
+        TokenTree::Literal(lit) => lit.to_string().contains(&format!("{sigil}{name}")).then(|| lit.span()),
+        TokenTree::Group(g) => find_var(&g.stream().into_iter().collect::<Vec<_>>(), name, sigil),
+        _ => None,
+    })
+}
+
+/// Makes `compile_error! { "message" }` at `span`, so the error is shown there instead of as a panic of the whole invocation.
+fn compile_error(message: &str, span: proc_macro::Span) -> proc_macro::TokenStream {
+    let mut literal = proc_macro::Literal::string(message);
+    literal.set_span(span);
+    let mut bang = proc_macro::Punct::new('!', Spacing::Alone);
+    bang.set_span(span);
+    let mut group = proc_macro::Group::new(Delimiter::Brace, TokenTree::Literal(literal).into());
+    group.set_span(span);
+    [TokenTree::Ident(proc_macro::Ident::new("compile_error", span)), TokenTree::Punct(bang), TokenTree::Group(group)]
+        .into_iter()
+        .collect()
+}
+
+/// Returns whether `tts` uses anything of akin, like a variable, an `*{...}` expression, an `@match`, a `~` joint,
+/// or a `for &var` loop or `{ let &var = ...; }` scope.
+///
+/// Rust code that looks like them, like `*ptr` or `"a * b"`, counts too, so it's only `false` when there's nothing to substitute.
+fn uses_akin(tts: &[TokenTree], sigil: char) -> bool {
+    tts.iter().enumerate().any(|(i, tt)| match tt {
+        TokenTree::Punct(p) if p.as_char() == sigil => matches!(tts.get(i + 1), Some(TokenTree::Ident(_) | TokenTree::Group(_))),
+        TokenTree::Punct(p) => matches!(p.as_char(), '@' | '~'),
+        TokenTree::Ident(id) => {
+            matches!(id.to_string().as_str(), "let" | "for")
+                && matches!(tts.get(i + 1), Some(TokenTree::Punct(p)) if p.as_char() == '&')
+        },
+        TokenTree::Literal(lit) => lit.to_string().contains(sigil),
+        TokenTree::Group(g) => uses_akin(&g.stream().into_iter().collect::<Vec<_>>(), sigil),
+    })
+}
+
+/// Replaces the groups without delimiters in `stream` by their contents.
+///
+/// They wrap the fragments given to a `macro_rules!`, like `$e:expr`, and have no text,
+/// so `akin!` can be called by one as if the fragments were written in place.
+///
+/// Groups without any inside are left untouched, as rebuilding them changes how they are written, which value sources like `methods_of` show.
+fn unwrap_none_groups(stream: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    fn has_none_groups(stream: proc_macro::TokenStream) -> bool {
+        stream.into_iter().any(|tt| matches!(tt, TokenTree::Group(g) if g.delimiter() == Delimiter::None || has_none_groups(g.stream())))
+    }
+
+    let mut out = proc_macro::TokenStream::new();
+    for tt in stream {
+        match tt {
+            TokenTree::Group(g) if g.delimiter() == Delimiter::None => out.extend(unwrap_none_groups(g.stream())),
+            TokenTree::Group(g) if has_none_groups(g.stream()) => {
+                let mut group = proc_macro::Group::new(g.delimiter(), unwrap_none_groups(g.stream()));
+                group.set_span(g.span());
+                out.extend([TokenTree::Group(group)]);
+            }
+            tt => out.extend([tt]),
+        }
+    }
+    out
+}
+
+/// Gives the generated tokens the location of the template tokens they come from,
+/// so the errors in the generated code point to the lines of the template instead of the whole invocation.
+///
+/// The generated code is still made as text, so the output is matched token by token with the template by their text,
+/// looking a few tokens ahead and starting again at its beginning on each copy. This guarantees that:
+/// - Every generated token out of the arguments of macro calls points inside the invocation, to the start of a template token,
+///   and never to the whole of it.
+/// - The tokens copied from the template point to themselves, and the values to where their variable is used,
+///   unless a value has a token written like a template token close to it, like `v` in `*var.push(v)`, which can take its span instead.
+/// - A copy that was matched wrong doesn't move the tokens of the next ones.
+///
+/// The tokens found only take the start of their template token, resolving names like the rest of the output,
+/// so lints that read the code of a span, like some of clippy, don't take them for code written by hand.
+/// The exception are the names written outside of the invocation, given by a `macro_rules!` calling `akin!`,
+/// which take the whole span so they are still resolved where they were written.
+/// The tokens that aren't found, like the values of variables, take the start of the template token where they were expected.
+///
+/// The arguments of macro calls are left as they are, because rebuilding them changes their spacing,
+/// which macros like `stringify!` show.
+struct Spans {
+    /// Text of each token of the template, with delimiters as their own tokens, and its span.
+    template: Vec<(String, proc_macro::Span)>,
+    cursor: usize,
+    /// Where the invocation is written, as its file and the line and column of its start and end.
+    site: (String, (usize, usize), (usize, usize)),
+}
+
+impl Spans {
+    /// How many template tokens are looked ahead to find the next output one.
+    const WINDOW: usize = 16;
+
+    fn new(template: Vec<TokenTree>) -> Self {
+        fn flatten(tts: impl IntoIterator<Item = TokenTree>, out: &mut Vec<(String, proc_macro::Span)>) {
+            for tt in tts {
+                match tt {
+                    TokenTree::Group(g) => {
+                        let (open, close) = get_delimiters(g.delimiter());
+                        out.push((open.to_string(), g.span()));
+                        flatten(g.stream(), out);
+                        out.push((close.to_string(), g.span_close()));
+                    }
+                    tt => out.push((tt.to_string(), tt.span())),
+                }
+            }
+        }
+        let mut spans = Spans { template: Vec::new(), cursor: 0, site: Self::location(proc_macro::Span::call_site()) };
+        flatten(template, &mut spans.template);
+        spans
+    }
+
+    fn location(span: proc_macro::Span) -> (String, (usize, usize), (usize, usize)) {
+        let (start, end) = (span.start(), span.end());
+        (span.file(), (start.line(), start.column()), (end.line(), end.column()))
+    }
+
+    /// Returns whether `span` is written outside of the invocation, like a name given to a `macro_rules!` that calls `akin!`.
+    fn is_outside(&self, span: proc_macro::Span) -> bool {
+        let (file, start, end) = Self::location(span);
+        file != self.site.0 || start < self.site.1 || end > self.site.2
+    }
+
+    /// Returns the span of the output token written `text`, and moves past its template token.
+    fn next(&mut self, text: &str) -> proc_macro::Span {
+        let site = proc_macro::Span::call_site();
+        let len = self.template.len();
+        if len == 0 {
+            return site;
+        }
+        let found = (0..Self::WINDOW.min(len)).map(|i| (self.cursor + i) % len).find(|&i| self.template[i].0 == text);
+        match found {
+            Some(i) => {
+                self.cursor = (i + 1) % len;
+                if text.starts_with(|c: char| c.is_alphabetic() || c == '_') && self.is_outside(self.template[i].1) {
+                    self.template[i].1
+                } else {
+                    site.located_at(self.template[i].1.start())
+                }
+            }
+            None => site.located_at(self.template[self.cursor].1.start()),
+        }
+    }
+
+    /// Moves past the template tokens of `stream`, without changing its spans.
+    fn skip(&mut self, stream: proc_macro::TokenStream) {
+        for tt in stream {
+            match tt {
+                TokenTree::Group(g) => {
+                    let (open, close) = get_delimiters(g.delimiter());
+                    self.next(&open.to_string());
+                    self.skip(g.stream());
+                    self.next(&close.to_string());
+                }
+                tt => {
+                    self.next(&tt.to_string());
+                }
+            }
+        }
+    }
+
+    /// Gives the tokens of a copy the spans of the template, matching them from its beginning.
+    fn restore_copy(&mut self, stream: proc_macro::TokenStream) -> proc_macro::TokenStream {
+        self.cursor = 0;
+        self.restore(stream)
+    }
+
+    fn restore(&mut self, stream: proc_macro::TokenStream) -> proc_macro::TokenStream {
+        let mut prev: Option<TokenTree> = None;
+        stream
+            .into_iter()
+            .map(|mut tt| {
+                let is_macro_call = matches!(&prev, Some(TokenTree::Punct(p)) if p.as_char() == '!');
+                prev = Some(tt.clone());
+                match &mut tt {
+                    TokenTree::Group(g) if is_macro_call => {
+                        let (open, close) = get_delimiters(g.delimiter());
+                        let span = self.next(&open.to_string());
+                        self.skip(g.stream());
+                        self.next(&close.to_string());
+                        g.set_span(span);
+                    }
+                    TokenTree::Group(g) => {
+                        let (open, close) = get_delimiters(g.delimiter());
+                        let span = self.next(&open.to_string());
+                        let mut group = proc_macro::Group::new(g.delimiter(), self.restore(g.stream()));
+                        group.set_span(span);
+                        self.next(&close.to_string());
+                        *g = group;
+                    }
+                    tt => {
+                        let span = self.next(&tt.to_string());
+                        tt.set_span(span);
+                    }
+                }
+                tt
+            })
+            .collect()
+    }
+}
+
+thread_local! {
+    /// Files read by the value sources of the current invocation.
+    static READ_FILES: std::cell::RefCell<Vec<std::path::PathBuf>> = const { std::cell::RefCell::new(Vec::new()) };
+    /// Items that value sources like `variants_of(emit) { ... }` add to the output of the current invocation.
+    static EMITTED: std::cell::RefCell<String> = const { std::cell::RefCell::new(String::new()) };
+    /// Spans of the modifiers and expressions folded in the current invocation, see [`mark_span`].
+    static MARKED_SPANS: std::cell::RefCell<Vec<proc_macro::Span>> = const { std::cell::RefCell::new(Vec::new()) };
+    /// Copies already made by [`duplicate`] in the current invocation, so identical values like `[{...}, {...}]` are only expanded once.
+    static DUPLICATED: std::cell::RefCell<Map<String, Vec<(DuplicateKey, String)>>> = const { std::cell::RefCell::new(Map::new()) };
+    /// Variables of the current invocation that reused their last value because they were used with a longer one,
+    /// as `(name, length, longer name, longer length)`, which are noted with the `diagnostics` feature.
+    #[cfg(feature = "diagnostics")]
+    static MISMATCHED: std::cell::RefCell<Vec<(String, usize, String, usize)>> = const { std::cell::RefCell::new(Vec::new()) };
+}
+
+/// Makes the compiler track the files read by value sources, so editing them regenerates the code.
+///
+/// This is done with `include_bytes!` constants, which are returned to be added to the output with [`add_items`].
+fn track_files() -> proc_macro::TokenStream {
+    let files = READ_FILES.with(|files| files.take());
+    let mut tracked = String::new();
+    for file in files {
+        let path = file.canonicalize().unwrap_or(file);
+        let path = proc_macro::Literal::string(&path.to_string_lossy());
+        write!(&mut tracked, "const _: &[u8] = include_bytes!({path});").unwrap();
+    }
+    tracked.parse().unwrap()
+}
+
+/// Adds `items`, like the constants of [`track_files`] or the warnings, to `out`, in a place that's valid wherever `out` is written:
+/// - Before it, if it's made of module items or statements.
+/// - At the start of the body of its first function, which works in a module, an impl or a trait alike.
+/// - In a block around the value of its first constant or static, for the ones in an impl.
+/// - In a block around it, if it's an expression.
+///
+/// Otherwise, like for the function declarations of a trait or an empty output, `out` is returned without them.
+fn add_items(out: proc_macro::TokenStream, items: proc_macro::TokenStream) -> proc_macro::TokenStream {
+    if items.is_empty() || out.is_empty() {
+        return out;
+    }
+    if is_module_level(&out) {
+        let mut with_items = items;
+        with_items.extend(out);
+        return with_items;
+    }
+
+    let mut tts: Vec<TokenTree> = out.into_iter().collect();
+    let keywords = item_keywords(&tts);
+    let end_of = |start: usize| (start..tts.len()).find(|&j| matches!(&tts[j], TokenTree::Punct(p) if p.as_char() == ';'));
+    for &(i, keyword) in &keywords {
+        let place = match keyword {
+            // The body is the first `{...}`, unless the function is only declared, like `fn f();`
+            "fn" => (i..end_of(i).unwrap_or(tts.len()))
+                .find(|&j| matches!(&tts[j], TokenTree::Group(g) if g.delimiter() == Delimiter::Brace))
+                .map(|j| (j, j + 1)),
+            "const" | "static" => {
+                let end = end_of(i).unwrap_or(tts.len());
+                (i..end).find(|&j| matches!(&tts[j], TokenTree::Punct(p) if p.as_char() == '=')).map(|j| (j + 1, end))
+            },
+            _ => None,
+        };
+        let Some((start, end)) = place else {
+            continue;
+        };
+        let (stream, span) = match &tts[start] {
+            TokenTree::Group(body) if keyword == "fn" => (body.stream(), body.span()),
+            _ => (tts[start..end].iter().cloned().collect(), proc_macro::Span::call_site()),
+        };
+        let mut group = proc_macro::Group::new(Delimiter::Brace, items.into_iter().chain(stream).collect());
+        group.set_span(span);
+        tts.splice(start..end, [TokenTree::Group(group)]);
+        return tts.into_iter().collect();
+    }
+    if !keywords.is_empty() {
+        return tts.into_iter().collect();
+    }
+    if tts.iter().any(|tt| matches!(tt, TokenTree::Punct(p) if p.as_char() == ';')) {
+        // Statements
+        let mut with_items = items;
+        with_items.extend(tts);
+        with_items
+    } else {
+        // An expression
+        TokenTree::Group(proc_macro::Group::new(Delimiter::Brace, items.into_iter().chain(tts).collect())).into()
+    }
+}
+
+/// Returns the keyword of each function, constant, static or type alias declared in `tts`, like `fn` in `pub const unsafe fn`,
+/// along with its position.
+fn item_keywords(tts: &[TokenTree]) -> Vec<(usize, &'static str)> {
+    let mut keywords = Vec::new();
+    let mut item_start = true;
+    for (i, tt) in tts.iter().enumerate() {
+        match tt {
+            TokenTree::Punct(p) if p.as_char() == ';' => item_start = true,
+            // Attributes, visibility like `pub(crate)` and ABI like `extern "C"`
+            TokenTree::Punct(p) if p.as_char() == '#' && item_start => {},
+            TokenTree::Group(g) if g.delimiter() != Delimiter::Brace && item_start => {},
+            TokenTree::Literal(_) if item_start => {},
+            TokenTree::Group(g) => item_start = g.delimiter() == Delimiter::Brace,
+            TokenTree::Ident(id) if item_start => match id.to_string().as_str() {
+                "pub" | "unsafe" | "extern" | "async" | "default" => {},
+                "const" if matches!(tts.get(i + 1), Some(TokenTree::Ident(next)) if ["fn", "unsafe", "async", "extern"].contains(&next.to_string().as_str())) => {},
+                keyword => {
+                    if let Some(keyword) = ["fn", "const", "static", "type"].into_iter().find(|k| *k == keyword) {
+                        keywords.push((i, keyword));
+                    }
+                    item_start = false;
+                },
+            },
+            _ => item_start = false,
+        }
+    }
+    keywords
+}
+
+/// Returns whether `out` has an item that can only be declared in a module or a block, like a struct or an impl,
+/// instead of being an expression or the contents of an impl or trait.
+fn is_module_level(out: &proc_macro::TokenStream) -> bool {
+    let mut item_start = true;
+    let mut attribute = false;
+    for tt in out.clone() {
+        match tt {
+            TokenTree::Punct(p) if p.as_char() == ';' => item_start = true,
+            TokenTree::Punct(p) if p.as_char() == '#' && item_start => attribute = true,
+            TokenTree::Group(g) if g.delimiter() == Delimiter::Bracket && attribute => attribute = false,
+            // Visibility like `pub(crate)`
+            TokenTree::Group(g) if g.delimiter() == Delimiter::Parenthesis && item_start => {},
+            TokenTree::Group(g) => item_start = g.delimiter() == Delimiter::Brace,
+            TokenTree::Ident(id) if item_start => match id.to_string().as_str() {
+                "struct" | "enum" | "union" | "impl" | "trait" | "mod" | "use" | "macro_rules" => return true,
+                "pub" | "unsafe" | "extern" => {},
+                _ => item_start = false,
+            },
+            _ => item_start = false,
+        }
+    }
+    false
+}
+
+/// Writes a one-line summary of the current invocation to the destination set in `AKIN_METRICS`.
+///
+/// Any value other than a path (`1`, `true`, `stderr`, ...) prints the report to stderr.
+fn report_metrics(dest: &std::ffi::OsStr, copies: usize, vars: usize, bytes: usize) {
+    let site = proc_macro::Span::call_site();
+    let report = format!(
+        "akin: {}:{}: {} copies, {} variables, {} bytes\n",
+        site.file(), site.line(), copies, vars, bytes
+    );
+
+    if matches!(dest.to_str(), Some("" | "1" | "true" | "stderr")) {
+        eprint!("{report}");
+        return;
+    }
+
+    use std::io::Write as _;
+    let written = std::fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dest)
+        .and_then(|mut f| f.write_all(report.as_bytes()));
+    if let Err(e) = written {
+        eprintln!("akin: could not write metrics to {:?}: {}", dest, e);
+    }
+}
+
+/// Options of an invocation, set with `#!directive` at its start.
+#[derive(Default)]
+struct Options {
+    /// `#!product`: copies are made for every combination of values, instead of advancing them in lockstep.
+    product: bool,
+    /// `#!product except [...]`: combinations of values that are left out, already normalized.
+    except: Vec<Vec<String>>,
+    /// `#!mismatch(policy)`: what the shorter variables do when variables with a different number of values are used together.
+    /// `#!strict` is the same as `#!mismatch(error)`.
+    mismatch: Mismatch,
+    /// `#!repeat(n)`: the code is copied exactly `n` times, regardless of the values of its variables.
+    repeat: Option<usize>,
+    /// `#!sigil(c)`: the character used instead of `*` to reference variables.
+    sigil: Option<char>,
+    /// `#!max_copies(n)`: the most copies the code can be expanded into.
+    max_copies: Option<usize>,
+    /// `#!max_bytes(n)`: the most bytes the copies can take.
+    max_bytes: Option<usize>,
+    /// `#!allow(lint)`, `#!warn(lint)` and `#!deny(lint)`: the level of each lint, the last one given for each.
+    lints: Vec<(String, Level)>,
+}
+
+impl Options {
+    fn sigil(&self) -> char {
+        self.sigil.unwrap_or('*')
+    }
+
+    fn max_copies(&self) -> usize {
+        self.max_copies.unwrap_or(100_000)
+    }
+
+    fn max_bytes(&self) -> usize {
+        self.max_bytes.unwrap_or(64 << 20)
+    }
+
+    fn lint(&self, lint: &str) -> Level {
+        // Names are matched as whole identifiers, so `&foo` and `&foobar` are only ambiguous to the reader,
+        // and are often on purpose, like `&field` and `&fields`
+        let default = if lint == "ambiguous" { Level::Allow } else { Level::Warn };
+        self.lints.iter().rev().find(|(name, _)| name == lint).map_or(default, |(_, level)| *level)
+    }
+}
+
+/// What is done when the code checked by a lint, like `unused` or `ambiguous`, is found.
+#[derive(Clone, Copy, PartialEq)]
+enum Level {
+    /// Nothing.
+    Allow,
+    /// A warning is shown, see [`warning`].
+    Warn,
+    /// It's a compile error.
+    Deny,
+}
+
+/// Lints that can be given to `#!allow(...)`, `#!warn(...)` and `#!deny(...)`.
+const LINTS: &[&str] = &["ambiguous", "misspelled", "unused"];
+
+/// What a variable does once it runs out of values, when used together with a longer one.
+#[derive(Clone, Copy, Default, PartialEq)]
+enum Mismatch {
+    /// Its last value is reused.
+    #[default]
+    Last,
+    /// It starts again from its first value.
+    Cycle,
+    /// Nothing is written, like with `NONE`.
+    Pad,
+    /// It's a compile error.
+    Error,
+}
+
+impl Mismatch {
+    fn parse(policy: &str) -> Option<Self> {
+        match policy {
+            "last" => Some(Mismatch::Last),
+            "cycle" => Some(Mismatch::Cycle),
+            "pad" => Some(Mismatch::Pad),
+            "error" => Some(Mismatch::Error),
+            _ => None,
+        }
+    }
+
+    fn as_str(self) -> &'static str {
+        match self {
+            Mismatch::Last => "last",
+            Mismatch::Cycle => "cycle",
+            Mismatch::Pad => "pad",
+            Mismatch::Error => "error",
+        }
+    }
+
+    /// Parses the policy in `(policy)` after `mismatch`, in `context`.
+    fn parse_group(tokens: &mut Lookahead, context: &str) -> Result<Self, Error> {
+        let span = tokens.span();
+        let policy = match tokens.next() {
+            Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => Mismatch::parse(&g.stream().to_string()),
+            _ => None,
+        };
+        policy.ok_or_else(|| Error::new(span, format!("akin: expected 'last', 'cycle', 'pad' or 'error' in 'mismatch(...)' of {}", context)))
+    }
+}
+
+/// Marker before the name of a variable, like `\u{12}*name`, to declare the `mismatch(...)` policy of `*name`.
+/// No text can match it, so it's never substituted.
+const MISMATCH: char = '\u{12}';
+
+fn parse_options(tokens: &mut Lookahead) -> Result<Options, Error> {
+    let mut opts = Options::default();
+
+    while matches!(tokens.peek_nth(0), Some(TokenTree::Punct(p)) if p.as_char() == '#')
+        && matches!(tokens.peek_nth(1), Some(TokenTree::Punct(p)) if p.as_char() == '!')
+    {
+        tokens.next();
+        tokens.next();
+
+        let span = tokens.span();
+        match tokens.next() {
+            Some(TokenTree::Ident(id)) if id.to_string() == "product" => {
+                opts.product = true;
+                if matches!(tokens.peek_nth(0), Some(TokenTree::Ident(id)) if id.to_string() == "except") {
+                    tokens.next();
+                    let span = tokens.span();
+                    opts.except = match tokens.next() {
+                        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Bracket => parse_except(g.stream()),
+                        _ => return Err(Error::new(span, "akin: expected list of combinations after '#!product except'")),
+                    };
+                }
+            }
+            Some(TokenTree::Ident(id)) if id.to_string() == "strict" => opts.mismatch = Mismatch::Error,
+            Some(TokenTree::Ident(id)) if id.to_string() == "mismatch" => opts.mismatch = Mismatch::parse_group(tokens, "'#!mismatch'")?,
+            Some(TokenTree::Ident(id)) if id.to_string() == "repeat" => {
+                let span = tokens.span();
+                let times = match tokens.next() {
+                    Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => g.stream().to_string().parse().ok(),
+                    _ => None,
+                };
+                opts.repeat = Some(times.ok_or_else(|| Error::new(span, "akin: expected number of copies in '#!repeat(n)'"))?);
+            }
+            Some(TokenTree::Ident(id)) if id.to_string() == "sigil" => {
+                let span = tokens.span();
+                let sigil = match tokens.next() {
+                    Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => {
+                        let mut stream = g.stream().into_iter();
+                        match (stream.next(), stream.next()) {
+                            (Some(TokenTree::Punct(p)), None) if !matches!(p.as_char(), '~' | '&' | '#' | ',' | ';') => Some(p.as_char()),
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                };
+                opts.sigil = Some(sigil.ok_or_else(|| Error::new(span, "akin: expected a punctuation character in '#!sigil(c)', like '#!sigil($)'"))?);
+            }
+            Some(TokenTree::Ident(id)) if matches!(id.to_string().as_str(), "max_copies" | "max_bytes") => {
+                let span = tokens.span();
+                let max = match tokens.next() {
+                    Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => parse_int(&g.stream().to_string()),
+                    _ => None,
+                };
+                let max = max.filter(|&max| max > 0).ok_or_else(|| Error::new(span, format!("akin: expected positive number in '#!{}(n)'", id)))?;
+                let max = usize::try_from(max).unwrap_or(usize::MAX);
+                if id.to_string() == "max_copies" {
+                    opts.max_copies = Some(max);
+                } else {
+                    opts.max_bytes = Some(max);
+                }
+            }
+            Some(TokenTree::Ident(id)) if matches!(id.to_string().as_str(), "allow" | "warn" | "deny") => {
+                let level = match id.to_string().as_str() {
+                    "allow" => Level::Allow,
+                    "warn" => Level::Warn,
+                    _ => Level::Deny,
+                };
+                let span = tokens.span();
+                let lints = match tokens.next() {
+                    Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => g.stream(),
+                    _ => return Err(Error::new(span, format!("akin: expected lints in parentheses after '#!{}', like '#!{}(unused)'", id, id))),
+                };
+                for lint in lints.into_iter().filter(|tt| !matches!(tt, TokenTree::Punct(p) if p.as_char() == ',')) {
+                    let span = lint.span();
+                    let lint = lint.to_string();
+                    if !LINTS.contains(&lint.as_str()) {
+                        return Err(Error::new(span, format!("akin: unknown lint '{}' in '#!{}(...)', expected one of: {}", lint, id, LINTS.join(", "))));
+                    }
+                    opts.lints.push((lint, level));
+                }
+            }
+            Some(tt) => return Err(Error::new(span, format!("akin: unknown directive '#!{}'", tt))),
+            None => return Err(Error::new(span, "akin: expected directive name after '#!'")),
+        }
+    }
+
+    Ok(opts)
+}
+
+/// Parses the combinations of `#!product except [(a, b), ...]`.
+/// An element that isn't a tuple is a combination of a single value.
+fn parse_except(stream: proc_macro::TokenStream) -> Vec<Vec<String>> {
+    fn split_commas(stream: proc_macro::TokenStream) -> Vec<String> {
+        let mut values = vec![proc_macro::TokenStream::new()];
+        for tt in stream {
+            match tt {
+                TokenTree::Punct(p) if p.as_char() == ',' => values.push(proc_macro::TokenStream::new()),
+                tt => values.last_mut().unwrap().extend([tt]),
+            }
+        }
+        values
+            .into_iter()
+            .filter(|v| !v.is_empty())
+            .map(|v| normalize_tokens(&v.to_string()))
+            .collect()
+    }
+
+    let mut combinations = Vec::new();
+    for value in split_commas(stream) {
+        let stream: proc_macro::TokenStream = value.parse().unwrap();
+        let mut iter = stream.clone().into_iter();
+        match (iter.next(), iter.next()) {
+            (Some(TokenTree::Group(g)), None) if g.delimiter() == Delimiter::Parenthesis => {
+                combinations.push(split_commas(g.stream()))
+            }
+            _ => combinations.push(vec![value]),
+        }
+    }
+    combinations
+}
+
+struct Lookahead {
+    queue: [Option<TokenTree>; 2],
+    iter: proc_macro::token_stream::IntoIter,
+    /// Span of the last token read, where a missing one is reported.
+    last: Option<proc_macro::Span>,
+}
+
+impl Lookahead {
+    fn queue_pop(&mut self) -> Option<TokenTree> {
+        if let Some(tt) = self.queue[0].take() {
+            self.queue[0] = self.queue[1].take();
+            return Some(tt);
+        }
+        None
+    }
+
+    fn queue_push(&mut self, elem: TokenTree) {
+        if self.queue[0].is_none() {
+            self.queue[0] = Some(elem)
+        } else if self.queue[1].is_none() {
+            self.queue[1] = Some(elem)
+        } else {
+            panic!("akin: internal bug, lookahead buffer size exceeded")
+        }
+    }
+
+    fn peek_nth(&mut self, i: usize) -> Option<&TokenTree> {
+        assert!(i < self.queue.len(), "akin: internal bug, lookahead buffer index too big: {}", i);
+        while self.queue[i].is_none() {
+            if let Some(elem) = self.iter.next() {
+                self.queue_push(elem);
+            } else {
+                return None;
+            }
+        }
+        self.queue[i].as_ref()
+    }
+
+    /// Returns the span of the next token, where an error about it is shown,
+    /// or the one of the last token read if there are no more.
+    fn span(&mut self) -> proc_macro::Span {
+        match self.peek_nth(0) {
+            Some(tt) => tt.span(),
+            None => self.last.unwrap_or_else(proc_macro::Span::call_site),
+        }
+    }
+}
+
+impl Iterator for Lookahead {
+    type Item = <proc_macro::token_stream::IntoIter as Iterator>::Item;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let tt = self.queue_pop().or_else(|| self.iter.next());
+        if let Some(tt) = &tt {
+            self.last = Some(tt.span());
+        }
+        tt
+    }
+}
+
+impl From<proc_macro::token_stream::IntoIter> for Lookahead {
+    fn from(iter: proc_macro::token_stream::IntoIter) -> Self {
+        Lookahead { queue: Default::default(), iter, last: None }
+    }
+}
+
+/// Parses the `let &` declarations at the start of `tokens` into `vars`.
+///
+/// Values can reference variables declared after them, which are substituted once all of them are declared.
+/// A variable whose values reference itself, directly or through the values of other variables,
+/// is an error, as `*name` would be left in the generated code.
+/// A variable declared before can still be used to declare it again, like `let &a = [*a + 1];`.
+///
+/// Returns the variables declared, with the span of their name and whether the values of another declaration use them.
+fn parse_vars(tokens: &mut Lookahead, vars: &mut Map<String, Vec<Value>>, opts: &Options) -> Result<Vec<(String, Option<proc_macro::Span>, bool)>, Error> {
+    // The variables referenced by each declaration, and if they were already declared, so their values were substituted
+    let mut refs: Map<String, Vec<(String, bool)>> = Map::new();
+    // The names given by each declaration, the span of its name, and if its values still reference variables declared after it
+    let mut declarations: Vec<(Vec<String>, proc_macro::Span, bool)> = Vec::new();
+    let mut lazy_vars: Vec<String> = Vec::new();
+    let mut declared_vars: Vec<(String, Option<proc_macro::Span>, bool)> = Vec::new();
+    // Variables referenced before they're declared, which are used once they are
+    let mut forward: Vec<String> = Vec::new();
+    while let Some(declaration) = next_declaration(tokens) {
+        let mut referenced = Vec::new();
+        // Skips `let &name`
+        references(declaration.clone().into_iter().skip(3).collect(), opts.sigil(), true, &mut referenced);
+        let referenced: Vec<(String, bool)> = referenced.into_iter().map(|name| {
+            let declared = vars.contains_key(&format!("*{name}"));
+            (name, declared)
+        }).collect();
+
+        let name_span = declaration.clone().into_iter().nth(2).map(|tt| tt.span());
+        let declared = parse_var(&mut declaration.into_iter().into(), vars, opts)?.unwrap();
+        let roots: Vec<&str> = declared.iter().filter_map(|(name, _)| name.strip_prefix('*').filter(|name| !name.contains('.'))).collect();
+        // Values copied from a variable that isn't complete yet, like with an alias or `+=`, aren't either
+        let lazy = referenced.iter().any(|(var, declared)| !declared || lazy_vars.contains(var))
+            || roots.iter().any(|name| lazy_vars.iter().any(|var| var == name));
+        for &name in &roots {
+            if let Some(cycle) = find_cycle(name, &referenced, &refs, &mut vec![name.to_owned()]) {
+                let message = format!("akin: '&{}' references itself through '{}', so it can't be substituted", name, cycle);
+                return Err(Error::new(name_span.unwrap_or_else(proc_macro::Span::call_site), message));
+            }
+            refs.insert(name.to_owned(), referenced.clone());
+            lazy_vars.retain(|var| var != name);
+            if lazy {
+                lazy_vars.push(name.to_owned());
+            }
+        }
+        for (name, _, used) in &mut declared_vars {
+            *used |= referenced.iter().any(|(var, _)| var == name);
+        }
+        forward.extend(referenced.iter().filter(|(_, declared)| !declared).map(|(var, _)| var.clone()));
+        declared_vars.retain(|(name, ..)| !roots.contains(&name.as_str()));
+        declared_vars.extend(roots.iter().map(|name| (name.to_string(), name_span, false)));
+        let span = name_span.unwrap_or_else(proc_macro::Span::call_site);
+        declarations.push((declared.iter().map(|(name, _)| name.clone()).collect(), span, lazy));
+        vars.extend(declared);
+    }
+    for (name, _, used) in &mut declared_vars {
+        *used |= forward.contains(name);
+    }
+
+    // Starting from the last declarations, so the variables they reference are already substituted.
+    // Declarations replaced by a later one of the same name are skipped.
+    let mut replaced: Vec<&String> = Vec::new();
+    for (names, span, lazy) in declarations.iter().rev() {
+        if *lazy && !names.iter().any(|name| replaced.contains(&name)) {
+            for name in names.iter().filter(|name| !name.starts_with(MISMATCH)) {
+                let values = vars[name]
+                    .iter()
+                    .map(|value| if value.contains('*') { duplicate(value, vars, opts, *span).map(Value::from) } else { Ok(value.clone()) })
+                    .collect::<Result<_, _>>()?;
+                vars.insert(name.clone(), values);
+            }
+        }
+        replaced.extend(names);
+    }
+    Ok(declared_vars)
+}
+
+/// Takes the tokens of the `let &...;` declaration `tokens` starts with, if any.
+fn next_declaration(tokens: &mut Lookahead) -> Option<proc_macro::TokenStream> {
+    if !matches!(tokens.peek_nth(0), Some(TokenTree::Ident(id)) if id.to_string() == "let")
+        || !matches!(tokens.peek_nth(1), Some(TokenTree::Punct(p)) if p.as_char() == '&')
+    {
+        return None;
+    }
+    let mut declaration = Vec::new();
+    for tt in tokens.by_ref() {
+        let end = matches!(&tt, TokenTree::Punct(p) if p.as_char() == ';');
+        declaration.push(tt);
+        if end {
+            break;
+        }
+    }
+    Some(declaration.into_iter().collect())
+}
+
+/// Adds to `out` the variables referenced in a declaration, with `*name` in its values or their string literals,
+/// the names in its expressions `*{...}`, or `&name` in its aliases and the arguments of its value sources,
+/// which are only looked for if `args` is set.
+fn references(stream: proc_macro::TokenStream, sigil: char, args: bool, out: &mut Vec<String>) {
+    let mut prev = None;
+    for tt in stream {
+        match &tt {
+            TokenTree::Ident(id) => match &prev {
+                Some(TokenTree::Punct(p)) if p.as_char() == sigil || (args && p.as_char() == '&') => {
+                    out.push(id.to_string());
+                },
+                _ => {},
+            },
+            // Variables are written without `*` in expressions, so every name in them can be one
+            TokenTree::Group(g) if g.delimiter() == Delimiter::Brace && matches!(&prev, Some(TokenTree::Punct(p)) if p.as_char() == sigil) => {
+                fn idents(stream: proc_macro::TokenStream, out: &mut Vec<String>) {
+                    for tt in stream {
+                        match tt {
+                            TokenTree::Ident(id) => out.push(id.to_string()),
+                            TokenTree::Group(g) => idents(g.stream(), out),
+                            _ => {},
+                        }
+                    }
+                }
+                idents(g.stream(), out);
+            },
+            TokenTree::Group(g) => references(g.stream(), sigil, args && g.delimiter() == Delimiter::Parenthesis, out),
+            // Variables are substituted in string literals too, like `"*name"`
+            TokenTree::Literal(lit) => out.extend(var_names(&lit.to_string()).filter(|name| !name.is_empty()).map(str::to_owned)),
+            _ => {},
+        }
+        prev = Some(tt);
+    }
+}
+
+/// Looks for a path from the variable `name` back to itself through the `referenced` variables,
+/// like `&a -> &b -> &a`.
+///
+/// A previous declaration of `name`, like in `let &a = [*a + 1];`, is already substituted, so it doesn't count.
+fn find_cycle(
+    name: &str,
+    referenced: &[(String, bool)],
+    refs: &Map<String, Vec<(String, bool)>>,
+    path: &mut Vec<String>,
+) -> Option<String> {
+    for (var, declared) in referenced {
+        if !declared && var == name {
+            return Some(path.iter().chain([var]).map(|var| format!("&{var}")).collect::<Vec<_>>().join(" -> "));
+        }
+        if !path.contains(var) {
+            if let Some(referenced) = refs.get(var) {
+                path.push(var.clone());
+                if let Some(cycle) = find_cycle(name, referenced, refs, path) {
+                    return Some(cycle);
+                }
+                path.pop();
+            }
+        }
+    }
+    None
+}
+
+/// Parses a `let &name = ...;` declaration, or a tuple one like `let &(a, b) = pairs ...;`.
+///
+/// Returns every variable it declares, which is more than one when the values are records or tuples.
+fn parse_var(
+    tokens: &mut Lookahead,
+    vars: &Map<String, Vec<Value>>,
+    opts: &Options,
+) -> Result<Option<Declared>, Error> {
+    if !matches!(tokens.peek_nth(0), Some(TokenTree::Ident(id)) if id.to_string() == "let") {
+        return Ok(None);
+    }
+
+    if !matches!(tokens.peek_nth(1), Some(TokenTree::Punct(p)) if p.as_char() == '&') {
+        return Ok(None);
+    }
+
+    tokens.next();
+    tokens.next();
+
+    let span = tokens.span();
+    let (name, tuple) = match tokens.next() {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => {
+            let names: Vec<String> = g
+                .stream()
+                .into_iter()
+                .filter(|tt| !matches!(tt, TokenTree::Punct(p) if p.as_char() == ','))
+                .map(|tt| format!("*{tt}"))
+                .collect();
+            if names.len() < 2 {
+                return Err(Error::new(span, format!("akin: expected at least two variable names in '&{g}'")));
+            }
+            (names[0].clone(), Some(names))
+        },
+        Some(tt) => (format!("*{tt}"), None),
+        None => return Err(Error::new(span, "akin: expected variable name after 'let &'")),
+    };
+
+    // `let &name += ...;` adds values to a declared variable
+    let append = matches!(tokens.peek_nth(0), Some(TokenTree::Punct(p)) if p.as_char() == '+');
+    if append {
+        tokens.next();
+        if tuple.is_some() || !vars.contains_key(&name) {
+            return Err(Error::new(span, format!("akin: values can only be added with '+=' to a declared variable, and '&{}' is not", &name[1..])));
+        }
+    }
+    let eq_span = tokens.span();
+    if !matches!(tokens.next(), Some(TokenTree::Punct(p)) if p.as_char() == '=') {
+        return Err(Error::new(eq_span, format!("akin: expected '=' after variable name '&{}'", &name[1..])));
+    }
+
+    let mode = match tokens.peek_nth(0) {
+        Some(TokenTree::Ident(id)) if matches!(id.to_string().as_str(), "cumulative" | "pairs") => {
+            Some(tokens.next().unwrap().to_string())
+        },
+        _ => None,
+    };
+    let pairs_mode = mode.as_deref() == Some("pairs");
+    if pairs_mode && tuple.as_ref().is_none_or(|names| names.len() != 2) {
+        return Err(Error::new(span, "akin: 'pairs' must be declared as a tuple of two variables, like 'let &(a, b) = pairs [...];'"));
+    }
+    // Value sources split into several variables by themselves, the other values are split by `pairs`
+    let names = match &tuple {
+        Some(names) if !pairs_mode => names.clone(),
+        _ => vec![name.clone()],
+    };
+    if names.len() > 1 && !matches!(tokens.peek_nth(0), Some(TokenTree::Ident(_))) {
+        return Err(Error::new(span, "akin: only 'pairs' and value sources like 'csv(...)' can be declared as a tuple, like 'let &(a, b) = pairs [...];'"));
+    }
+
+    let mut after_last = Value::default();
+    let mut before_first = Value::default();
+    let values_span = tokens.span();
+    let mut declared = match tokens.next() {
+        Some(TokenTree::Group(g)) => parse_group_values(name.clone(), &g, vars, opts)?,
+        Some(TokenTree::Ident(source)) => {
+            // Arguments in parentheses `(...)`, an item in braces `{...}`, or both
+            let args = match tokens.peek_nth(0) {
+                Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => Some(g.stream()),
+                _ => None,
+            };
+            if args.is_some() {
+                tokens.next();
+            }
+            let item = match tokens.peek_nth(0) {
+                Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => Some(g.stream()),
+                _ => None,
+            };
+            if item.is_some() {
+                tokens.next();
+            }
+            if args.is_none() && item.is_none() {
+                let got = tokens.peek_nth(0).map(|tt| format!(", got '{tt}'")).unwrap_or_default();
+                return Err(Error::new(tokens.span(), format!("akin: expected arguments in parentheses after '&{}={}'{}", &name[1..], source, got)));
+            }
+            parse_source(&names, &source, args.unwrap_or_default(), item, vars, opts)?
+        },
+        Some(TokenTree::Punct(p)) if p.as_char() == '&' => match tokens.next() {
+            Some(TokenTree::Ident(of)) => {
+                // The alias keeps the values of the `next` and `prev` clauses
+                let of_span = of.span();
+                let mut of = of.to_string();
+                while matches!(tokens.peek_nth(0), Some(TokenTree::Punct(p)) if p.as_char() == '.') {
+                    tokens.next();
+                    let span = tokens.span();
+                    match tokens.next() {
+                        Some(TokenTree::Ident(field)) => write!(&mut of, ".{field}").unwrap(),
+                        tt => return Err(Error::new(span, format!("akin: expected field name after '&{}=&{}.', got {:?}", &name[1..], of, tt))),
+                    }
+                }
+                after_last = vars.get(&format!("*{of}.next")).and_then(|next| next.last().cloned()).unwrap_or_default();
+                before_first = vars.get(&format!("*{of}.prev")).and_then(|prev| prev.first().cloned()).unwrap_or_default();
+                let declared = alias(&name, &of, of_span, vars)?;
+                match tokens.peek_nth(0) {
+                    // A slice, `&of[1..3]`, whose `next` and `prev` are the values around it
+                    Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Bracket => {
+                        let range = slice_range(g, declared[0].1.len(), &name)?;
+                        tokens.next();
+                        if let Some(next) = declared[0].1.get(range.end) {
+                            after_last = next.clone();
+                        }
+                        if let Some(prev) = range.start.checked_sub(1) {
+                            before_first = declared[0].1[prev].clone();
+                        }
+                        select(declared, &range.collect::<Vec<_>>())
+                    },
+                    _ => declared,
+                }
+            },
+            tt => {
+                let span = tt.as_ref().map_or(tokens.span(), TokenTree::span);
+                return Err(Error::new(span, format!("akin: expected variable name after '&{}=&', got {:?}", &name[1..], tt)));
+            },
+        },
+        // A range, which can start with a negative number
+        Some(tt) if matches!(&tt, TokenTree::Literal(_)) || matches!(&tt, TokenTree::Punct(p) if p.as_char() == '-') => {
+            tokens.queue_push(tt);
+            let values = parse_range_expr(&name[1..], tokens, vars, opts)?;
+            vec![(name.clone(), values.into_iter().map(Value::from).collect())]
+        },
+        tt => return Err(Error::new(values_span, format!("akin: expected bracketed/braced group or range expression after '&{}=', got {:?}", &name[1..], tt))),
+    };
+
+    // Clauses after the values, like `next value` or `step(n)`.
+    let mut step = 1;
+    let mut hex = None;
+    let mut mismatch = None;
+    loop {
+        let span = tokens.span();
+        match tokens.next() {
+            Some(TokenTree::Punct(p)) if p.as_char() == ';' => break,
+            Some(TokenTree::Ident(id)) if id.to_string() == "next" => {
+                let value = tokens.next().ok_or_else(|| Error::new(id.span(), format!("akin: expected value after 'next' in '&{}' declaration", &name[1..])))?;
+                after_last = parse_single_value(value, vars, opts)?.into();
+            },
+            Some(TokenTree::Ident(id)) if id.to_string() == "prev" => {
+                let value = tokens.next().ok_or_else(|| Error::new(id.span(), format!("akin: expected value after 'prev' in '&{}' declaration", &name[1..])))?;
+                before_first = parse_single_value(value, vars, opts)?.into();
+            },
+            // `step(n)`, `step n` or `.step_by(n)`, like in a range
+            Some(TokenTree::Ident(id)) if id.to_string() == "step" => step = parse_step(tokens, &name)?,
+            Some(TokenTree::Punct(p)) if p.as_char() == '.' => match tokens.next() {
+                Some(TokenTree::Ident(id)) if id.to_string() == "step_by" => step = parse_step(tokens, &name)?,
+                tt => return Err(Error::new(tokens.last.unwrap_or(span), format!("akin: expected 'step_by(n)' after '.' in '&{}' declaration, got {:?}", &name[1..], tt))),
+            },
+            // `as hex`, to write integers as hex literals
+            Some(TokenTree::Ident(id)) if id.to_string() == "as" => match tokens.next() {
+                Some(TokenTree::Ident(id)) if id.to_string() == "hex" => hex = Some(id.span()),
+                tt => return Err(Error::new(tokens.last.unwrap_or(span), format!("akin: expected 'hex' after 'as' in '&{}' declaration, got {:?}", &name[1..], tt))),
+            },
+            // `mismatch(policy)`, like the directive but only for this variable
+            Some(TokenTree::Ident(id)) if id.to_string() == "mismatch" => {
+                mismatch = Some(Mismatch::parse_group(tokens, &format!("'&{}' declaration", &name[1..]))?);
+            },
+            _ => return Err(Error::new(span, format!("akin: expected ';' on end of '&{}' declaration", &name[1..]))),
+        }
+    }
+
+    if step > 1 {
+        for (_, values) in &mut declared {
+            *values = values.iter().step_by(step).cloned().collect();
+        }
+    }
+
+    if let Some(span) = hex {
+        for (_, values) in &mut declared {
+            *values = to_hex(values, &name, span)?;
+        }
+    }
+
+    if append {
+        if after_last.is_empty() {
+            after_last = vars.get(&format!("{name}.next")).and_then(|next| next.last().cloned()).unwrap_or_default();
+        }
+        if before_first.is_empty() {
+            before_first = vars.get(&format!("{name}.prev")).and_then(|prev| prev.first().cloned()).unwrap_or_default();
+        }
+        declared = concat_lists(vec![alias(&name, &name[1..], span, vars)?, declared]);
+    }
+
+    match mode.as_deref() {
+        Some("cumulative") => {
+            for (_, values) in &mut declared {
+                *values = prefixes(values);
+            }
+        },
+        Some("pairs") => {
+            let [first, second] = tuple.as_deref().unwrap() else { unreachable!() };
+            declared = pairs(&declared, first, second);
+        },
+        _ => {},
+    }
+
+    // `*name.next` is the value of the following copy, or the one of the `next` clause in the last one,
+    // and `*name.prev` the value of the previous copy, or the one of the `prev` clause in the first one.
+    // `*name.len`, `*name.first` and `*name.last` are the same in every copy.
+    for name in tuple.unwrap_or(names) {
+        let values = &declared.iter().find(|(n, _)| *n == name).unwrap().1;
+        let next = values.iter().skip(1).cloned().chain([after_last.clone()]).collect();
+        let prev = [before_first.clone()].into_iter().chain(values.iter().take(values.len().saturating_sub(1)).cloned()).collect();
+        let len = vec![values.len().to_string().into()];
+        let first = vec![values.first().cloned().unwrap_or_default()];
+        let last = vec![values.last().cloned().unwrap_or_default()];
+        declared.push((format!("{name}.next"), next));
+        declared.push((format!("{name}.prev"), prev));
+        declared.push((format!("{name}.len"), len));
+        declared.push((format!("{name}.first"), first));
+        declared.push((format!("{name}.last"), last));
+        // Always declared, so a new declaration without it goes back to the one of the directive
+        let policy = mismatch.map(|policy| vec![policy.as_str().into()]).unwrap_or_default();
+        declared.push((format!("{MISMATCH}{name}"), policy));
+    }
+
+    Ok(Some(declared))
+}
+
+/// Parses the `n` of a `step(n)` or `step n` clause in the declaration of `name`.
+fn parse_step(tokens: &mut Lookahead, name: &str) -> Result<usize, Error> {
+    let span = tokens.span();
+    match tokens.next() {
+        Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => parse_int(&g.stream().to_string()),
+        Some(TokenTree::Literal(l)) => parse_int(&l.to_string()),
+        _ => None,
+    }
+    .filter(|&step| step > 0)
+    .map(|step| step as usize)
+    .ok_or_else(|| Error::new(span, format!("akin: expected positive number in 'step(n)' of '&{}' declaration", &name[1..])))
+}
+
+/// Fields declared for every variable, like `*name.next`.
+///
+/// Followed by `(`, they are a method call on the value instead, like `*name.len()`.
+const ACCESSORS: &[&str] = &["next", "prev", "len", "first", "last"];
+
+/// Declares `name` as an alias of the variable `of`, written at `span`, with the same values and fields.
+///
+/// Accessors like `*of.next` aren't copied, as they are declared again for `name`.
+fn alias(name: &str, of: &str, span: proc_macro::Span, vars: &Map<String, Vec<Value>>) -> Result<Vec<(String, Vec<Value>)>, Error> {
+    let of = format!("*{of}");
+    if !vars.contains_key(&of) {
+        return Err(Error::new(span, format!("akin: can't declare '&{}' as '&{}', which isn't declared", &name[1..], &of[1..])));
+    }
+    let mut declared = vec![(name.to_owned(), vars[&of].clone())];
+    for (var, values) in vars {
+        let Some(field) = var.strip_prefix(&of).and_then(|rest| rest.strip_prefix('.')) else {
+            continue;
+        };
+        if !ACCESSORS.contains(&field) {
+            declared.push((format!("{name}.{field}"), values.clone()));
+        }
+    }
+    Ok(declared)
+}
+
+/// Parses a value written on its own instead of in a list, like the one of a `next` or `prev` clause.
+fn parse_single_value(tt: TokenTree, vars: &Map<String, Vec<Value>>, opts: &Options) -> Result<String, Error> {
+    let mut fold = String::new();
+    let span = tt.span();
+    match tt {
+        TokenTree::Group(g) if g.delimiter() == Delimiter::Brace => {
+            let mut prev = Some(group_start());
+            fold_stream(&mut fold, g.stream(), &mut prev, vars, opts)?;
+        },
+        tt => write!(&mut fold, "{tt}").unwrap(),
+    }
+
+    match fold.trim_start() {
+        "NONE" => Ok(String::new()),
+        "SKIP" => Ok(SKIP.to_owned()),
+        fold => duplicate(fold, vars, opts, span),
+    }
+}
+
+/// Splits every variable declared as `first` into `first` and `second`,
+/// holding each pair of values `(i, j)` with `i <= j`.
+///
+/// Fields of records are split the same way, into `first.field` and `second.field`.
+fn pairs(declared: &[(String, Vec<Value>)], first: &str, second: &str) -> Vec<(String, Vec<Value>)> {
+    let len = declared[0].1.len();
+    let mut split = Vec::new();
+    for (var, values) in declared {
+        let field = &var[first.len()..];
+        let (mut a, mut b) = (Vec::new(), Vec::new());
+        for i in 0..len {
+            for j in i..len {
+                a.push(values[i].clone());
+                b.push(values[j].clone());
+            }
+        }
+        split.push((format!("{first}{field}"), a));
+        split.push((format!("{second}{field}"), b));
+    }
+    split
+}
+
+/// Turns every value into the list of all the values up to it, separated by commas.
+///
+/// Empty values (`NONE`) are left out of the lists.
+fn prefixes(values: &[Value]) -> Vec<Value> {
+    let mut prefix = String::new();
+    values
+        .iter()
+        .map(|value| {
+            let value = value.trim();
+            if !value.is_empty() {
+                if !prefix.is_empty() {
+                    prefix.push_str(", ");
+                }
+                prefix.push_str(value);
+            }
+            Value::from(prefix.as_str())
+        })
+        .collect()
+}
+
+/// Parses the values of the variable `name` from a bracketed list `[...]` or a braced code block `{...}`.
+///
+/// Returns every variable declared, which is more than one when the values are records.
+fn parse_group_values(
+    name: String,
+    group: &proc_macro::Group,
+    vars: &Map<String, Vec<Value>>,
+    opts: &Options,
+) -> Result<Vec<(String, Vec<Value>)>, Error> {
+    let mut values: Vec<String> = Vec::new();
+    let mut records: Vec<Vec<(String, String)>> = Vec::new();
+
+    // `[value; N]` repeats the value N times
+    let tts: Vec<TokenTree> = group.stream().into_iter().collect();
+    let semicolon = tts.iter().position(|tt| matches!(tt, TokenTree::Punct(p) if p.as_char() == ';'));
+    if let (Some(semicolon), Delimiter::Bracket) = (semicolon, group.delimiter()) {
+        let span = tts.get(semicolon + 1).unwrap_or(&tts[semicolon]).span();
+        let times: String = tts[semicolon + 1..].iter().map(|tt| tt.to_string()).collect();
+        let times: usize = strip_int_suffix(&times).replace('_', "").parse().map_err(|_| {
+            Error::new(span, format!("akin: expected number of times after ';' in '&{}=[...; N]', got '{}'", &name[1..], times))
+        })?;
+        let value = proc_macro::Group::new(Delimiter::Bracket, tts[..semicolon].iter().cloned().collect());
+        return parse_group_values(name.clone(), &value, vars, opts)?
+            .into_iter()
+            .map(|(var, values)| {
+                // Checked before repeating them, so a huge `N` fails right away instead of taking all the memory
+                let count = values.len().saturating_mul(times);
+                check_values(count, &name[1..], span, opts)?;
+                Ok((var, values.iter().cycle().take(count).cloned().collect()))
+            })
+            .collect();
+    }
+
+    if group.delimiter() == Delimiter::Bracket {
+        let mut splits = value_commas(&tts).into_iter();
+        let mut stream = tts.into_iter();
+
+        while let Some(mut var) = stream.next() {
+            // A value gated by `#[cfg(...)]` only generates code if the predicate holds
+            let mut cfg = None;
+            if matches!(&var, TokenTree::Punct(p) if p.as_char() == '#') {
+                let mut span = var.span();
+                cfg = match stream.next() {
+                    Some(TokenTree::Group(attr)) if attr.delimiter() == Delimiter::Bracket => {
+                        span = attr.span();
+                        parse_cfg(&attr)
+                    },
+                    _ => None,
+                };
+                let Some((cfg, next)) = cfg.as_ref().zip(stream.next()) else {
+                    return Err(Error::new(span, format!("akin: expected '#[cfg(...)]' followed by a value in '&{}' declaration", &name[1..])));
+                };
+                if matches!(&next, TokenTree::Punct(p) if p.as_char() == ',') {
+                    return Err(Error::new(next.span(), format!("akin: expected value after '#[cfg({})]' in '&{}' declaration", cfg, &name[1..])));
+                }
+                var = next;
+            }
+
+            let mut new = String::new();
+            let mut len = 0;
+            let mut record = None;
+            let mut braced = false;
+            while !matches!(&var, TokenTree::Punct(p) if p.as_char() == ',' && splits.next().unwrap_or(true)) {
+                match &var {
+                    TokenTree::Group(g) if g.delimiter() == Delimiter::Brace => {
+                        if len == 0 {
+                            record = parse_record(g.stream(), vars, opts)?;
+                        }
+                        // Only the start of the value isn't spaced, so it isn't glued to the tokens before it
+                        let mut prev = if len == 0 { Some(group_start()) } else { None };
+                        fold_stream(&mut new, g.stream(), &mut prev, vars, opts)?;
+                        braced = true;
+                    },
+                    _ => write!(&mut new, "{var}").unwrap(),
+                };
+                len += 1;
+
+                if let Some(v) = stream.next() {
+                    var = v;
+                } else {
+                    break;
+                }
+            }
+
+            let mut record = record.filter(|_| len == 1).unwrap_or_default();
+            // `{NONE}` and `{SKIP}` are written as is
+            let mut value = if new == "NONE" && !braced {
+                String::new()
+            } else if new == "SKIP" && !braced {
+                SKIP.to_owned()
+            } else {
+                duplicate(&new, vars, opts, group.span())?
+            };
+            if let Some(cfg) = cfg {
+                value.insert_str(0, &format!("{CFG}{cfg}{CFG}"));
+                for (_, field) in &mut record {
+                    field.insert_str(0, &format!("{CFG}{cfg}{CFG}"));
+                }
+            }
+            records.push(record);
+            values.push(value);
+        }
+    } else {
+        records.push(parse_record(group.stream(), vars, opts)?.unwrap_or_default());
+        let mut fold = String::new();
+        let mut prev = Some(group_start());
+        fold_stream(&mut fold, group.stream(), &mut prev, vars, opts)?;
+        values.push(duplicate(&fold, vars, opts, group.span())?);
+    }
+
+    Ok(declare_records(name, values, records))
+}
+
+/// Returns, for each comma of a `[...]` list, whether it separates two values,
+/// which it doesn't inside angle brackets, like in `HashMap<K, V>`.
+///
+/// A `<` only opens them after a name, like `Vec<`, `::` or at the start of a value, so `1 < 2` is still a comparison,
+/// and if they aren't balanced, every comma separates values.
+fn value_commas(tts: &[TokenTree]) -> Vec<bool> {
+    let punct = |i: usize| match tts.get(i) {
+        Some(TokenTree::Punct(p)) => Some(p),
+        _ => None,
+    };
+    // `<=`, `>=`, `->` and `=>` aren't angle brackets
+    let joint_eq = |i: usize| punct(i).is_some_and(|p| p.spacing() == Spacing::Joint) && punct(i + 1).is_some_and(|p| p.as_char() == '=');
+    let arrow = |i: usize| i > 0 && punct(i - 1).is_some_and(|p| p.spacing() == Spacing::Joint && matches!(p.as_char(), '-' | '='));
+    // Generics follow a type name or `::`, and qualified paths like `<T as Trait>::X` start a value
+    let generic = |i: usize| match i.checked_sub(1).map(|i| &tts[i]) {
+        None => true,
+        Some(TokenTree::Ident(_)) => true,
+        Some(TokenTree::Punct(p)) => matches!(p.as_char(), ',' | ':'),
+        _ => false,
+    };
+
+    let mut depth = 0usize;
+    let mut splits = Vec::new();
+    for (i, tt) in tts.iter().enumerate() {
+        let TokenTree::Punct(p) = tt else {
+            continue;
+        };
+        match p.as_char() {
+            '<' if !joint_eq(i) && generic(i) => depth += 1,
+            '>' if !joint_eq(i) && !arrow(i) => match depth.checked_sub(1) {
+                Some(d) => depth = d,
+                None => return Vec::new(),
+            },
+            ',' => splits.push(depth == 0),
+            _ => {},
+        }
+    }
+    if depth > 0 {
+        return Vec::new();
+    }
+    splits
+}
+
+/// Removes the predicates of gated values that weren't used as a whole, like the ones in `:join` or cumulative values,
+/// which are always included.
+fn strip_cfgs(text: &str) -> String {
+    let mut parts = text.split(CFG);
+    let mut out: String = parts.next().unwrap_or_default().to_owned();
+    while let (Some(_), Some(rest)) = (parts.next(), parts.next()) {
+        out.push_str(rest);
+    }
+    out
+}
+
+/// Returns the predicate of a `#[cfg(...)]` attribute, given its brackets.
+fn parse_cfg(attr: &proc_macro::Group) -> Option<String> {
+    let mut tts = attr.stream().into_iter();
+    match (tts.next(), tts.next(), tts.next()) {
+        (Some(TokenTree::Ident(id)), Some(TokenTree::Group(g)), None) if id.to_string() == "cfg" && g.delimiter() == Delimiter::Parenthesis => Some(g.stream().to_string()),
+        _ => None,
+    }
+}
+
+/// Declares the variable `name` with `values`, and every field of `records` as its own variable `*name.field`,
+/// with one value per element.
+///
+/// Elements that lack the field get an empty value, like NONE.
+fn declare_records(name: String, values: Vec<String>, records: Vec<Vec<(String, String)>>) -> Vec<(String, Vec<Value>)> {
+    let mut declared = vec![(name, values.into_iter().map(Value::from).collect::<Vec<_>>())];
+    for (i, record) in records.into_iter().enumerate() {
+        for (field, value) in record {
+            let field = format!("{}.{}", declared[0].0, field);
+            let pos = match declared.iter().position(|(n, _)| *n == field) {
+                Some(pos) => pos,
+                None => {
+                    declared.push((field, Vec::new()));
+                    declared.len() - 1
+                }
+            };
+            let values = &mut declared[pos].1;
+            values.resize(i, Value::default());
+            values.push(value.into());
+        }
+    }
+    let len = declared[0].1.len();
+    for (_, values) in &mut declared[1..] {
+        values.resize(len, Value::default());
+    }
+
+    declared
+}
+
+/// Parses the contents of a braced value as the fields of a record, `{field: value, ...}`.
+///
+/// Returns `None` if the contents are not shaped like a record.
+/// A field value can be enclosed in brackets `{...}` if it contains commas.
+fn parse_record(
+    stream: proc_macro::TokenStream,
+    vars: &Map<String, Vec<Value>>,
+    opts: &Options,
+) -> Result<Option<Vec<(String, String)>>, Error> {
+    let mut fields = Vec::new();
+    let mut stream = stream.into_iter().peekable();
+
+    while let Some(tt) = stream.next() {
+        let TokenTree::Ident(field) = tt else {
+            return Ok(None);
+        };
+        if !matches!(stream.next(), Some(TokenTree::Punct(p)) if p.as_char() == ':' && p.spacing() == Spacing::Alone) {
+            return Ok(None);
+        }
+
+        let mut value: Vec<TokenTree> = Vec::new();
+        while let Some(tt) = stream.next_if(|tt| !matches!(tt, TokenTree::Punct(p) if p.as_char() == ',')) {
+            value.push(tt);
+        }
+        stream.next();
+
+        let value = match value.as_slice() {
+            [TokenTree::Group(g)] if g.delimiter() == Delimiter::Brace => g.stream().into_iter().collect(),
+            _ => value,
+        };
+        let mut fold = String::new();
+        let mut prev = None;
+        fold_stream(&mut fold, value, &mut prev, vars, opts)?;
+
+        let value = match fold.trim_start() {
+            "NONE" => String::new(),
+            "SKIP" => SKIP.to_owned(),
+            fold => duplicate(fold, vars, opts, field.span())?,
+        };
+        fields.push((field.to_string(), value));
+    }
+
+    Ok(Some(fields).filter(|f| !f.is_empty()))
+}
+
+/// Concatenates the values of several lists declared with the same name, and the ones of their fields.
+///
+/// The elements of a list that lack a field get an empty value, like NONE.
+fn concat_lists(lists: Vec<Vec<(String, Vec<Value>)>>) -> Vec<(String, Vec<Value>)> {
+    let mut merged: Vec<(String, Vec<Value>)> = Vec::new();
+    let mut len = 0;
+    for list in lists {
+        let list_len = list[0].1.len();
+        for (var, values) in list {
+            let pos = match merged.iter().position(|(v, _)| *v == var) {
+                Some(pos) => pos,
+                None => {
+                    merged.push((var, vec![Value::default(); len]));
+                    merged.len() - 1
+                }
+            };
+            merged[pos].1.extend(values);
+        }
+        len += list_len;
+        for (_, values) in &mut merged {
+            values.resize(len, Value::default());
+        }
+    }
+    merged
+}
+
+/// Fails if a variable would have more values than the copies allowed, at the `span` of what makes them,
+/// checked before making them, so a huge list of values fails right away instead of hanging the compiler.
+fn check_values(count: usize, declared: &str, span: proc_macro::Span, opts: &Options) -> Result<(), Error> {
+    if count > opts.max_copies() {
+        let message = format!(
+            "akin: '&{}' would have {} values, over the limit of {}; raise it with '#!max_copies(n)' if that's intended",
+            declared, thousands(count), thousands(opts.max_copies())
+        );
+        return Err(Error::new(span, message));
+    }
+    Ok(())
+}
+
+/// Parses the range in the brackets `group` of a slice of a list with `len` values, like `1..3`, `2..`, `..=4` or a single index, like `2`.
+fn slice_range(group: &proc_macro::Group, len: usize, name: &str) -> Result<std::ops::Range<usize>, Error> {
+    let slice = group.stream().to_string().replace(' ', "");
+    let bound = |bound: &str, default: usize| match bound {
+        "" => Some(default),
+        _ => parse_int(bound).and_then(|n| usize::try_from(n).ok()),
+    };
+    let range = match slice.split_once("..") {
+        Some((start, end)) => match end.strip_prefix('=') {
+            Some("") => None,
+            Some(end) => bound(start, 0).zip(bound(end, 0).map(|end| end + 1)),
+            None => bound(start, 0).zip(bound(end, len)),
+        },
+        None => parse_int(&slice).and_then(|i| usize::try_from(i).ok()).map(|i| (i, i + 1)),
+    };
+    match range {
+        Some((start, end)) if start <= end && end <= len => Ok(start..end),
+        Some((start, end)) => Err(Error::new(
+            group.span(),
+            format!("akin: slice '[{}]' in '&{}' declaration is out of bounds, the list has {} values ({}..{})", slice, &name[1..], len, start, end),
+        )),
+        None => Err(Error::new(group.span(), format!("akin: expected range like '[1..3]' in slice of '&{}', got '[{}]'", &name[1..], slice))),
+    }
+}
+
+/// Keeps the values at `indices` of a list and its fields, in that order.
+fn select(list: Vec<(String, Vec<Value>)>, indices: &[usize]) -> Vec<(String, Vec<Value>)> {
+    list.into_iter()
+        .map(|(var, values)| (var, indices.iter().map(|&i| values.get(i).cloned().unwrap_or_default()).collect()))
+        .collect()
+}
+
+/// Writes integer values as hex literals, padded to the width of the widest one, like `0x0A`.
+///
+/// `span` is the one of the `hex` asking for it, where a value that isn't an integer is reported.
+fn to_hex(values: &[Value], name: &str, span: proc_macro::Span) -> Result<Vec<Value>, Error> {
+    let suffixes: Vec<&str> = values.iter().map(|v| int_suffix(v)).collect();
+    let values = values
+        .iter()
+        .map(|v| {
+            parse_int(v).ok_or_else(|| Error::new(span, format!("akin: '{}' in '&{}' declaration is not an integer, so it can't be written 'as hex'", v, &name[1..])))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let width = values.iter().map(|v| format!("{:X}", v.unsigned_abs()).len()).max().unwrap_or(1);
+    Ok(values
+        .iter()
+        .zip(suffixes)
+        .map(|(v, suffix)| format!("{}0x{:0width$X}{suffix}", if *v < 0 { "-" } else { "" }, v.unsigned_abs()).into())
+        .collect())
+}
+
+/// Parses an integer literal, which can be negative, like `-10`.
+fn parse_integer_literal(tokens: &mut Lookahead) -> Result<i128, &'static str> {
+    let negative = matches!(tokens.peek_nth(0), Some(TokenTree::Punct(p)) if p.as_char() == '-');
+    match tokens.peek_nth(negative as usize) {
+        Some(TokenTree::Literal(l)) => {
+            let n = parse_int(&l.to_string()).ok_or("non-integer literal")?;
+            tokens.next();
+            if negative {
+                tokens.next();
+            }
+            Ok(if negative { -n } else { n })
+        },
+        Some(_) => Err("non-literal token"),
+        None => Err("unexpected end of input"),
+    }
+}
+
+fn parse_char_literal(tokens: &mut Lookahead) -> Result<char, &'static str> {
+    match tokens.peek_nth(0) {
+        Some(TokenTree::Literal(l)) => {
+            let literal = l.to_string();
+            let inner = literal.strip_prefix('\'').and_then(|l| l.strip_suffix('\'')).ok_or("non-char literal")?;
+            let mut chars = unescape_str(&format!("\"{inner}\"")).ok_or("invalid char literal")?.chars().collect::<Vec<_>>();
+            let c = chars.pop().filter(|_| chars.is_empty()).ok_or("invalid char literal")?;
+            tokens.next();
+            Ok(c)
+        },
+        Some(_) => Err("non-literal token"),
+        None => Err("unexpected end of input"),
+    }
+}
+
+/// Parses the `..` or `..=` of a range starting with `start`, returning whether it's inclusive.
+fn parse_range_op(var_name: &str, start: &str, tokens: &mut Lookahead) -> Result<bool, Error> {
+    let span = tokens.span();
+    let inclusive = match (tokens.next(), tokens.next(), tokens.peek_nth(0)) {
+        (Some(TokenTree::Punct(p1)), Some(TokenTree::Punct(p2)), p3) if p1.spacing() == Spacing::Joint && (p1.as_char(), p2.as_char()) == ('.', '.') => {
+            p2.spacing() == Spacing::Joint && matches!(p3, Some(TokenTree::Punct(p3)) if p3.as_char() == '=')
+        },
+        _ => return Err(Error::new(span, format!("akin: expected '..' or '..=' after 'let &{}={}'", var_name, start))),
+    };
+
+    if inclusive {
+        tokens.next(); // drop the '=' in '..='
+    }
+    Ok(inclusive)
+}
+
+/// Parses a range of chars, like `'a'..='z'`, into char literals.
+fn parse_char_range(var_name: &str, tokens: &mut Lookahead) -> Result<Vec<String>, Error> {
+    let span = tokens.span();
+    let range_start = parse_char_literal(tokens).map_err(|e| Error::new(span, format!("akin: invalid range start in 'let &{}=': {}", var_name, e)))?;
+    let inclusive = parse_range_op(var_name, &format!("{range_start:?}"), tokens)?;
+    let range_end = match parse_char_literal(tokens) {
+        Ok(c) => c,
+        Err(e) => {
+            let got = tokens.peek_nth(0).map(|tt| format!(", got {} '{}'", e, tt)).unwrap_or_default();
+            return Err(Error::new(tokens.span(), format!("akin: char literal expected after 'let &{}={:?}..'{}", var_name, range_start, got)));
+        }
+    };
+
+    let last = Some(range_end).filter(|_| inclusive);
+    Ok((range_start..range_end).chain(last).map(|c| format!("{c:?}")).collect())
+}
+
+/// Parses a float literal, which can be negative, returning its value and its text.
+fn parse_float_literal(tokens: &mut Lookahead) -> Result<(f64, String), &'static str> {
+    let negative = matches!(tokens.peek_nth(0), Some(TokenTree::Punct(p)) if p.as_char() == '-');
+    match tokens.peek_nth(negative as usize) {
+        Some(TokenTree::Literal(l)) => {
+            let literal = l.to_string();
+            let n = parse_float(&literal).or_else(|| parse_int(&literal).map(|n| n as f64)).ok_or("non-numeric literal")?;
+            tokens.next();
+            if negative {
+                tokens.next();
+            }
+            Ok(if negative { (-n, format!("-{literal}")) } else { (n, literal) })
+        },
+        Some(_) => Err("non-literal token"),
+        None => Err("unexpected end of input"),
+    }
+}
+
+/// Returns the value of a float literal, like `0.25` or `1e3f32`, or `None` if it's another kind of literal.
+fn parse_float(literal: &str) -> Option<f64> {
+    let digits = literal.strip_suffix("f32").or_else(|| literal.strip_suffix("f64")).unwrap_or(literal);
+    let is_float = digits != literal || digits.contains('.') || (!digits.starts_with("0x") && digits.contains(['e', 'E']));
+    digits.replace('_', "").parse().ok().filter(|_| is_float)
+}
+
+/// Parses a range of floats with an explicit step, like `0.0..=1.0 step 0.25`, into float literals.
+///
+/// The values are written with as many decimals as the bounds and the step, and with the suffix of the start, if any.
+fn parse_float_range(var_name: &str, tokens: &mut Lookahead, opts: &Options) -> Result<Vec<String>, Error> {
+    let span = tokens.span();
+    let (start, start_text) = parse_float_literal(tokens).map_err(|e| Error::new(span, format!("akin: invalid range start in 'let &{}=': {}", var_name, e)))?;
+    let inclusive = parse_range_op(var_name, &start_text, tokens)?;
+    let end_span = tokens.span();
+    let (end, end_text) =
+        parse_float_literal(tokens).map_err(|e| Error::new(end_span, format!("akin: invalid range end in 'let &{}={}..': {}", var_name, start_text, e)))?;
+
+    let step_span = tokens.span();
+    let step = match tokens.next() {
+        Some(TokenTree::Ident(id)) if id.to_string() == "step" => parse_float_literal(tokens).ok(),
+        Some(TokenTree::Punct(p)) if p.as_char() == '.' => match (tokens.next(), tokens.next()) {
+            (Some(TokenTree::Ident(id)), Some(TokenTree::Group(g))) if id.to_string() == "step_by" => {
+                parse_float_literal(&mut g.stream().into_iter().into()).ok()
+            }
+            _ => None,
+        },
+        _ => None,
+    };
+    let Some((step, step_text)) = step.filter(|(step, _)| *step > 0.0) else {
+        return Err(Error::new(step_span, format!("akin: float range 'let &{}={}..{}' needs a positive step, like 'step 0.5'", var_name, start_text, end_text)));
+    };
+
+    let decimals = |text: &str| match text.split_once('.') {
+        Some((_, decimals)) if !decimals.contains(['e', 'E']) => decimals.chars().take_while(|c| c.is_ascii_digit() || *c == '_').filter(|c| *c != '_').count(),
+        _ => 0,
+    };
+    let precision = decimals(&start_text).max(decimals(&end_text)).max(decimals(&step_text)).max(1);
+    let suffix = ["f32", "f64"].into_iter().find(|s| start_text.ends_with(s)).unwrap_or_default();
+
+    // Computed from the start each time, and compared with some tolerance, so rounding errors don't add up
+    let len = (end - start) / step;
+    let len = if inclusive { ((len + 1e-9).floor() as usize).saturating_add(1) } else { (len - 1e-9).ceil().max(0.0) as usize };
+    check_values(len, var_name, span, opts)?;
+    Ok((0..len).map(|i| format!("{:.precision$}{suffix}", start + step * i as f64)).collect())
+}
+
+fn parse_range_expr(
+    var_name: &str,
+    tokens: &mut Lookahead,
+    vars: &Map<String, Vec<Value>>,
+    opts: &Options,
+) -> Result<Vec<String>, Error> {
+    if matches!(tokens.peek_nth(0), Some(TokenTree::Literal(l)) if l.to_string().starts_with('\'')) {
+        return parse_char_range(var_name, tokens);
+    }
+    let negative = matches!(tokens.peek_nth(0), Some(TokenTree::Punct(p)) if p.as_char() == '-');
+    if matches!(tokens.peek_nth(negative as usize), Some(TokenTree::Literal(l)) if parse_float(&l.to_string()).is_some()) {
+        return parse_float_range(var_name, tokens, opts);
+    }
+
+    // The values keep the type suffix of the start, like `0u8..4`
+    let suffix = match tokens.peek_nth(negative as usize) {
+        Some(TokenTree::Literal(l)) => int_suffix(&l.to_string()).to_owned(),
+        _ => String::new(),
+    };
+    let span = tokens.span();
+    let range_start = match parse_integer_literal(tokens) {
+        Ok(v) => v,
+        Err(e) => {
+            let got = tokens.peek_nth(0).map(|tt| format!(", got {} '{}'", e, tt)).unwrap_or_default();
+            return Err(Error::new(span, format!("akin: integer literal expected after 'let &{}='{}", var_name, got)));
+        }
+    };
+
+    let inclusive = parse_range_op(var_name, &range_start.to_string(), tokens)?;
+
+    // The end can be the number of values of a variable, `len(&var)`
+    let end_span = tokens.span();
+    let len = match tokens.peek_nth(0) {
+        Some(TokenTree::Ident(id)) if id.to_string() == "len" => {
+            tokens.next();
+            let span = tokens.span();
+            let var = match tokens.next() {
+                Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => g.stream().to_string().replace(' ', ""),
+                tt => return Err(Error::new(span, format!("akin: expected '(&var)' after 'let &{}={}..len', got {:?}", var_name, range_start, tt))),
+            };
+            let values = var.strip_prefix('&').and_then(|var| vars.get(&format!("*{var}")));
+            let values = values.ok_or_else(|| {
+                Error::new(span, format!("akin: '{}' in 'let &{}={}..len({})' isn't a declared variable", var, var_name, range_start, var))
+            })?;
+            Ok(values.len() as i128)
+        },
+        _ => parse_integer_literal(tokens),
+    };
+
+    let range_end = match len {
+        Ok(v) => v,
+        Err(e) => {
+            let got = tokens.peek_nth(0).map(|tt| format!(", got {} '{}'", e, tt)).unwrap_or_default();
+            return Err(Error::new(end_span, format!("akin: integer literal expected after 'let &{}={}..'{}", var_name, range_start, got)));
+        }
+    };
+
+    let len = range_end.saturating_sub(range_start).saturating_add(inclusive as i128).max(0);
+    check_values(usize::try_from(len).unwrap_or(usize::MAX), var_name, span, opts)?;
+    let last = Some(range_end).filter(|_| inclusive);
+    let iter = (range_start..range_end).chain(last).map(|i| format!("{i}{suffix}"));
+    Ok(iter.collect())
+}
+
+fn duplicate(stream: &str, vars: &Map<String, Vec<Value>>, opts: &Options, span: proc_macro::Span) -> Result<String, Error> {
+    // Each `*GENSYM` must be new, so code using it is never reused
+    let key = (!stream.contains("*GENSYM")).then(|| duplicate_key(stream, vars));
+    if let Some(key) = &key {
+        let cached = DUPLICATED.with(|cache| {
+            let cache = cache.borrow();
+            cache.get(stream)?.iter().find(|(k, _)| k == key).map(|(_, copies)| copies.clone())
+        });
+        if let Some(copies) = cached {
+            return Ok(copies);
+        }
+    }
+
+    let mut out = String::with_capacity(stream.len());
+    for_each_copy(stream, vars, opts, span, None, None, |copy| {
+        // The copies are separate tokens, so `{*x}` is `1 2` instead of `12`
+        if !out.is_empty() && !copy.starts_with(char::is_whitespace) {
+            out.push(' ');
+        }
+        out.push_str(copy);
+        Ok(())
+    })?;
+
+    if let Some(key) = key {
+        DUPLICATED.with(|cache| cache.borrow_mut().entry(stream.to_owned()).or_default().push((key, out.clone())));
+    }
+    Ok(out)
+}
+
+/// What [`duplicate`] depends on besides the code itself: the values and mismatch policy of each variable it names,
+/// fields included, like `*name.field`.
+///
+/// Names in `*{...}` expressions are written with a `*` too, so they're found the same way.
+/// Names that aren't declared have no entry, so declaring one later gives a different key.
+/// The values are shared, so comparing the ones of a variable that didn't change is only comparing pointers.
+type DuplicateKey = Vec<(String, Vec<Value>)>;
+
+fn duplicate_key(stream: &str, vars: &Map<String, Vec<Value>>) -> DuplicateKey {
+    let mut names: Vec<&str> = var_names(stream).filter(|name| !name.is_empty()).collect();
+    names.sort_unstable();
+    names.dedup();
+
+    let mut key = Vec::new();
+    for name in names {
+        for prefix in ["*", &format!("{MISMATCH}*")] {
+            let var = format!("{prefix}{name}");
+            let entries = vars.range(var.clone()..).take_while(|(k, _)| k.strip_prefix(&var).is_some_and(|rest| rest.is_empty() || rest.starts_with('.')));
+            key.extend(entries.map(|(k, values)| (k.clone(), values.clone())));
+        }
+    }
+    key
+}
+
+/// Returns the names written after each `*` in `stream`, like `name` for `*name.field`,
+/// which are empty if it isn't followed by one.
+fn var_names(stream: &str) -> impl Iterator<Item = &str> {
+    stream.match_indices('*').map(move |(start, _)| {
+        let rest = &stream[start + 1..];
+        &rest[..rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len())]
+    })
+}
+
+/// Bytes of copies an expansion must reach before they are written by several threads,
+/// since spawning them costs more than writing smaller ones.
+const PARALLEL_BYTES: usize = 1 << 20;
+
+/// Evaluates the text of a copy once its values are written, like [`eval_copy`].
+type Eval = fn(&str) -> Result<String, EvalError>;
+
+/// Same as [`duplicate`], but gives each copy of `stream` to `f` as soon as it's made, instead of writing them all to a `String`,
+/// and returns how many copies were made.
+///
+/// If `repeat` is given, it's used as the number of copies instead of the one given by the variables.
+/// If `eval` is given, each copy goes through it before `f`, in the thread that wrote it.
+/// Errors that can't be shown at a token of their own, like going over the limits, are shown at `span`.
+///
+/// Only the text is made by other threads: `f` is always called on this one,
+/// as the `proc_macro` API, which it uses to parse the copies and restore their spans, can't be used anywhere else.
+fn for_each_copy(
+    stream: &str,
+    vars: &Map<String, Vec<Value>>,
+    opts: &Options,
+    span: proc_macro::Span,
+    repeat: Option<usize>,
+    eval: Option<Eval>,
+    mut f: impl FnMut(&str) -> Result<(), Error>,
+) -> Result<usize, Error> {
+    let mut chunks = Chunk::new(stream).split_by_vars(vars);
+    for chunk in chunks.iter_mut().filter(|c| c.times() > 0) {
+        let policy = vars.get(&format!("{MISMATCH}{}", chunk.var)).and_then(|policy| policy.first());
+        chunk.mismatch = policy.and_then(|policy| Mismatch::parse(policy)).unwrap_or(opts.mismatch);
+    }
+
+    // In product mode, each variable is a dimension, ordered by its first use.
+    // Fields of the same record share their dimension, so they are still advanced together.
+    let mut dims: Vec<(&str, usize)> = Vec::new();
+    if opts.product {
+        for c in chunks.iter().filter(|c| c.times() > 0) {
+            match dims.iter_mut().find(|(var, _)| *var == c.var) {
+                Some((_, len)) => *len = c.times().max(*len),
+                None => dims.push((c.var, c.times())),
+            }
+        }
+    }
+
+    if !opts.product {
+        check_lengths(&chunks, span)?;
+    }
+
+    let times = if let Some(times) = repeat {
+        times
+    } else if opts.product {
+        dims.iter().try_fold(1usize, |times, (_, len)| times.checked_mul(*len)).unwrap_or(usize::MAX)
+    } else {
+        chunks.iter().map(|c| c.times()).max().unwrap_or(1).max(1)
+    };
+
+    // Checked before making anything, so a huge expansion fails right away instead of hanging the compiler
+    if times > opts.max_copies() {
+        return Err(Error::new(span, format!(
+            "akin: the code would be copied {} times, over the limit of {}; raise it with '#!max_copies(n)' if that's intended",
+            thousands(times), thousands(opts.max_copies())
+        )));
+    }
+    let bytes = chunks.iter().map(|c| c.total_len(times)).fold(0, usize::saturating_add);
+    if bytes > opts.max_bytes() {
+        return Err(Error::new(span, format!(
+            "akin: the copies would take {} bytes, over the limit of {}; raise it with '#!max_bytes(n)' if that's intended",
+            thousands(bytes), thousands(opts.max_bytes())
+        )));
+    }
+
+    let builtins = builtin_vars(stream, times);
+    for (name, values) in &builtins {
+        for i in (0..chunks.len()).rev() {
+            chunks.splice(i..=i, chunks[i].split_by_var(name, values));
+        }
+    }
+
+    // Values of each dimension, normalized to be compared with the excluded combinations.
+    let except_values: Vec<Vec<String>> = if opts.except.iter().any(|c| c.len() == dims.len()) {
+        dims.iter()
+            .map(|(var, _)| vars[*var].iter().map(|v| normalize_tokens(v)).collect())
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    // Writes the `i`-th copy to `out`, returning `false` if it's left out
+    let write_copy = |i: usize, out: &mut String| -> bool {
+        if !except_values.is_empty() {
+            let combination = dims
+                .iter()
+                .zip(&except_values)
+                .map(|((var, _), values)| values.get(product_index(i, var, &dims)));
+            if opts.except.iter().any(|c| combination.clone().eq(c.iter().map(Some))) {
+                return false;
+            }
+        }
+
+        out.clear();
+        let mut skipped = false;
+        let mut cfgs = Vec::new();
+        for chunk in &chunks {
+            let i = if opts.product { product_index(i, chunk.var, &dims) } else { i };
+            skipped |= chunk.push_to_string(i, out, &mut cfgs);
+        }
+        if skipped {
+            return false;
+        }
+
+        // The copy is gated by the predicates of the values it uses
+        cfgs.sort_unstable();
+        cfgs.dedup();
+        match cfgs.as_slice() {
+            [] => {},
+            [cfg] => out.insert_str(0, &format!("#[cfg({cfg})] ")),
+            cfgs => out.insert_str(0, &format!("#[cfg(all({}))] ", cfgs.join(", "))),
+        }
+        true
+    };
+
+    let threads = if bytes >= PARALLEL_BYTES {
+        std::thread::available_parallelism().map_or(1, |n| n.get()).min(times)
+    } else {
+        1
+    };
+
+    let mut copies = 0;
+    if threads <= 1 {
+        // Each copy is written to the same buffer, which can hold the longest one
+        let mut out = String::with_capacity(bytes / times.max(1));
+        for i in 0..times {
+            if write_copy(i, &mut out) {
+                copies += 1;
+                match eval {
+                    Some(eval) => f(&eval(&out).map_err(|e| e.into_error(span))?)?,
+                    None => f(&out)?,
+                }
+            }
+        }
+        return Ok(copies);
+    }
+
+    // Copies are independent, so big expansions are written by several threads at once.
+    // They are made in batches of about `PARALLEL_BYTES` per thread to bound the memory in use,
+    // and handed to `f` in order, since the token streams it makes can't leave this thread.
+    // Everything else, `eval` included, is done by the threads.
+    let per_thread = (PARALLEL_BYTES / (bytes / times).max(1)).max(1);
+    for batch in (0..times).step_by(per_thread * threads) {
+        let batch = batch..times.min(batch + per_thread * threads);
+        let written: Vec<Result<Vec<String>, EvalError>> = std::thread::scope(|s| {
+            let workers: Vec<_> = batch
+                .clone()
+                .step_by(per_thread)
+                .map(|start| {
+                    let range = start..batch.end.min(start + per_thread);
+                    s.spawn(|| {
+                        range
+                            .filter_map(|i| {
+                                let mut out = String::with_capacity(bytes / times);
+                                write_copy(i, &mut out).then(|| match eval {
+                                    Some(eval) => eval(&out),
+                                    None => Ok(out),
+                                })
+                            })
+                            .collect()
+                    })
+                })
+                .collect();
+            workers.into_iter().map(|w| w.join().unwrap_or_else(|e| std::panic::resume_unwind(e))).collect()
+        });
+        for written in written {
+            for copy in written.map_err(|e| e.into_error(span))? {
+                copies += 1;
+                f(&copy)?;
+            }
+        }
+    }
+
+    Ok(copies)
+}
+
+/// Returns an error at `span` if a variable with the `error` mismatch policy, like in `#!strict` mode, is used with another one of different length.
+/// Variables with a single value, like `{...}` code blocks, are reused in every copy and don't count.
+///
+/// With the `diagnostics` feature, the ones with the `last` policy are also recorded, to be noted by [`note_mismatches`].
+fn check_lengths(chunks: &[Chunk], span: proc_macro::Span) -> Result<(), Error> {
+    let used = || chunks.iter().filter(|c| c.times() > 1);
+    #[cfg(feature = "diagnostics")]
+    if let Some(longest) = used().max_by_key(|c| c.times()) {
+        for short in used().filter(|c| c.mismatch == Mismatch::Last && c.times() < longest.times()) {
+            let pair = (short.var[1..].to_string(), short.times(), longest.var[1..].to_string(), longest.times());
+            MISMATCHED.with(|mismatched| {
+                let mut mismatched = mismatched.borrow_mut();
+                if !mismatched.contains(&pair) {
+                    mismatched.push(pair);
+                }
+            });
+        }
+    }
+    for strict in used().filter(|c| c.mismatch == Mismatch::Error) {
+        if let Some(other) = used().find(|c| c.times() != strict.times()) {
+            let (a, b) = if other.times() > strict.times() { (other, strict) } else { (strict, other) };
+            return Err(Error::new(span, format!(
+                "akin: '&{}' has {} values but '&{}' has {}, all variables used together must have the same length with '#!strict' or 'mismatch(error)'",
+                &a.var[1..], a.times(), &b.var[1..], b.times()
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Returns a short hash of `text`, which is the same between compilations and toolchains.
+fn stable_hash(text: &str) -> String {
+    // 64-bit FNV-1a folded to 32 bits
+    let hash = text.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, b| (hash ^ b as u64).wrapping_mul(0x100_0000_01b3));
+    format!("{:08x}", (hash ^ (hash >> 32)) as u32)
+}
+
+/// Names of the built-in variables.
+const BUILTINS: &[&str] = &["*INDEX", "*RINDEX", "*COUNT", "*FIRST", "*LAST", "*GENSYM"];
+
+thread_local! {
+    /// Hash of the current invocation that starts the identifiers made by `*GENSYM`, and how many it has made so far.
+    static GENSYM: std::cell::RefCell<(String, usize)> = const { std::cell::RefCell::new((String::new(), 0)) };
+}
+
+/// Returns the values of the built-in variables used in `stream`, for a region copied `times` times.
+///
+/// They are substituted after the declared variables, so a variable with the same name takes precedence.
+fn builtin_vars(stream: &str, times: usize) -> Vec<(&'static str, Vec<Value>)> {
+    let mut builtins = Vec::new();
+    if stream.contains("*INDEX") {
+        builtins.push(("*INDEX", (0..times).map(|i| i.to_string().into()).collect()));
+    }
+    if stream.contains("*RINDEX") {
+        builtins.push(("*RINDEX", (0..times).rev().map(|i| i.to_string().into()).collect()));
+    }
+    if stream.contains("*COUNT") {
+        builtins.push(("*COUNT", vec![times.to_string().into()]));
+    }
+    if stream.contains("*FIRST") {
+        builtins.push(("*FIRST", (0..times).map(|i| (i == 0).to_string().into()).collect()));
+    }
+    if stream.contains("*LAST") {
+        builtins.push(("*LAST", (0..times).map(|i| (i + 1 == times).to_string().into()).collect()));
+    }
+    if stream.contains("*GENSYM") {
+        let names = GENSYM.with(|gensym| {
+            let (prefix, count) = &mut *gensym.borrow_mut();
+            *count += times;
+            (*count - times..*count).map(|n| format!("__akin_{prefix}_{n}").into()).collect()
+        });
+        builtins.push(("*GENSYM", names));
+    }
+    builtins
+}
+
+/// Returns the index of the value of `var` used in the `copy`-th combination of `dims`.
+///
+/// Variables that aren't part of `dims`, like the built-in ones, just follow the copies.
+fn product_index(copy: usize, var: &str, dims: &[(&str, usize)]) -> usize {
+    let mut stride = 1;
+    for (name, len) in dims.iter().rev() {
+        if *name == var {
+            return copy / stride % len;
+        }
+        stride *= len;
+    }
+    copy
+}
+
+/// Value of `SKIP`, which drops the whole copy it's used in.
+/// It can't appear in Rust code, so it can't be mistaken for a regular value.
+const SKIP: &str = "\u{6}SKIP";
+
+/// Encloses the predicate of a value gated by `#[cfg(...)]`, which is put before the value.
+const CFG: char = '\u{11}';
+
+/// Represents a substitution chunk. A fixed piece of text followed by 0 or more text variants.
+struct Chunk<'c> {
+    prefix: &'c str,
+    suffix_variants: &'c [Value],
+    /// Variable the variants come from, without the field of a record.
+    var: &'c str,
+    /// What the chunk writes once it runs out of variants.
+    mismatch: Mismatch,
+}
+
+impl<'c> Chunk<'c> {
+    /// Creates a chunk from a fixed piece of text.
+    fn new(prefix: &'c str) -> Self {
+        Chunk { prefix, suffix_variants: &[], var: "", mismatch: Mismatch::Last }
+    }
+
+    /// Writes the `i`-th variant of this chunk to `out`, returning `true` if the variant is `SKIP`.
+    ///
+    /// The predicate of a variant gated by `#[cfg(...)]` is pushed to `cfgs`.
+    fn push_to_string(&self, i: usize, out: &mut String, cfgs: &mut Vec<&'c str>) -> bool {
+        let Chunk { prefix, suffix_variants, mismatch, .. } = *self;
+        // A value ending in a dot, like the float `2.`, is kept apart from a `.` after it,
+        // which would otherwise be read together with it, like `2..max()` for `*x.max()`
+        if prefix.starts_with('.') && out.strip_suffix('.').is_some_and(|value| value.ends_with(|c: char| c.is_ascii_digit())) {
+            out.push(' ');
+        }
+        out.push_str(prefix);
+        let fallback = || match mismatch {
+            Mismatch::Cycle => suffix_variants.get(i % suffix_variants.len().max(1)),
+            Mismatch::Pad => None,
+            Mismatch::Last | Mismatch::Error => suffix_variants.last(),
+        };
+        let suffix = suffix_variants.get(i).or_else(fallback).map(|suffix| {
+            let Some(gated) = suffix.strip_prefix(CFG) else {
+                return &**suffix;
+            };
+            let (cfg, suffix) = gated.split_once(CFG).unwrap();
+            cfgs.push(cfg);
+            suffix
+        });
+        match suffix {
+            Some(SKIP) => true,
+            Some(suffix) => {
+                out.push_str(suffix);
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn times(&self) -> usize {
+        self.suffix_variants.len()
+    }
+
+    // Calculates the length of a string, that could hold `times` repetitions of this chunk.
+    fn total_len(&self, times: usize) -> usize {
+        let Chunk { prefix, suffix_variants, .. } = *self;
+        let mut total_len = prefix.len().saturating_mul(times);
+        if let Some(last) = suffix_variants.last() {
+            total_len = total_len.saturating_add(suffix_variants.iter().map(|s| s.len()).sum::<usize>());
+            total_len = total_len.saturating_add(last.len().saturating_mul(times.saturating_sub(suffix_variants.len())));
+        }
+        total_len
+    }
+
+    fn split_by_var<'s: 'c>(
+        &self,
+        var_name: &'s str,
+        var_values: &'s [Value],
+    ) -> impl Iterator<Item = Chunk<'c>> {
+        let Chunk { prefix, suffix_variants, var, mismatch } = *self;
+        let root = var_name.split('.').next().unwrap_or(var_name);
+
+        let mut text_start = 0usize;
+        let chopped = prefix
+            .match_indices(var_name)
+            .filter(move |(idx, v)| ends_var(prefix, idx + v.len()))
+            .map(move |(idx, v)| (idx, var_len(prefix, idx, v.len()), var_values, root, Mismatch::Last));
+        let chopped = chopped.chain(std::iter::once((prefix.len(), 0, suffix_variants, var, mismatch)));
+        chopped.map(move |(var_start, var_len, values, var, mismatch)| {
+            let new_prefix = &prefix[text_start..var_start];
+            text_start = var_start + var_len;
+            Chunk { prefix: new_prefix, suffix_variants: values, var, mismatch }
+        })
+    }
+
+    /// Splits this chunk by all the variables at once, scanning its text a single time.
+    ///
+    /// At each `*`, the longest variable name that matches a whole identifier is used,
+    /// so "*foobar" is substituted instead of "*foo", and "*foo_extra" is left as is if only `foo` is declared.
+    fn split_by_vars<'s: 'c>(
+        self,
+        vars: &'s Map<String, Vec<Value>>,
+    ) -> Vec<Chunk<'c>> {
+        let Chunk { prefix, suffix_variants, var, mismatch } = self;
+        let mut lens: Vec<usize> = vars.keys().map(String::len).collect();
+        lens.sort_unstable_by(|a, b| b.cmp(a));
+        lens.dedup();
+
+        let mut chunks = Vec::with_capacity(16);
+        let mut text_start = 0;
+        let mut i = 0;
+        while let Some(offset) = prefix[i..].find('*') {
+            let start = i + offset;
+            let found = lens
+                .iter()
+                .filter(|&&len| ends_var(prefix, start + len) && !calls_accessor(prefix, start, len))
+                .find_map(|&len| vars.get_key_value(prefix.get(start..start + len)?));
+            let Some((name, values)) = found else {
+                i = start + 1;
+                continue;
+            };
+            let root = name.split('.').next().unwrap_or(name);
+            chunks.push(Chunk { prefix: &prefix[text_start..start], suffix_variants: values, var: root, mismatch: Mismatch::Last });
+            text_start = start + var_len(prefix, start, name.len());
+            i = text_start;
+        }
+        chunks.push(Chunk { prefix: &prefix[text_start..], suffix_variants, var, mismatch });
+
+        chunks
+    }
+}
+
+/// Returns `true` if a variable name ending at `end` of `text` isn't part of a longer identifier, like `*foo` in `*foo_extra`.
+///
+/// A `~` right after it also ends it, as in string literals it can't be a joint modifier, like in `"*foo~_extra"`.
+fn ends_var(text: &str, end: usize) -> bool {
+    text.get(end..).is_some_and(|rest| rest.starts_with('~') || !rest.starts_with(|c: char| c.is_alphanumeric() || c == '_'))
+}
+
+/// Returns `true` if the variable name of `len` bytes at `start` of `text` ends with an accessor followed by `(`,
+/// like `*name.len()`, which calls the method on the value of `*name` instead.
+fn calls_accessor(text: &str, start: usize, len: usize) -> bool {
+    let name = &text[start..start + len];
+    name.rsplit_once('.').is_some_and(|(_, field)| ACCESSORS.contains(&field)) && text[start + len..].starts_with('(')
+}
+
+/// Length of the variable name of `len` bytes at `start` of `text`, along with the `~` that ends it, if any.
+fn var_len(text: &str, start: usize, len: usize) -> usize {
+    len + text[start + len..].starts_with('~') as usize
+}
+
+fn get_delimiters(delimiter: Delimiter) -> (char, char) {
+    match delimiter {
+        Delimiter::Parenthesis => ('(', ')'),
+        Delimiter::Brace => ('{', '}'),
+        Delimiter::Bracket => ('[', ']'),
+        Delimiter::None => ('\0', '\0'),
+    }
+}
+
+/// Folds a stream of tokens into `a`, expanding the `for &var in [...] {...}` loops
+/// and encoding the `@match` expressions it contains.
+fn fold_stream(
+    a: &mut String,
+    stream: impl IntoIterator<Item = TokenTree>,
+    prev: &mut Option<TokenTree>,
+    vars: &Map<String, Vec<Value>>,
+    opts: &Options,
+) -> Result<(), Error> {
+    let tts: Vec<TokenTree> = stream.into_iter().collect();
+    let mut i = 0;
+    while i < tts.len() {
+        if let Some(expanded) = fold_for_loop(&tts[i..], vars, opts)? {
+            a.push_str(&expanded);
+            *prev = None;
+            i += 6;
+        } else if let Some(encoded) = fold_doc(&tts[i], prev, opts) {
+            a.push_str(&encoded);
+            *prev = Some(tts[i].clone());
+            i += 1;
+        } else if let Some(expanded) = fold_scope(&tts[i], vars, opts)? {
+            a.push_str(&expanded);
+            *prev = None;
+            i += 1;
+        } else if let Some((encoded, len)) = fold_match(&tts[i..], vars, opts)? {
+            a.push_str(&encoded);
+            *prev = None;
+            i += len;
+        } else if closes_repetition(&tts[..=i]) {
+            // Never taken for a variable, so `akin!` can write macros with `$(...)*name` or `$(...)*{...}`
+            a.push(STAR);
+            *prev = Some(tts[i].clone());
+            i += 1;
+        } else if let Some((encoded, len)) = fold_modifier(&tts[i..], prev, vars, opts)? {
+            a.push_str(&encoded);
+            *prev = None;
+            i += len;
+        } else {
+            fold_tt(a, tts[i].clone(), prev, vars, opts)?;
+            i += 1;
+        }
+    }
+    Ok(())
+}
+
+/// Returns `true` if `tts` ends with the `*` of a `macro_rules!` repetition, like `$(...)*` or `$(...),*`.
+fn closes_repetition(tts: &[TokenTree]) -> bool {
+    let opens = |tts: &[TokenTree]| {
+        matches!(tts, [.., TokenTree::Punct(dollar), TokenTree::Group(g)] if dollar.as_char() == '$' && g.delimiter() == Delimiter::Parenthesis)
+    };
+    match tts {
+        [rest @ .., TokenTree::Punct(star)] if star.as_char() == '*' => {
+            // With a separator, like `,`, in between
+            opens(rest) || matches!(rest, [rest @ .., TokenTree::Punct(_) | TokenTree::Ident(_) | TokenTree::Literal(_)] if opens(rest))
+        },
+        _ => false,
+    }
+}
+
+/// Expands `for &var in [...] {...}` if `tts` starts with one, returning the body copied once per value of `var`.
+fn fold_for_loop(tts: &[TokenTree], vars: &Map<String, Vec<Value>>, opts: &Options) -> Result<Option<String>, Error> {
+    let [TokenTree::Ident(for_), TokenTree::Punct(amp), TokenTree::Ident(name), TokenTree::Ident(in_), TokenTree::Group(values), TokenTree::Group(body), ..] = tts else {
+        return Ok(None);
+    };
+    if for_.to_string() != "for"
+        || amp.as_char() != '&'
+        || in_.to_string() != "in"
+        || values.delimiter() != Delimiter::Bracket
+        || body.delimiter() != Delimiter::Brace
+    {
+        return Ok(None);
+    }
+
+    // Values are substituted later along with the rest of the template, so no variables are given here.
+    let declared = parse_group_values(format!("*{name}"), values, &Map::new(), opts)?;
+
+    // Variables of the outer scopes are kept as-is, so that they are substituted afterwards
+    // and a name like "*foobar" is never mistaken for the loop variable "*foo".
+    let mut scope = keep_vars(vars);
+    scope.extend(declared);
+
+    let mut fold = String::new();
+    let mut prev = None;
+    fold_stream(&mut fold, body.stream(), &mut prev, &scope, opts)?;
+    duplicate(&fold, &scope, opts, for_.span()).map(Some)
+}
+
+/// Maps every variable of `vars` to its own name, so they are kept as-is inside a scope.
+///
+/// Each name is followed by [`VAR_END`], so a value written right after it can't make it look like a longer name,
+/// like `*x` followed by the value of `*y` in `"*x*y"`.
+fn keep_vars(vars: &Map<String, Vec<Value>>) -> Map<String, Vec<Value>> {
+    vars.keys().map(|k| (k.clone(), vec![format!("{k}{VAR_END}").into()])).collect()
+}
+
+/// Expands `{ let &var = ...; ... }` if `tt` is a block that starts declaring variables,
+/// returning its code with the variables substituted and without the brackets.
+///
+/// Variables declared in the block are only visible inside it, and can shadow the outer ones.
+fn fold_scope(tt: &TokenTree, vars: &Map<String, Vec<Value>>, opts: &Options) -> Result<Option<String>, Error> {
+    let TokenTree::Group(block) = tt else {
+        return Ok(None);
+    };
+    if block.delimiter() != Delimiter::Brace {
+        return Ok(None);
+    }
+
+    let tts: Vec<TokenTree> = block.stream().into_iter().collect();
+    if !starts_declaration(&tts, vars) {
+        return Ok(None);
+    }
+    let mut tokens: Lookahead = tts.into_iter().collect::<proc_macro::TokenStream>().into_iter().into();
+
+    // Like in a `for` loop, the outer variables are kept as-is, both in the values and the code,
+    // so that they are substituted afterwards with the value of each outer copy.
+    let mut scope = keep_vars(vars);
+    parse_vars(&mut tokens, &mut scope, opts)?;
 
-fn parse_integer_literal(tokens: &mut Lookahead) -> Result<u64, &'static str> {
-    match tokens.peek_nth(0) {
-        Some(TokenTree::Literal(l)) => {
-            l.to_string().parse().map(|i| {
-                tokens.next();
-                i
-            }).map_err(|_| {
-                "non-integer literal"
-            })
+    let mut fold = String::new();
+    let mut prev = None;
+    fold_stream(&mut fold, tokens, &mut prev, &scope, opts)?;
+    duplicate(&fold, &scope, opts, block.span()).map(Some)
+}
+
+/// Returns `true` if `tts` starts with a declaration of variables, like `let &var = [...];`.
+///
+/// `let &x = r;` is also a Rust `let` with a reference pattern, so the block is only taken as a scope
+/// when the values can't be Rust code: a group, a range, `cumulative` or `pairs`, a value source like `csv(...)`,
+/// or an alias of a variable of `vars`, like `&var`.
+fn starts_declaration(tts: &[TokenTree], vars: &Map<String, Vec<Value>>) -> bool {
+    let [TokenTree::Ident(let_), TokenTree::Punct(amp), _, rest @ ..] = tts else {
+        return false;
+    };
+    if let_.to_string() != "let" || amp.as_char() != '&' {
+        return false;
+    }
+    // `+=` adds values to an outer variable
+    let rest = match rest {
+        [TokenTree::Punct(plus), rest @ ..] if plus.as_char() == '+' => rest,
+        _ => rest,
+    };
+    let [TokenTree::Punct(eq), values @ ..] = rest else {
+        return false;
+    };
+    if eq.as_char() != '=' {
+        return false;
+    }
+    match values {
+        [TokenTree::Group(g), ..] => matches!(g.delimiter(), Delimiter::Bracket | Delimiter::Brace),
+        [TokenTree::Literal(_), ..] => true,
+        [TokenTree::Punct(p), ..] if p.as_char() == '-' => true,
+        [TokenTree::Ident(id), ..] if matches!(id.to_string().as_str(), "cumulative" | "pairs") => true,
+        [TokenTree::Ident(source), TokenTree::Group(g), ..] => {
+            SOURCES.contains(&source.to_string().as_str()) && g.delimiter() != Delimiter::Bracket
         },
-        Some(_) => Err("non-literal token"),
-        None => Err("unexpected end of input"),
+        [TokenTree::Punct(p), TokenTree::Ident(of), ..] if p.as_char() == '&' => vars.contains_key(&format!("*{of}")),
+        _ => false,
     }
 }
 
-fn parse_range_expr(
-    var_name: &str,
-    tokens: &mut Lookahead,
-) -> Vec<String> {
-    let range_start = match parse_integer_literal(tokens) {
-        Ok(v) => v,
-        Err(e) => {
-            panic!(
-                "akin: integer literal expected after 'let &{}='{}",
-                var_name, tokens.peek_nth(0).map(|tt| format!(", got {} '{}'", e, tt)).unwrap_or_default()
-            );
-        }
+// Markers used to encode an `@match` in the folded text, until it can be evaluated.
+// They can't appear in Rust code, so they survive the substitution untouched.
+const MATCH_START: char = '\u{1}';
+const MATCH_ARM: char = '\u{2}';
+const MATCH_BODY: char = '\u{3}';
+const MATCH_OR: char = '\u{5}';
+const MATCH_END: char = '\u{4}';
+
+/// Encodes `@match scrutinee { pattern | pattern => {...}, _ => ... }` if `tts` starts with one,
+/// returning it along with the number of tokens consumed.
+///
+/// The match is evaluated by [`eval_matches`] once all variables have been substituted.
+fn fold_match(tts: &[TokenTree], vars: &Map<String, Vec<Value>>, opts: &Options) -> Result<Option<(String, usize)>, Error> {
+    let [TokenTree::Punct(at), TokenTree::Ident(match_), rest @ ..] = tts else {
+        return Ok(None);
     };
+    if at.as_char() != '@' || match_.to_string() != "match" {
+        return Ok(None);
+    }
 
-    let inclusive = match (tokens.next(), tokens.next(), tokens.peek_nth(0)) {
-        (Some(TokenTree::Punct(p1)), Some(TokenTree::Punct(p2)), p3) if p1.spacing() == Spacing::Joint && (p1.as_char(), p2.as_char()) == ('.', '.') => {
-            p2.spacing() == Spacing::Joint && matches!(p3, Some(TokenTree::Punct(p3)) if p3.as_char() == '=')
-        },
-        _ => {
-            panic!( "akin: expected '..' or '..=' after 'let &{}={}'", var_name, range_start);
-        },
+    let Some(arms_pos) = rest.iter().position(|tt| matches!(tt, TokenTree::Group(g) if g.delimiter() == Delimiter::Brace)) else {
+        return Err(Error::new(match_.span(), "akin: expected '{' with the arms of '@match'"));
     };
+    let TokenTree::Group(arms) = &rest[arms_pos] else { unreachable!() };
 
-    if inclusive {
-        tokens.next(); // drop the '=' in '..='
+    let mut encoded = String::from(MATCH_START);
+    let mut prev = None;
+    fold_stream(&mut encoded, rest[..arms_pos].iter().cloned(), &mut prev, vars, opts)?;
+
+    let arms: Vec<TokenTree> = arms.stream().into_iter().collect();
+    let mut i = 0;
+    while i < arms.len() {
+        let arrow = (i..arms.len()).find(|&j| {
+            matches!((&arms[j], arms.get(j + 1)), (TokenTree::Punct(eq), Some(TokenTree::Punct(gt)))
+                if eq.as_char() == '=' && eq.spacing() == Spacing::Joint && gt.as_char() == '>')
+        });
+        let Some(arrow) = arrow else {
+            return Err(Error::new(arms[i].span(), "akin: expected '=>' after '@match' pattern"));
+        };
+
+        encoded.push(MATCH_ARM);
+        for (n, alt) in arms[i..arrow].split(|tt| matches!(tt, TokenTree::Punct(p) if p.as_char() == '|')).enumerate() {
+            if n > 0 {
+                encoded.push(MATCH_OR);
+            }
+            let mut prev = None;
+            fold_stream(&mut encoded, alt.iter().cloned(), &mut prev, vars, opts)?;
+        }
+
+        encoded.push(MATCH_BODY);
+        let body_start = arrow + 2;
+        let body_end = match arms.get(body_start) {
+            Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => {
+                let mut prev = None;
+                fold_stream(&mut encoded, g.stream(), &mut prev, vars, opts)?;
+                body_start + 1
+            }
+            _ => {
+                let end = arms[body_start..]
+                    .iter()
+                    .position(|tt| matches!(tt, TokenTree::Punct(p) if p.as_char() == ','))
+                    .map_or(arms.len(), |p| body_start + p);
+                let mut prev = None;
+                fold_stream(&mut encoded, arms[body_start..end].iter().cloned(), &mut prev, vars, opts)?;
+                end
+            }
+        };
+
+        i = body_end;
+        if matches!(arms.get(i), Some(TokenTree::Punct(p)) if p.as_char() == ',') {
+            i += 1;
+        }
     }
+    encoded.push(MATCH_END);
 
-    let range_end = match parse_integer_literal(tokens) {
-        Ok(v) => v,
-        Err(e) => {
-            panic!(
-                "akin: integer literal expected after 'let &{}={}..'{}",
-                var_name, range_start, tokens.peek_nth(0).map(|tt| format!(", got {} '{}'", e, tt)).unwrap_or_default()
-            );
+    Ok(Some((encoded, arms_pos + 3)))
+}
+
+/// Evaluates the `@match` expressions encoded by [`fold_match`], keeping the body of the first arm
+/// whose pattern is equal to the scrutinee. If no arm matches, nothing is written.
+fn eval_matches(text: &str) -> Result<String, EvalError> {
+    if !text.contains(MATCH_START) {
+        return Ok(text.to_owned());
+    }
+
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(MATCH_START) {
+        out.push_str(&rest[..start]);
+        let end = start + matching_end(&rest[start..], MATCH_START, MATCH_END)?;
+        let mut parts = split_top(&rest[start + 1..end], MATCH_ARM).into_iter();
+        let scrutinee = normalize_tokens(&eval_matches(parts.next().unwrap_or_default())?);
+
+        for arm in parts {
+            let (pattern, body) = arm.split_once(MATCH_BODY).unwrap_or((arm, ""));
+            let mut matched = false;
+            for alt in split_top(pattern, MATCH_OR) {
+                let alt = normalize_tokens(&eval_matches(alt)?);
+                if alt == "_" || alt == scrutinee {
+                    matched = true;
+                    break;
+                }
+            }
+            if matched {
+                out.push_str(&eval_matches(body)?);
+                break;
+            }
         }
-    };
 
-    if !matches!(tokens.next(), Some(TokenTree::Punct(p)) if p.as_char() == ';') {
-        panic!( "akin: expected ';' on end of '&{}' declaration", var_name);
+        rest = &rest[end + 1..];
     }
+    out.push_str(rest);
+    Ok(out)
+}
 
-    let last = Some(range_end).filter(|_| inclusive);
-    let iter = (range_start..range_end).chain(last).map(|i| i.to_string());
-    iter.collect()
+/// Returns the position of the `close` marker that closes the `open` one at the beginning of `text`.
+fn matching_end(text: &str, open: char, close: char) -> Result<usize, EvalError> {
+    let mut depth = 0;
+    for (i, c) in text.char_indices() {
+        if c == open {
+            depth += 1;
+        } else if c == close {
+            depth -= 1;
+            if depth == 0 {
+                return Ok(i);
+            }
+        }
+    }
+    Err(EvalError { message: format!("akin: internal bug, unterminated marker {:?}", open), marker: None })
+}
+
+/// Splits `text` by `sep`, ignoring the separators inside nested `@match` expressions.
+fn split_top(text: &str, sep: char) -> Vec<&str> {
+    let mut parts = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        match c {
+            MATCH_START => depth += 1,
+            MATCH_END => depth -= 1,
+            c if c == sep && depth == 0 => {
+                parts.push(&text[start..i]);
+                start = i + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    parts.push(&text[start..]);
+    parts
 }
 
-fn duplicate(stream: &str, vars: &Map<String, Vec<String>>) -> String {
-    let chunks = Chunk::new(stream).split_by_vars(vars);
+// Markers used to encode a variable with a modifier, `*var:modifier`, until it can be applied.
+const MODIFIER_START: char = '\u{7}';
+const MODIFIER_VALUE: char = '\u{8}';
+const MODIFIER_END: char = '\u{e}';
 
-    let times = chunks.iter().map(|c| c.times()).max().unwrap_or(1).max(1);
+/// Names of the modifiers that take arguments, `*var:modifier(args)`.
+/// The others never do, so a group after them is left as is, like in `Enum::*var:capitalize(value)`.
+const MODIFIERS_WITH_ARGS: &[&str] = &["pad", "strip_prefix", "strip_suffix", "join", "hex", "bin"];
 
-    let total_len = chunks.iter().map(|c| c.total_len(times)).sum();
+/// Names of the modifiers that can be applied with `*var:modifier`.
+const MODIFIERS: &[&str] = &["str", "pad", "strip_prefix", "strip_suffix", "join", "upper", "lower", "snake", "camel", "capitalize", "hex", "bin", "ordinal", "words", "hash"];
 
-    let mut out = String::with_capacity(total_len);
+/// Returns `true` if the token at `right` is written right after the one at `left`, without any space in between.
+fn adjacent(left: proc_macro::Span, right: proc_macro::Span) -> bool {
+    let (end, start) = (left.end(), right.start());
+    (end.line(), end.column()) == (start.line(), start.column())
+}
 
-    for i in 0..times {
-        for chunk in &chunks {
-            chunk.push_to_string(i, &mut out);
+/// Encodes `*var:modifier` or `*var:modifier(args)` if `tts` starts with one,
+/// returning it along with the number of tokens consumed.
+///
+/// The indexed access `*var[i]` is also handled here, as it's written directly.
+///
+/// The modifier is applied by [`eval_modifiers`] once all variables have been substituted.
+fn fold_modifier(
+    tts: &[TokenTree],
+    prev: &Option<TokenTree>,
+    vars: &Map<String, Vec<Value>>,
+    opts: &Options,
+) -> Result<Option<(String, usize)>, Error> {
+    if let [TokenTree::Punct(star), TokenTree::Group(expr), ..] = tts {
+        if star.as_char() == opts.sigil() && expr.delimiter() == Delimiter::Brace {
+            return Ok(Some((fold_expression(expr, prev, vars, opts)?, 2)));
         }
     }
 
-    out
+    if !matches!(tts, [TokenTree::Punct(star), TokenTree::Ident(_), ..] if star.as_char() == opts.sigil()) {
+        return Ok(None);
+    }
+
+    // '*GENSYM(tag)' is the identifier of '*GENSYM' followed by the tag, '__akin_5e0c12a4_0_tag'
+    if let [_, TokenTree::Ident(gensym), TokenTree::Group(tag), ..] = tts {
+        if gensym.to_string() == "GENSYM" && tag.delimiter() == Delimiter::Parenthesis && !vars.contains_key("*GENSYM") {
+            let space = if needs_space(prev, opts) { " " } else { "" };
+            let tag = tag.stream().to_string();
+            return Ok(Some((format!("{space}*GENSYM{JOINT}_{JOINT}{}", tag.trim()), 3)));
+        }
+    }
+
+    // The variable can be a field of a record, '*var.field'
+    let mut len = 2;
+    while matches!(&tts[len..], [TokenTree::Punct(dot), TokenTree::Ident(_), ..] if dot.as_char() == '.') {
+        len += 2;
+    }
+    let name: String = std::iter::once("*".to_owned()).chain(tts[1..len].iter().map(|tt| tt.to_string())).collect();
+
+    let mut encoded = String::new();
+    if needs_space(prev, opts) {
+        encoded.push(' ');
+    }
+
+    // Indexed access, '*var.[i]', which is the same in every copy.
+    // A '.' can't be followed by '[' in Rust, so unlike '*var[i]' it never indexes into the value itself.
+    if let [TokenTree::Punct(dot), TokenTree::Group(index), ..] = &tts[len..] {
+        if let (true, Delimiter::Bracket, Some(values)) = (dot.as_char() == '.', index.delimiter(), vars.get(&name)) {
+            if let Ok(i) = index.stream().to_string().parse::<usize>() {
+                let Some(value) = values.get(i) else {
+                    let message = format!("akin: index {} is out of bounds in '{}.[{}]', '&{}' has {} values", i, name, i, &name[1..], values.len());
+                    return Err(Error::new(index.span(), message));
+                };
+                encoded.push_str(value.trim());
+                return Ok(Some((encoded, len + 2)));
+            }
+        }
+    }
+
+    // Modifiers are applied from left to right, so each one wraps the ones before it
+    let mut modifiers: Vec<(String, String, proc_macro::Span)> = Vec::new();
+    let mut consumed = len;
+    while let [TokenTree::Punct(colon), TokenTree::Ident(modifier_ident), rest @ ..] = &tts[consumed..] {
+        let modifier = modifier_ident.to_string();
+        if colon.as_char() != ':' || colon.spacing() != Spacing::Alone {
+            break;
+        }
+        // The first one is written right after the variable, '*var:lower', as '*field: lower' is a struct field instead
+        let joint = adjacent(tts[consumed - 1].span(), colon.span()) && adjacent(colon.span(), modifier_ident.span());
+        if modifiers.is_empty() && !joint {
+            break;
+        }
+        if !MODIFIERS.contains(&modifier.as_str()) {
+            // Without a modifier right before, it's regular code like a struct field, '*field:u32'
+            if modifiers.is_empty() || !adjacent(colon.span(), modifier_ident.span()) {
+                break;
+            }
+            let message = format!("akin: unknown modifier ':{}' after '{}', expected one of: {}", modifier, name, MODIFIERS.join(", "));
+            return Err(Error::new(modifier_ident.span(), message));
+        }
+
+        consumed += 2;
+        let mut args = String::new();
+        if let Some(TokenTree::Group(group)) = rest.first().filter(|tt| matches!(tt, TokenTree::Group(g) if g.delimiter() == Delimiter::Parenthesis)) {
+            if MODIFIERS_WITH_ARGS.contains(&modifier.as_str()) {
+                let mut prev = None;
+                fold_stream(&mut args, group.stream(), &mut prev, vars, opts)?;
+                consumed += 1;
+            }
+        }
+        modifiers.push((modifier, args, modifier_ident.span()));
+    }
+    if modifiers.is_empty() {
+        return Ok(None);
+    }
+
+    let mut value = String::new();
+    let mut prev = None;
+    fold_stream(&mut value, tts[..len].iter().cloned(), &mut prev, vars, opts)?;
+    let (mut before, mut after) = (String::new(), String::new());
+    let mut joined = false;
+    for (modifier, args, span) in modifiers {
+        // All the values are written at once, so it can't wait until the copies are made.
+        // The modifiers before it are applied to each value, and the ones after it to the result.
+        if modifier == "join" {
+            let Some(values) = vars.get(&name).filter(|_| !joined) else {
+                return Err(Error::new(span, format!("akin: ':join' can only be used once, on a declared variable, and '{}' is not", name)));
+            };
+            let args = args.trim();
+            let separator = args.strip_prefix('"').and_then(|a| a.strip_suffix('"')).unwrap_or(args);
+            let values: Vec<String> = values
+                .iter()
+                .map(|v| v.trim())
+                .filter(|v| !v.is_empty() && *v != SKIP)
+                .map(|v| format!("{before}{v}{after}"))
+                .collect();
+            value = values.join(separator);
+            before.clear();
+            after.clear();
+            joined = true;
+            continue;
+        }
+
+        // Its errors are shown at the name of the modifier
+        let args = if args.is_empty() { String::new() } else { format!("({args})") };
+        before = format!("{MODIFIER_START}{}{modifier}{args}{MODIFIER_VALUE}{before}", mark_span(span));
+        after.push(MODIFIER_END);
+    }
+
+    encoded.push_str(&before);
+    encoded.push_str(&value);
+    encoded.push_str(&after);
+    Ok(Some((encoded, consumed)))
 }
 
-/// Represents a substitution chunk. A fixed piece of text followed by 0 or more text variants.
-struct Chunk<'c> {
-    prefix: &'c str,
-    suffix_variants: &'c [String],
+/// Encodes the arithmetic expression `*{...}`, in which variables are written without `*`.
+///
+/// It's encoded as a modifier without name, and evaluated by [`eval_expression`].
+fn fold_expression(
+    expr: &proc_macro::Group,
+    prev: &Option<TokenTree>,
+    vars: &Map<String, Vec<Value>>,
+    opts: &Options,
+) -> Result<String, Error> {
+    fn deref_vars(stream: proc_macro::TokenStream, vars: &Map<String, Vec<Value>>, sigil: char) -> Vec<TokenTree> {
+        let mut tts = Vec::new();
+        for tt in stream {
+            match tt {
+                TokenTree::Group(g) => {
+                    let mut group = proc_macro::Group::new(g.delimiter(), deref_vars(g.stream(), vars, sigil).into_iter().collect());
+                    group.set_span(g.span());
+                    tts.push(TokenTree::Group(group));
+                }
+                TokenTree::Ident(id) if is_var(&format!("*{id}"), vars) => {
+                    tts.push(TokenTree::Punct(proc_macro::Punct::new(sigil, Spacing::Alone)));
+                    tts.push(TokenTree::Ident(id));
+                }
+                tt => tts.push(tt),
+            }
+        }
+        tts
+    }
+
+    let mut encoded = String::new();
+    if needs_space(prev, opts) {
+        encoded.push(' ');
+    }
+    // Its errors are shown at the whole expression
+    write!(encoded, "{MODIFIER_START}{}{MODIFIER_VALUE}", mark_span(expr.span())).unwrap();
+    let mut prev = None;
+    fold_stream(&mut encoded, deref_vars(expr.stream(), vars, opts.sigil()), &mut prev, vars, opts)?;
+    encoded.push(MODIFIER_END);
+    Ok(encoded)
 }
 
-impl<'c> Chunk<'c> {
-    /// Creates a chunk from a fixed piece of text.
-    fn new(prefix: &'c str) -> Self {
-        Chunk { prefix, suffix_variants: &[] }
+/// Returns `true` if a token written after `prev` must be separated from it by a space.
+fn needs_space(prev: &Option<TokenTree>, opts: &Options) -> bool {
+    // Case '*' => To make variable formatting simpler ('*var' instead of '* var')
+    // Case '~' => Behaviour of the '~' modifier
+    // Case '.' => Method calls and fields ('.pow(2)' instead of '. pow(2)')
+    match prev {
+        Some(TokenTree::Punct(p)) => !(p.spacing() == Spacing::Joint || matches!(p.as_char(), '*' | '~' | '.') || p.as_char() == opts.sigil()),
+        // The start of a group, see 'group_start'
+        Some(TokenTree::Group(g)) => !(g.delimiter() == Delimiter::None && g.stream().is_empty()),
+        _ => true,
     }
+}
 
-    fn push_to_string(&self, i: usize, out: &mut String) {
-        let Chunk { prefix, suffix_variants } = *self;
-        out.push_str(prefix);
-        if let Some(suffix) = suffix_variants.get(i).or_else(|| suffix_variants.last()) {
-            out.push_str(suffix);
+/// Returns `true` if `name` is a declared or built-in variable.
+fn is_var(name: &str, vars: &Map<String, Vec<Value>>) -> bool {
+    vars.contains_key(name) || BUILTINS.contains(&name)
+}
+
+/// Evaluates an integer arithmetic expression, like `(3 + 1) * 2`.
+///
+/// Supports `+ - * / %`, the bitwise operators `& | ^ << >>`, unary `-` and parentheses.
+fn eval_expression(expr: &str) -> Result<String, String> {
+    fn binary(tts: &[TokenTree], pos: &mut usize, level: usize) -> Result<i128, String> {
+        const LEVELS: &[&[&str]] = &[&["|"], &["^"], &["&"], &["<<", ">>"], &["+", "-"], &["*", "/", "%"]];
+        if level == LEVELS.len() {
+            return unary(tts, pos);
+        }
+
+        let mut lhs = binary(tts, pos, level + 1)?;
+        loop {
+            let op = match (tts.get(*pos), tts.get(*pos + 1)) {
+                (Some(TokenTree::Punct(a)), Some(TokenTree::Punct(b))) if a.spacing() == Spacing::Joint && a.as_char() == b.as_char() && matches!(a.as_char(), '<' | '>') => {
+                    format!("{a}{b}")
+                }
+                (Some(TokenTree::Punct(a)), _) => a.to_string(),
+                _ => return Ok(lhs),
+            };
+            if !LEVELS[level].contains(&op.as_str()) {
+                return Ok(lhs);
+            }
+            *pos += op.len();
+
+            let rhs = binary(tts, pos, level + 1)?;
+            lhs = match op.as_str() {
+                "|" => lhs | rhs,
+                "^" => lhs ^ rhs,
+                "&" => lhs & rhs,
+                "<<" => lhs.checked_shl(rhs as u32).ok_or("shift overflow")?,
+                ">>" => lhs.checked_shr(rhs as u32).ok_or("shift overflow")?,
+                "+" => lhs.checked_add(rhs).ok_or("overflow")?,
+                "-" => lhs.checked_sub(rhs).ok_or("overflow")?,
+                "*" => lhs.checked_mul(rhs).ok_or("overflow")?,
+                "/" => lhs.checked_div(rhs).ok_or("division by zero")?,
+                _ => lhs.checked_rem(rhs).ok_or("division by zero")?,
+            };
         }
     }
 
-    fn times(&self) -> usize {
-        self.suffix_variants.len()
+    fn unary(tts: &[TokenTree], pos: &mut usize) -> Result<i128, String> {
+        let tt = tts.get(*pos).ok_or("expected a number")?;
+        *pos += 1;
+        match tt {
+            TokenTree::Punct(p) if p.as_char() == '-' => Ok(-unary(tts, pos)?),
+            TokenTree::Group(g) if g.delimiter() == Delimiter::Parenthesis => {
+                let inner: Vec<TokenTree> = g.stream().into_iter().collect();
+                let mut inner_pos = 0;
+                let value = binary(&inner, &mut inner_pos, 0)?;
+                match inner.get(inner_pos) {
+                    None => Ok(value),
+                    Some(tt) => Err(format!("unexpected '{tt}'")),
+                }
+            }
+            TokenTree::Literal(l) => parse_int(&l.to_string()).ok_or_else(|| format!("'{l}' is not an integer")),
+            tt => Err(format!("unexpected '{tt}'")),
+        }
     }
 
-    // Calculates the length of a string, that could hold `times` repetitions of this chunk.
-    fn total_len(&self, times: usize) -> usize {
-        let Chunk { prefix, suffix_variants } = *self;
-        let mut total_len = prefix.len() * times;
-        if let Some(last) = suffix_variants.last() {
-            total_len += suffix_variants.iter().map(|s| s.len()).sum::<usize>();
-            total_len += last.len() * times.saturating_sub(suffix_variants.len());
+    let result = expr.parse::<proc_macro::TokenStream>().map_err(|e| e.to_string()).and_then(|stream| {
+        let tts: Vec<TokenTree> = stream.into_iter().collect();
+        let mut pos = 0;
+        let value = binary(&tts, &mut pos, 0)?;
+        match tts.get(pos) {
+            None => Ok(value),
+            Some(tt) => Err(format!("unexpected '{tt}'")),
         }
-        total_len
+    });
+
+    match result {
+        Ok(value) => Ok(value.to_string()),
+        Err(e) => Err(format!("akin: can't evaluate '*{{{}}}': {}", expr.trim(), e)),
     }
+}
 
-    fn split_by_var<'s: 'c>(
-        &self,
-        var_name: &'s str,
-        var_values: &'s [String],
-    ) -> impl Iterator<Item = Chunk<'c>> {
-        let Chunk { prefix, suffix_variants } = *self;
+/// Parses an integer literal, in any base and with an optional sign and type suffix, like `-0x1Fu8`.
+fn parse_int(literal: &str) -> Option<i128> {
+    let literal = literal.replace('_', "");
+    let (negative, literal) = match literal.strip_prefix('-') {
+        Some(literal) => (true, literal.trim_start()),
+        None => (false, literal.as_str()),
+    };
+    let digits = strip_int_suffix(literal);
+    let parsed = match digits.get(..2) {
+        Some("0x") => i128::from_str_radix(&digits[2..], 16),
+        Some("0o") => i128::from_str_radix(&digits[2..], 8),
+        Some("0b") => i128::from_str_radix(&digits[2..], 2),
+        _ => digits.parse(),
+    };
+    parsed.ok().map(|n| if negative { -n } else { n })
+}
 
-        let mut text_start = 0usize;
-        let chopped = prefix.match_indices(var_name).map(move |(idx, v)| (idx, v.len(), var_values));
-        let chopped = chopped.chain(std::iter::once((prefix.len(), 0, suffix_variants)));
-        let chopped = chopped.map(move |(var_start, var_len, values)| {
-            let new_prefix = &prefix[text_start..var_start];
-            text_start = var_start + var_len;
-            Chunk { prefix: new_prefix, suffix_variants: values }
-        });
-        chopped
+/// Removes the type suffix of an integer literal, like the `u8` in `7u8`.
+fn strip_int_suffix(literal: &str) -> &str {
+    for suffix in ["u8", "u16", "u32", "u64", "u128", "usize", "i8", "i16", "i32", "i64", "i128", "isize"] {
+        if let Some(digits) = literal.strip_suffix(suffix) {
+            return digits;
+        }
     }
+    literal
+}
 
-    fn split_by_vars<'s: 'c>(
-        self,
-        vars: &'s Map<String, Vec<String>>,
-    ) -> Vec<Chunk<'c>> {
-        let mut chunks = Vec::with_capacity(16);
-        chunks.push(self);
+/// Returns the type suffix of an integer literal, like the `u8` in `7u8`, or an empty string if it has none.
+fn int_suffix(literal: &str) -> &str {
+    &literal[strip_int_suffix(literal).len()..]
+}
+
+/// Encodes the contents of a doc comment, `#[doc = "..."]`, so the values written in it are escaped.
+///
+/// It's encoded as the internal modifier `doc`, which writes its value back as a string literal.
+fn fold_doc(tt: &TokenTree, prev: &Option<TokenTree>, opts: &Options) -> Option<String> {
+    let TokenTree::Group(group) = tt else {
+        return None;
+    };
+    let tts: Vec<TokenTree> = group.stream().into_iter().collect();
+    let [TokenTree::Ident(doc), TokenTree::Punct(eq), TokenTree::Literal(text)] = tts.as_slice() else {
+        return None;
+    };
+    if group.delimiter() != Delimiter::Bracket || doc.to_string() != "doc" || eq.as_char() != '=' {
+        return None;
+    }
+    let text = unescape_str(&text.to_string())?;
+
+    let space = if needs_space(prev, opts) { " " } else { "" };
+    Some(format!("{space}[doc = {MODIFIER_START}doc{MODIFIER_VALUE}{text}{MODIFIER_END}]"))
+}
 
-        // Iterate over vars in reverse lexicographical order,
-        // so that "*foobar" has a chance to get substituted before "*foo".
-        for (name, values) in vars.iter().rev() {
-            // Iterate over chunks in reverse order, so that newly inserted chunks
-            // don't get processed more than once for the same variable.
-            for i in (0..chunks.len()).rev() {
-                chunks.splice(i..=i, chunks[i].split_by_var(name, values));
+/// Returns the contents of a string literal `"..."`, or `None` if `literal` is another kind of literal.
+fn unescape_str(literal: &str) -> Option<String> {
+    let literal = literal.strip_prefix('"')?.strip_suffix('"')?;
+    let mut out = String::with_capacity(literal.len());
+    let mut chars = literal.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next()? {
+            'n' => out.push('\n'),
+            'r' => out.push('\r'),
+            't' => out.push('\t'),
+            '0' => out.push('\0'),
+            'x' => {
+                let hex: String = chars.by_ref().take(2).collect();
+                out.push(u8::from_str_radix(&hex, 16).ok()? as char);
+            }
+            'u' => {
+                let hex: String = chars.by_ref().skip(1).take_while(|&c| c != '}').collect();
+                out.push(char::from_u32(u32::from_str_radix(&hex, 16).ok()?)?);
             }
+            '\n' => {
+                // Line continuation, skips the leading whitespace of the next line
+                let rest = chars.as_str().trim_start();
+                chars = rest.chars();
+            }
+            c => out.push(c),
         }
+    }
+    Some(out)
+}
 
-        chunks
+/// Applies the modifiers encoded by [`fold_modifier`] to the values they enclose, from the innermost out.
+///
+/// The number written after the start of a marker is the one of its span, see [`mark_span`].
+fn eval_modifiers(text: &str) -> Result<String, EvalError> {
+    if !text.contains(MODIFIER_START) {
+        return Ok(text.to_owned());
+    }
+
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find(MODIFIER_START) {
+        out.push_str(&rest[..start]);
+        let end = start + matching_end(&rest[start..], MODIFIER_START, MODIFIER_END)?;
+        let marker = &rest[start + 1..end];
+        let digits = marker.find(|c: char| !c.is_ascii_digit()).unwrap_or(marker.len());
+        let (modifier, value) = marker[digits..].split_once(MODIFIER_VALUE).unwrap_or_default();
+        let (name, args) = match modifier.split_once('(') {
+            Some((name, args)) => (name, args.strip_suffix(')').unwrap_or(args)),
+            None => (modifier, ""),
+        };
+        let value = eval_modifiers(value)?;
+        // The spaces of a doc comment are part of its text
+        let value = if name == "doc" { &value } else { value.trim() };
+        let applied = apply_modifier(name, args.trim(), value).map_err(|message| EvalError { message, marker: marker[..digits].parse().ok() })?;
+        out.push_str(&applied);
+        rest = &rest[end + 1..];
     }
+    out.push_str(rest);
+    Ok(out)
 }
 
-fn get_delimiters(delimiter: Delimiter) -> (char, char) {
-    match delimiter {
-        Delimiter::Parenthesis => ('(', ')'),
-        Delimiter::Brace => ('{', '}'),
-        Delimiter::Bracket => ('[', ']'),
-        Delimiter::None => ('\0', '\0'),
+/// Applies the modifier `name`, one of [`MODIFIERS`] or an internal one, to `value`.
+fn apply_modifier(name: &str, args: &str, value: &str) -> Result<String, String> {
+    let applied = match name {
+        // The case of a raw identifier is changed without its `r#`, which is kept only if it's still a keyword
+        "capitalize" | "upper" | "lower" | "snake" | "camel" if value.starts_with("r#") => {
+            let changed = apply_modifier(name, args, &value[2..])?;
+            if RAW_KEYWORDS.contains(&changed.as_str()) {
+                format!("r#{changed}")
+            } else {
+                changed
+            }
+        }
+        "" => eval_expression(value)?,
+        "str" | "doc" => proc_macro::Literal::string(value).to_string(),
+        "pad" => {
+            let width: usize = args.parse().map_err(|_| format!("akin: expected width in ':pad(n)', got '{}'", args))?;
+            match value.parse::<i128>() {
+                Ok(n) => format!("{n:0width$}"),
+                Err(_) => format!("{value:0>width$}"),
+            }
+        }
+        "strip_prefix" => value.strip_prefix(args.trim_matches('"')).unwrap_or(value).to_owned(),
+        "strip_suffix" => value.strip_suffix(args.trim_matches('"')).unwrap_or(value).to_owned(),
+        "hex" | "bin" => {
+            let Some(n) = parse_int(value) else {
+                return Err(format!("akin: ':{}' can only be used on integers, got '{}'", name, value));
+            };
+            let width: usize = if args.is_empty() {
+                0
+            } else {
+                args.parse().map_err(|_| format!("akin: expected number of digits in ':{}(n)', got '{}'", name, args))?
+            };
+            let sign = if n < 0 { "-" } else { "" };
+            match name {
+                "hex" => format!("{sign}0x{:0width$X}", n.unsigned_abs()),
+                _ => format!("{sign}0b{:0width$b}", n.unsigned_abs()),
+            }
+        }
+        "ordinal" | "words" => {
+            let Some(n) = parse_int(value) else {
+                return Err(format!("akin: ':{}' can only be used on integers, got '{}'", name, value));
+            };
+            if name == "words" {
+                number_words(n)
+            } else {
+                let suffix = match (n.unsigned_abs() % 10, n.unsigned_abs() % 100) {
+                    (_, 11..=13) => "th",
+                    (1, _) => "st",
+                    (2, _) => "nd",
+                    (3, _) => "rd",
+                    _ => "th",
+                };
+                format!("{n}{suffix}")
+            }
+        }
+        "hash" => stable_hash(value),
+        "capitalize" => capitalize(value),
+        "upper" => value.to_uppercase(),
+        "lower" => value.to_lowercase(),
+        "snake" => words(value).iter().map(|w| w.to_lowercase()).collect::<Vec<_>>().join("_"),
+        "camel" => words(value).iter().map(|w| capitalize(&w.to_lowercase())).collect(),
+        _ => unreachable!("akin: unknown modifier ':{}'", name),
+    };
+    Ok(applied)
+}
+
+/// Keywords that can be written as raw identifiers, like `r#type`.
+const RAW_KEYWORDS: &[&str] = &[
+    "abstract", "as", "async", "await", "become", "box", "break", "const", "continue", "do", "dyn", "else", "enum", "extern",
+    "false", "final", "fn", "for", "gen", "if", "impl", "in", "let", "loop", "macro", "match", "mod", "move", "mut", "override",
+    "priv", "pub", "ref", "return", "static", "struct", "trait", "true", "try", "type", "typeof", "unsafe", "unsized", "use",
+    "virtual", "where", "while", "yield",
+];
+
+/// Writes `n` with its thousands separated by commas, like `1,048,576`.
+fn thousands(n: usize) -> String {
+    let digits = n.to_string();
+    let mut out = String::with_capacity(digits.len() * 4 / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i).is_multiple_of(3) {
+            out.push(',');
+        }
+        out.push(digit);
+    }
+    out
+}
+
+/// Writes `n` in English words, like `twenty-one`.
+fn number_words(n: i128) -> String {
+    const ONES: [&str; 20] = [
+        "zero", "one", "two", "three", "four", "five", "six", "seven", "eight", "nine", "ten",
+        "eleven", "twelve", "thirteen", "fourteen", "fifteen", "sixteen", "seventeen", "eighteen", "nineteen",
+    ];
+    const TENS: [&str; 10] = ["", "", "twenty", "thirty", "forty", "fifty", "sixty", "seventy", "eighty", "ninety"];
+    const SCALES: [(u128, &str); 6] = [
+        (1_000_000_000_000_000_000, "quintillion"),
+        (1_000_000_000_000_000, "quadrillion"),
+        (1_000_000_000_000, "trillion"),
+        (1_000_000_000, "billion"),
+        (1_000_000, "million"),
+        (1_000, "thousand"),
+    ];
+
+    fn below_thousand(n: u128) -> String {
+        let (hundreds, rest) = (n / 100, (n % 100) as usize);
+        let mut words = Vec::new();
+        if hundreds > 0 {
+            words.push(format!("{} hundred", ONES[hundreds as usize]));
+        }
+        match rest {
+            0 => {}
+            1..=19 => words.push(ONES[rest].to_owned()),
+            _ if rest % 10 == 0 => words.push(TENS[rest / 10].to_owned()),
+            _ => words.push(format!("{}-{}", TENS[rest / 10], ONES[rest % 10])),
+        }
+        words.join(" ")
+    }
+
+    if n == 0 {
+        return ONES[0].to_owned();
+    }
+    let mut words = Vec::new();
+    if n < 0 {
+        words.push("minus".to_owned());
+    }
+    let mut rest = n.unsigned_abs();
+    for (scale, name) in SCALES {
+        if rest >= scale {
+            words.push(format!("{} {}", number_words((rest / scale) as i128), name));
+            rest %= scale;
+        }
+    }
+    if rest > 0 {
+        words.push(below_thousand(rest));
+    }
+    words.join(" ")
+}
+
+/// Splits an identifier in words, by `_`, `-` and spaces, and where the case changes, like `HTTPServerError`.
+fn words(value: &str) -> Vec<&str> {
+    let mut words = Vec::new();
+    for part in value.split(|c: char| !c.is_alphanumeric()).filter(|p| !p.is_empty()) {
+        let chars: Vec<(usize, char)> = part.char_indices().collect();
+        let mut start = 0;
+        for w in 1..chars.len() {
+            let (i, c) = chars[w];
+            let prev = chars[w - 1].1;
+            let next_lower = chars.get(w + 1).is_some_and(|(_, n)| n.is_lowercase());
+            if c.is_uppercase() && (prev.is_lowercase() || prev.is_numeric() || prev.is_uppercase() && next_lower) {
+                words.push(&part[start..i]);
+                start = i;
+            }
+        }
+        words.push(&part[start..]);
+    }
+    words
+}
+
+/// Uppercases the first character of `value`.
+fn capitalize(value: &str) -> String {
+    let mut chars = value.chars();
+    chars.next().map(|c| c.to_uppercase().chain(chars).collect()).unwrap_or_default()
+}
+
+/// Formats `text` as tokens separated by a single space, so that differences in spacing don't matter when comparing it.
+fn normalize_tokens(text: &str) -> String {
+    fn write_stream(out: &mut String, stream: proc_macro::TokenStream) {
+        for tt in stream {
+            if !out.is_empty() {
+                out.push(' ');
+            }
+            match tt {
+                TokenTree::Group(g) => {
+                    let (start, end) = get_delimiters(g.delimiter());
+                    out.push(start);
+                    write_stream(out, g.stream());
+                    out.push(end);
+                }
+                tt => write!(out, "{tt}").unwrap(),
+            }
+        }
+    }
+
+    match text.parse::<proc_macro::TokenStream>() {
+        Ok(stream) => {
+            let mut out = String::new();
+            write_stream(&mut out, stream);
+            out
+        }
+        Err(_) => text.trim().to_owned(),
     }
 }
 
-fn fold_tt(a: &mut String, tt: TokenTree, prev: &mut Option<TokenTree>) {
+fn fold_tt(
+    a: &mut String,
+    tt: TokenTree,
+    prev: &mut Option<TokenTree>,
+    vars: &Map<String, Vec<Value>>,
+    opts: &Options,
+) -> Result<(), Error> {
     match &tt {
         TokenTree::Group(g) => {
             let (start, end) = get_delimiters(g.delimiter());
             a.push(start);
-            for tt in g.stream() {
-                fold_tt(a, tt, prev);
-            }
+            *prev = Some(group_start());
+            fold_stream(a, g.stream(), prev, vars, opts)?;
             a.push(end);
         }
         TokenTree::Punct(p) if p.as_char() == '~' => {
-            // skip character
+            // Glues both sides once the values are known, see 'eval_joints'
+            a.push(JOINT);
+        }
+        TokenTree::Punct(p) if p.as_char() == '.' && matches!(&prev, Some(TokenTree::Ident(_))) && ends_with_var(a) => {
+            // Field access of a record variable, '*var.field'
+            a.push('.');
+        }
+        TokenTree::Ident(_) | TokenTree::Literal(_) if matches!(&prev, Some(TokenTree::Punct(p)) if p.as_char() == '.') && a.strip_suffix('.').is_some_and(ends_with_var) => {
+            write!(a, "{tt}").unwrap();
+        }
+        // With '#!sigil', the sigil is written as '*' and a real '*' is escaped so it's never substituted
+        TokenTree::Punct(p) if opts.sigil() != '*' && (p.as_char() == '*' || p.as_char() == opts.sigil()) => {
+            if needs_space(prev, opts) {
+                a.push(' ');
+            }
+            a.push(if p.as_char() == '*' { STAR } else { '*' });
         }
-        _ if matches!(&prev, Some(TokenTree::Punct(p)) if p.spacing() == Spacing::Joint || matches!(p.as_char(), '*' | '~')) => {
-            // Case '*' => To make variable formatting simpler ('*var' instead of '* var')
-            // Case '~' => Behaviour of the '~' modifier
+        _ if !needs_space(prev, opts) || sticks_to_prev(&tt, prev) || a.ends_with("::") => {
             write!(a, "{tt}").unwrap();
         }
         _ => {
@@ -578,6 +4565,74 @@ fn fold_tt(a: &mut String, tt: TokenTree, prev: &mut Option<TokenTree>) {
     };
 
     *prev = Some(tt);
+    Ok(())
+}
+
+/// Stands for the opening delimiter of a group as the previous token, so its first token isn't spaced, like in `pow(2)`.
+fn group_start() -> TokenTree {
+    TokenTree::Group(proc_macro::Group::new(Delimiter::None, proc_macro::TokenStream::new()))
+}
+
+/// Returns `true` if `tt` is written right after `prev`, like a `,`, or the `.` of a method call.
+///
+/// A `.` after a literal is still spaced, as `1.0` would be a single float literal instead.
+fn sticks_to_prev(tt: &TokenTree, prev: &Option<TokenTree>) -> bool {
+    let TokenTree::Punct(p) = tt else {
+        return false;
+    };
+    match prev {
+        Some(TokenTree::Ident(_) | TokenTree::Group(_)) => matches!(p.as_char(), ',' | ';' | '.' | '?' | ':'),
+        Some(TokenTree::Literal(_)) => matches!(p.as_char(), ',' | ';' | '?' | ':'),
+        _ => false,
+    }
+}
+
+/// Marker written in place of a real `*` when another sigil is chosen with `#!sigil`, so it's never substituted.
+const STAR: char = '\u{10}';
+
+/// Marker written after the variables kept as-is inside a scope, removed once they are substituted.
+const VAR_END: char = '\u{13}';
+
+/// Marker written in place of the joint modifier `~`.
+/// It also separates a variable from the text after it, so `*name~_impl` is `*name` followed by `_impl`.
+const JOINT: char = '\u{f}';
+
+/// Removes the `JOINT` markers along with the whitespace around them, gluing the tokens on both sides.
+///
+/// This is done after the substitution, so values that start or end with a space, like `{...}` code, are glued too.
+fn eval_joints(text: &str) -> String {
+    if !text.contains(JOINT) {
+        return text.to_owned();
+    }
+
+    let mut out = String::with_capacity(text.len());
+    for (i, part) in text.split(JOINT).enumerate() {
+        if i > 0 {
+            out.truncate(out.trim_end().len());
+            let mut part = part.trim_start();
+            // A raw identifier glued after another one is part of it, like `get_` and `r#type` in `get_type`
+            if out.ends_with(|c: char| c.is_alphanumeric() || c == '_') {
+                part = part.strip_prefix("r#").unwrap_or(part);
+            }
+            out.push_str(part);
+        } else {
+            out.push_str(part);
+        }
+    }
+    out
+}
+
+/// Returns `true` if `a` ends with a variable reference, like `*var`.
+fn ends_with_var(a: &str) -> bool {
+    let rest = a.trim_end_matches(|c: char| c.is_alphanumeric() || c == '_');
+    rest.len() < a.len() && rest.ends_with('*')
 }
 
 type Map<T, S> = std::collections::BTreeMap<T, S>;
+
+/// A value of a variable, shared by all the lists and copies that use it instead of being copied.
+/// It's atomically counted so the threads writing the copies can read it too.
+type Value = std::sync::Arc<str>;
+
+/// The variables made by a declaration, with their values, like `*name` and its fields `*name.field`.
+type Declared = Vec<(String, Vec<Value>)>;
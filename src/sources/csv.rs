@@ -0,0 +1,129 @@
+//! `csv("file.csv", columns = [0, 2], header = true)`, the rows of a CSV file.
+//!
+//! A file without rows can't have a header:
+//!
+//! ```compile_fail
+//! akin::akin! {
+//!     let &(name, code) = csv("tests/data/invalid/empty.csv", header = true);
+//!     const *name: u16 = *code;
+//! }
+//! ```
+
+use proc_macro::{Delimiter, TokenTree};
+
+use super::{read_file, split_top_tokens, Call};
+use crate::{declare_records, unescape_str, Declared, Error};
+
+pub(super) fn csv(call: &Call) -> Result<Declared, Error> {
+    let (names, declared, span) = (call.names, &call.declared, call.span);
+    let path = call.string_arg(0)?.ok_or_else(|| Error::new(span, format!("akin: expected file path in 'csv(...)' of '&{}'", declared)))?;
+    let path_span = call.path_span();
+    let (_, text) = read_file(&path, false, path_span)?;
+    let mut rows = parse_csv(&text).map_err(|e| Error::new(path_span, format!("akin: can't parse '{}': {}", path, e)))?;
+    let header = match call.named_arg("header") {
+        Some([TokenTree::Ident(b)]) if b.to_string() == "true" => {
+            if rows.is_empty() {
+                return Err(Error::new(path_span, format!("akin: '{}' has no header row", path)));
+            }
+            Some(rows.remove(0))
+        },
+        Some([TokenTree::Ident(b)]) if b.to_string() == "false" => None,
+        None => None,
+        Some(arg) => {
+            let got = arg.iter().map(|tt| tt.to_string()).collect::<String>();
+            return Err(Error::new(call.span_of(arg), format!("akin: expected 'true' or 'false' in 'header = ...' of '&{}', got '{}'", declared, got)));
+        },
+    };
+
+    // Columns are selected by index, or by name if the file has a header
+    let columns: Vec<usize> = match call.named_arg("columns") {
+        Some([TokenTree::Group(g)]) if g.delimiter() == Delimiter::Bracket => split_top_tokens(g.stream())
+            .iter()
+            .map(|column| {
+                let text: String = column.iter().map(|tt| tt.to_string()).collect();
+                text.parse().ok().or_else(|| {
+                    let column = unescape_str(&text)?;
+                    header.as_ref()?.iter().position(|h| *h == column)
+                })
+                .ok_or_else(|| Error::new(call.span_of(column), format!("akin: unknown column {} in 'csv(...)' of '&{}'", text, declared)))
+            })
+            .collect::<Result<_, _>>()?,
+        Some(arg) => {
+            return Err(Error::new(call.span_of(arg), format!("akin: expected list of columns like 'columns = [0, 2]' in 'csv(...)' of '&{}'", declared)));
+        },
+        None if names.len() > 1 => (0..names.len()).collect(),
+        None => (0..rows.iter().map(Vec::len).max().unwrap_or(0)).collect(),
+    };
+    let cell = |row: &[String], column: usize| row.get(column).cloned().unwrap_or_default();
+
+    if names.len() > 1 {
+        if columns.len() != names.len() {
+            return Err(Error::new(span, format!("akin: '&({})' declares {} variables, but {} columns are selected", declared, names.len(), columns.len())));
+        }
+        return Ok(names.iter().zip(&columns).map(|(name, &column)| (name.clone(), rows.iter().map(|row| cell(row, column).into()).collect())).collect());
+    }
+
+    // A single variable holds the selected cells of every row, which are also its fields if the file has a header
+    let values = rows.iter().map(|row| columns.iter().map(|&c| cell(row, c)).collect::<Vec<_>>().join(", ")).collect();
+    let records = rows
+        .iter()
+        .map(|row| match &header {
+            Some(header) => columns.iter().filter_map(|&c| Some((header.get(c)?.clone(), cell(row, c)))).collect(),
+            None => Vec::new(),
+        })
+        .collect();
+    Ok(declare_records(call.name().clone(), values, records))
+}
+
+/// Parses the rows of a CSV file, with fields optionally in double quotes `"..."`, in which `""` is a quote.
+///
+/// Empty lines are skipped, and the fields that aren't quoted are trimmed.
+pub(super) fn parse_csv(text: &str) -> Result<Vec<Vec<String>>, String> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut rest = text;
+    loop {
+        let trimmed = rest.trim_start_matches([' ', '\t']);
+        let field = if let Some(quoted) = trimmed.strip_prefix('"') {
+            let mut field = String::new();
+            let mut chars = quoted.char_indices();
+            loop {
+                match chars.next() {
+                    Some((i, '"')) if quoted[i + 1..].starts_with('"') => {
+                        field.push('"');
+                        chars.next();
+                    }
+                    Some((i, '"')) => {
+                        rest = quoted[i + 1..].trim_start_matches([' ', '\t']);
+                        break;
+                    }
+                    Some((_, c)) => field.push(c),
+                    None => return Err(format!("unterminated quoted field in row {}", rows.len() + 1)),
+                }
+            }
+            field
+        } else {
+            let end = rest.find([',', '\n']).unwrap_or(rest.len());
+            let field = rest[..end].trim().to_owned();
+            rest = &rest[end..];
+            field
+        };
+        row.push(field);
+
+        match rest.chars().next() {
+            Some(',') => rest = &rest[1..],
+            Some('\r' | '\n') | None => {
+                if row.len() > 1 || !row[0].is_empty() {
+                    rows.push(std::mem::take(&mut row));
+                }
+                row.clear();
+                rest = rest.trim_start_matches('\r');
+                match rest.strip_prefix('\n') {
+                    Some(next) => rest = next,
+                    None => return Ok(rows),
+                }
+            }
+            Some(c) => return Err(format!("unexpected '{}' after quoted field in row {}", c, rows.len() + 1)),
+        }
+    }
+}
@@ -0,0 +1,162 @@
+//! `variants_of {...}`, `fields_of {...}` and `methods_of {...}`, the parts of an item written in the invocation.
+
+use proc_macro::{Delimiter, Spacing, TokenTree};
+
+use super::{declare_columns, split_top_tokens, Call};
+use crate::{Declared, Error};
+
+/// `variants_of { enum ... }`, the names of the variants of an enum, and the patterns that match them.
+pub(super) fn variants_of(call: &Call, item: proc_macro::TokenStream) -> Result<Declared, Error> {
+    let (_, body) = item_body(item, "enum", &call.source, call.span)?;
+    let mut variants = Vec::new();
+    let mut patterns = Vec::new();
+    for variant in split_top_tokens(body.stream()) {
+        let mut tts = skip_attributes(variant).into_iter();
+        let Some(TokenTree::Ident(variant)) = tts.next() else {
+            return Err(Error::new(body.span(), format!("akin: expected variant name in '{}' of '&{}'", body, call.declared)));
+        };
+        let pattern = match tts.next() {
+            Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis => format!("{variant}(..)"),
+            Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Brace => format!("{variant} {{ .. }}"),
+            _ => variant.to_string(),
+        };
+        variants.push(variant.to_string());
+        patterns.push(pattern);
+    }
+    declare_columns(call.names, vec![("name", variants), ("pattern", patterns)], call.span)
+}
+
+/// `fields_of { struct ... }`, the names and types of the fields of a struct.
+pub(super) fn fields_of(call: &Call, item: proc_macro::TokenStream) -> Result<Declared, Error> {
+    let (_, body) = item_body(item, "struct", &call.source, call.span)?;
+    let mut fields = Vec::new();
+    let mut types = Vec::new();
+    for (i, field) in split_fields(body.stream()).into_iter().enumerate() {
+        let mut field = skip_attributes(field);
+        // Visibility like `pub` or `pub(crate)`
+        if matches!(field.first(), Some(TokenTree::Ident(id)) if id.to_string() == "pub") {
+            let len = if matches!(field.get(1), Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis) { 2 } else { 1 };
+            field.drain(..len);
+        }
+        let ty = match field.as_slice() {
+            [TokenTree::Ident(name), TokenTree::Punct(p), ty @ ..] if body.delimiter() == Delimiter::Brace && p.as_char() == ':' => {
+                fields.push(name.to_string());
+                ty
+            }
+            ty if body.delimiter() == Delimiter::Parenthesis => {
+                fields.push(i.to_string());
+                ty
+            }
+            _ => return Err(Error::new(call.span_of(&field), format!("akin: expected field like 'name: Type' in '{}' of '&{}'", body, call.declared))),
+        };
+        types.push(ty.iter().cloned().collect::<proc_macro::TokenStream>().to_string());
+    }
+    declare_columns(call.names, vec![("name", fields), ("ty", types)], call.span)
+}
+
+/// `methods_of { trait ... }`, the names, parameters, arguments, return types, generics and where clauses of the methods of a trait.
+pub(super) fn methods_of(call: &Call, item: proc_macro::TokenStream) -> Result<Declared, Error> {
+    let (_, body) = item_body(item, "trait", &call.source, call.span)?;
+    let (mut methods, mut params, mut args, mut rets) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+    let (mut generics, mut bounds) = (Vec::new(), Vec::new());
+    let to_string = |tts: &[TokenTree]| tts.iter().cloned().collect::<proc_macro::TokenStream>().to_string();
+
+    // Every item ends with `;` or with the braces of a default body
+    let mut items = vec![Vec::new()];
+    for tt in body.stream() {
+        let end = matches!(&tt, TokenTree::Punct(p) if p.as_char() == ';') || matches!(&tt, TokenTree::Group(g) if g.delimiter() == Delimiter::Brace);
+        items.last_mut().unwrap().push(tt);
+        if end {
+            items.push(Vec::new());
+        }
+    }
+
+    for item in items {
+        let Some(fn_pos) = item.iter().position(|tt| matches!(tt, TokenTree::Ident(id) if id.to_string() == "fn")) else {
+            continue;
+        };
+        let Some(TokenTree::Ident(method)) = item.get(fn_pos + 1) else {
+            return Err(Error::new(item[fn_pos].span(), format!("akin: expected method name in '{}' of '&{}'", to_string(&item), call.declared)));
+        };
+        let Some(params_pos) = item.iter().position(|tt| matches!(tt, TokenTree::Group(g) if g.delimiter() == Delimiter::Parenthesis)) else {
+            return Err(Error::new(method.span(), format!("akin: expected parameters of '{}' in '&{}'", method, call.declared)));
+        };
+        let TokenTree::Group(method_params) = &item[params_pos] else { unreachable!() };
+
+        // The arguments to call the method with, which are the parameters without `self` and their types
+        let method_args: Vec<String> = split_fields(method_params.stream())
+            .into_iter()
+            .filter(|param| !param.iter().any(|tt| matches!(tt, TokenTree::Ident(id) if id.to_string() == "self")))
+            .map(|param| {
+                let pattern = param.iter().position(|tt| matches!(tt, TokenTree::Punct(p) if p.as_char() == ':')).unwrap_or(param.len());
+                let pattern: Vec<TokenTree> = param[..pattern].iter().filter(|tt| !matches!(tt, TokenTree::Ident(id) if id.to_string() == "mut")).cloned().collect();
+                to_string(&pattern)
+            })
+            .collect();
+
+        // The return type goes from `->` to the `where` clause, which goes up to the body or the `;`
+        let rest = &item[params_pos + 1..];
+        let end = rest.iter().position(|tt| matches!(tt, TokenTree::Punct(p) if p.as_char() == ';') || matches!(tt, TokenTree::Group(g) if g.delimiter() == Delimiter::Brace));
+        let rest = &rest[..end.unwrap_or(rest.len())];
+        let where_pos = rest.iter().position(|tt| matches!(tt, TokenTree::Ident(id) if id.to_string() == "where")).unwrap_or(rest.len());
+        let ret = match &rest[..where_pos] {
+            [TokenTree::Punct(dash), TokenTree::Punct(gt), ret @ ..] if dash.as_char() == '-' && gt.as_char() == '>' => to_string(ret),
+            _ => "()".to_owned(),
+        };
+
+        methods.push(method.to_string());
+        params.push(method_params.stream().to_string());
+        args.push(method_args.join(", "));
+        rets.push(ret);
+        generics.push(to_string(&item[fn_pos + 2..params_pos]));
+        bounds.push(to_string(&rest[where_pos..]));
+    }
+    let columns = vec![("name", methods), ("params", params), ("args", args), ("ret", rets), ("generics", generics), ("where", bounds)];
+    declare_columns(call.names, columns, call.span)
+}
+
+/// Returns the name and the body of the item declared with `keyword` in `item`, like the variants of an `enum`,
+/// skipping its attributes, visibility and generics.
+fn item_body(item: proc_macro::TokenStream, keyword: &str, source: &str, span: proc_macro::Span) -> Result<(String, proc_macro::Group), Error> {
+    let mut tts = item.clone().into_iter().skip_while(|tt| !matches!(tt, TokenTree::Ident(id) if id.to_string() == keyword)).skip(1);
+    let Some(TokenTree::Ident(name)) = tts.next() else {
+        return Err(Error::new(span, format!("akin: expected '{}' in '{}' of '{}'", keyword, item, source)));
+    };
+    let body = tts.find_map(|tt| match tt {
+        TokenTree::Group(g) if matches!(g.delimiter(), Delimiter::Brace | Delimiter::Parenthesis) => Some(g),
+        _ => None,
+    });
+    let body = body.ok_or_else(|| Error::new(name.span(), format!("akin: expected body of '{} {}' in '{}'", keyword, name, source)))?;
+    Ok((name.to_string(), body))
+}
+
+/// Splits the fields or parameters in `stream` on the commas that aren't inside a group or generics `<...>`.
+fn split_fields(stream: proc_macro::TokenStream) -> Vec<Vec<TokenTree>> {
+    let mut parts = vec![Vec::new()];
+    let mut depth = 0usize;
+    let mut prev_dash = false;
+    for tt in stream {
+        match &tt {
+            TokenTree::Punct(p) if p.as_char() == ',' && depth == 0 => {
+                parts.push(Vec::new());
+                continue;
+            }
+            TokenTree::Punct(p) if p.as_char() == '<' => depth += 1,
+            // Not the `>` of `->`
+            TokenTree::Punct(p) if p.as_char() == '>' && !prev_dash => depth = depth.saturating_sub(1),
+            _ => {}
+        }
+        prev_dash = matches!(&tt, TokenTree::Punct(p) if p.as_char() == '-' && p.spacing() == Spacing::Joint);
+        parts.last_mut().unwrap().push(tt);
+    }
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+/// Removes the attributes like `#[cfg(...)]` at the start of `tts`.
+fn skip_attributes(mut tts: Vec<TokenTree>) -> Vec<TokenTree> {
+    while matches!(tts.as_slice(), [TokenTree::Punct(p), TokenTree::Group(_), ..] if p.as_char() == '#') {
+        tts.drain(..2);
+    }
+    tts
+}
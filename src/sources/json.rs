@@ -0,0 +1,122 @@
+//! JSON data files read by `file("codes.json", "opcodes.name")`.
+//!
+//! Files that aren't valid JSON are rejected, like one with a missing comma:
+//!
+//! ```compile_fail
+//! akin::akin! {
+//!     let &name = file("tests/data/invalid/missing_comma.json", "name");
+//!     fn *name() {}
+//! }
+//! ```
+//!
+//! ```compile_fail
+//! akin::akin! {
+//!     let &name = file("tests/data/invalid/unquoted_key.json", "name");
+//!     fn *name() {}
+//! }
+//! ```
+
+use super::{found, insert, parse_quoted, Data};
+
+/// Characters that can follow a `\` in a JSON string.
+const ESCAPES: &str = "\"\\/bfnrtu";
+
+/// Parses a JSON value from the start of `text`, advancing it past the value.
+///
+/// `null` is read as an empty value, like NONE.
+pub(super) fn parse_json(text: &mut &str) -> Result<Data, String> {
+    *text = text.trim_start();
+    match text.chars().next() {
+        Some('{') => {
+            *text = &text[1..];
+            let mut entries = Vec::new();
+            *text = text.trim_start();
+            if let Some(rest) = text.strip_prefix('}') {
+                *text = rest;
+                return Ok(Data::Table(entries));
+            }
+            loop {
+                *text = text.trim_start();
+                if !text.starts_with('"') {
+                    return Err(format!("expected key in double quotes, got {}", found(text)));
+                }
+                let key = parse_quoted(text, ESCAPES)?;
+                *text = text.trim_start().strip_prefix(':').ok_or_else(|| format!("expected ':' after key \"{key}\", got {}", found(text.trim_start())))?;
+                let value = parse_json(text)?;
+                insert(&mut entries, &key, value)?;
+                if separator(text, '}')? {
+                    return Ok(Data::Table(entries));
+                }
+            }
+        }
+        Some('[') => {
+            *text = &text[1..];
+            let mut elements = Vec::new();
+            *text = text.trim_start();
+            if let Some(rest) = text.strip_prefix(']') {
+                *text = rest;
+                return Ok(Data::Array(elements));
+            }
+            loop {
+                elements.push(parse_json(text)?);
+                if separator(text, ']')? {
+                    return Ok(Data::Array(elements));
+                }
+            }
+        }
+        Some('"') => parse_quoted(text, ESCAPES).map(Data::Value),
+        Some(_) => {
+            let end = text.find(|c: char| c.is_whitespace() || ",:[]{}\"".contains(c)).unwrap_or(text.len());
+            let value = &text[..end];
+            if !matches!(value, "null" | "true" | "false") && !is_number(value) {
+                return Err(format!("expected value, got {}", found(text)));
+            }
+            *text = &text[end..];
+            Ok(Data::Value(if value == "null" { String::new() } else { value.to_owned() }))
+        }
+        None => Err("unexpected end of file".to_owned()),
+    }
+}
+
+/// Skips the `,` after an element of an array or object, returning whether it was its `close` instead.
+fn separator(text: &mut &str, close: char) -> Result<bool, String> {
+    *text = text.trim_start();
+    match text.chars().next() {
+        Some(',') => {
+            *text = &text[1..];
+            Ok(false)
+        }
+        Some(c) if c == close => {
+            *text = &text[1..];
+            Ok(true)
+        }
+        _ => Err(format!("expected ',' or '{close}', got {}", found(text))),
+    }
+}
+
+/// Whether `value` is a JSON number like `-1.5e3`, which unlike a Rust one can't start with `+`, `.` or a `0` followed by digits.
+fn is_number(value: &str) -> bool {
+    let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let mut rest = value.strip_prefix('-').unwrap_or(value);
+    let int = digits(rest);
+    if int == 0 || (int > 1 && rest.starts_with('0')) {
+        return false;
+    }
+    rest = &rest[int..];
+    if let Some(fraction) = rest.strip_prefix('.') {
+        let len = digits(fraction);
+        if len == 0 {
+            return false;
+        }
+        rest = &fraction[len..];
+    }
+    if let Some(exponent) = rest.strip_prefix(['e', 'E']) {
+        let exponent = exponent.strip_prefix(['+', '-']).unwrap_or(exponent);
+        let len = digits(exponent);
+        if len == 0 {
+            return false;
+        }
+        rest = &exponent[len..];
+    }
+    rest.is_empty()
+}
@@ -0,0 +1,700 @@
+//! Value sources, which declare the values of a variable from something else than a list,
+//! like a file with `csv(...)`, an item with `variants_of {...}` or other variables with `zip(...)`.
+
+mod csv;
+mod items;
+mod json;
+mod toml;
+
+use proc_macro::{Delimiter, TokenTree};
+
+use crate::{
+    alias, apply_modifier, check_values, concat_lists, parse_float, parse_group_values, parse_int, parse_range_expr, select, slice_range,
+    unescape_str, declare_records, Declared, Error, Lookahead, Map, Options, Value, EMITTED, MODIFIERS, MODIFIERS_WITH_ARGS, READ_FILES,
+};
+
+/// Names of the value sources, like `csv` in `let &name = csv(...);`.
+pub(crate) const SOURCES: &[&str] = &[
+    "chunks", "combinations", "concat", "cross", "csv", "dedup", "difference", "enumerate", "env", "fields_of", "file", "filter", "idents",
+    "intersect", "lines", "map", "methods_of", "permutations", "rev", "sort", "split", "union", "variants_of", "windows", "zip",
+];
+
+/// A call to a value source, like `csv("errors.csv", header = true)`, with what its errors refer to.
+struct Call<'a> {
+    /// Name of the source, like `csv`.
+    source: String,
+    /// Variables it declares, which are more than one if it's declared as a tuple.
+    names: &'a [String],
+    /// Names of the variables as written in the errors, like `a, b`.
+    declared: String,
+    /// Where the errors that aren't about an argument are shown, the name of the source.
+    span: proc_macro::Span,
+    /// Positional arguments like `"path"`.
+    positional: Vec<Vec<TokenTree>>,
+    /// Named arguments like `columns = [0, 2]`.
+    named: Vec<(String, Vec<TokenTree>)>,
+    vars: &'a Map<String, Vec<Value>>,
+    opts: &'a Options,
+}
+
+impl Call<'_> {
+    /// The variable the values are declared with, the first one if it's a tuple.
+    fn name(&self) -> &String {
+        &self.names[0]
+    }
+
+    /// Where an error about `arg` is shown, or the name of the source if it's empty.
+    fn span_of(&self, arg: &[TokenTree]) -> proc_macro::Span {
+        arg.first().map_or(self.span, TokenTree::span)
+    }
+
+    /// Where an error about the file read by the source is shown, its first argument.
+    fn path_span(&self) -> proc_macro::Span {
+        self.positional.first().map_or(self.span, |arg| self.span_of(arg))
+    }
+
+    fn string(&self, arg: &[TokenTree]) -> Result<String, Error> {
+        let text: String = arg.iter().map(|tt| tt.to_string()).collect();
+        unescape_str(&text).ok_or_else(|| {
+            Error::new(self.span_of(arg), format!("akin: expected string literal in '{}(...)' of '&{}', got '{}'", self.source, self.declared, text))
+        })
+    }
+
+    /// The positional argument `i` as a string, if it's given.
+    fn string_arg(&self, i: usize) -> Result<Option<String>, Error> {
+        self.positional.get(i).map(|arg| self.string(arg)).transpose()
+    }
+
+    fn named_arg(&self, key: &str) -> Option<&[TokenTree]> {
+        self.named.iter().find(|(k, _)| k == key).map(|(_, value)| value.as_slice())
+    }
+
+    /// Reads an argument that gives a list of values, like `&var`, a slice of it like `&var[1..]`, or `[...]`.
+    fn list_arg(&self, arg: &[TokenTree]) -> Result<Declared, Error> {
+        let name = self.name();
+        match arg {
+            [TokenTree::Punct(p), of @ .., TokenTree::Group(g)] if p.as_char() == '&' && !of.is_empty() && g.delimiter() == Delimiter::Bracket => {
+                let list = alias(name, &of.iter().map(|tt| tt.to_string()).collect::<String>(), self.span_of(of), self.vars)?;
+                let range = slice_range(g, list[0].1.len(), name)?;
+                Ok(select(list, &range.collect::<Vec<_>>()))
+            },
+            [TokenTree::Punct(p), of @ ..] if p.as_char() == '&' && !of.is_empty() => {
+                alias(name, &of.iter().map(|tt| tt.to_string()).collect::<String>(), self.span_of(of), self.vars)
+            },
+            [TokenTree::Group(g)] if g.delimiter() == Delimiter::Bracket => parse_group_values(name.clone(), g, self.vars, self.opts),
+            _ => Err(Error::new(
+                self.span_of(arg),
+                format!(
+                    "akin: expected variable like '&var' or list like '[...]' in '{}(...)' of '&{}', got '{}'",
+                    self.source, self.declared, arg.iter().map(|tt| tt.to_string()).collect::<String>()
+                ),
+            )),
+        }
+    }
+
+    /// Reads every positional argument as a list, failing if there are less than two.
+    fn list_args(&self) -> Result<Vec<Declared>, Error> {
+        let lists = self.positional.iter().map(|arg| self.list_arg(arg)).collect::<Result<Vec<_>, _>>()?;
+        if lists.len() < 2 {
+            return Err(Error::new(self.span, format!("akin: expected at least two variables or lists in '{}(...)' of '&{}'", self.source, self.declared)));
+        }
+        Ok(lists)
+    }
+
+    fn only_list_arg(&self) -> Result<Declared, Error> {
+        match self.positional.as_slice() {
+            [arg] => self.list_arg(arg),
+            _ => Err(Error::new(self.span, format!("akin: expected a single variable or list in '{}(...)' of '&{}'", self.source, self.declared))),
+        }
+    }
+
+    /// Reads the list and the positive size of sources like `chunks(&values, 2)`.
+    fn list_and_size(&self) -> Result<(Declared, usize), Error> {
+        let [list, size] = self.positional.as_slice() else {
+            return Err(Error::new(self.span, format!("akin: expected variable or list and size like '{}(&values, 2)' in '&{}'", self.source, self.declared)));
+        };
+        let text: String = size.iter().map(|tt| tt.to_string()).collect();
+        let size_span = self.span_of(size);
+        let size = parse_int(&text).and_then(|n| usize::try_from(n).ok()).filter(|&n| n > 0);
+        let size = size.ok_or_else(|| Error::new(size_span, format!("akin: expected positive size in '{}(...)' of '&{}', got '{}'", self.source, self.declared, text)))?;
+        Ok((self.list_arg(list)?, size))
+    }
+}
+
+/// Parses the values of the variables `names` from a value source, like `file("codes.toml", "opcodes.name")`.
+///
+/// Only some sources, like `csv(...)`, can declare more than one variable.
+/// `item` is the item in braces of the sources that read one, like `variants_of { enum ... }`.
+pub(crate) fn parse_source(
+    names: &[String],
+    source: &proc_macro::Ident,
+    args: proc_macro::TokenStream,
+    item: Option<proc_macro::TokenStream>,
+    vars: &Map<String, Vec<Value>>,
+    opts: &Options,
+) -> Result<Declared, Error> {
+    let mut call = Call {
+        source: source.to_string(),
+        names,
+        declared: names.iter().map(|n| &n[1..]).collect::<Vec<_>>().join(", "),
+        // Errors are shown at the argument that caused them, or at the name of the source
+        span: source.span(),
+        positional: Vec::new(),
+        named: Vec::new(),
+        vars,
+        opts,
+    };
+    for arg in split_top_tokens(args) {
+        match arg.as_slice() {
+            [TokenTree::Ident(key), TokenTree::Punct(p), value @ ..] if p.as_char() == '=' => call.named.push((key.to_string(), value.to_vec())),
+            _ => call.positional.push(arg),
+        }
+    }
+    let (source, declared, span) = (call.source.as_str(), &call.declared, call.span);
+
+    if names.len() > 1 && !matches!(source, "csv" | "variants_of" | "fields_of" | "methods_of" | "zip" | "cross" | "enumerate" | "permutations" | "combinations") {
+        return Err(Error::new(span, format!("akin: '{}(...)' declares a single variable, it can't be declared as the tuple '&({})'", source, declared)));
+    }
+    let item = match (item, source) {
+        (Some(item), "variants_of" | "fields_of" | "methods_of") => item,
+        (None, "variants_of" | "fields_of" | "methods_of") => {
+            return Err(Error::new(span, format!("akin: expected item in braces after '&{}={}', like '{} {{ enum ... }}'", declared, source, source)));
+        },
+        (Some(item), _) => return Err(Error::new(span, format!("akin: '{}(...)' doesn't read an item, got '{{ {} }}'", source, item))),
+        (None, _) => proc_macro::TokenStream::new(),
+    };
+    // `emit` also adds the item to the output, once
+    if call.positional.iter().any(|arg| matches!(arg.as_slice(), [TokenTree::Ident(id)] if id.to_string() == "emit")) {
+        EMITTED.with(|emitted| emitted.borrow_mut().push_str(&item.to_string()));
+    }
+
+    match source {
+        "csv" => csv::csv(&call),
+        "env" => env(&call),
+        "lines" => lines(&call),
+        "split" => split(&call),
+        "idents" => idents(&call),
+        "concat" => concat(&call),
+        "rev" | "sort" | "dedup" => reorder(&call),
+        "zip" => zip(&call),
+        "cross" => cross(&call),
+        "enumerate" => enumerate(&call),
+        "filter" => filter(&call),
+        "map" => map(&call),
+        "chunks" | "windows" => chunks(&call),
+        "union" | "intersect" | "difference" => set(&call),
+        "permutations" | "combinations" => permutations(&call),
+        "variants_of" => items::variants_of(&call, item),
+        "fields_of" => items::fields_of(&call, item),
+        "methods_of" => items::methods_of(&call, item),
+        "file" => file(&call),
+        _ => {
+            let message = format!("akin: unknown value source '{}(...)' in '&{}' declaration, expected one of: {}", source, declared, SOURCES.join(", "));
+            Err(Error::new(span, message))
+        },
+    }
+}
+
+/// `env("VAR")`, the value of an environment variable, or its values with `split = ","`.
+fn env(call: &Call) -> Result<Declared, Error> {
+    let var = call.string_arg(0)?.ok_or_else(|| Error::new(call.span, format!("akin: expected variable name in 'env(...)' of '&{}'", call.declared)))?;
+    // On nightly the compiler is told about the variable, so a change to it expands the macro again
+    #[cfg(feature = "diagnostics")]
+    let value = proc_macro::tracked::env_var(&var);
+    #[cfg(not(feature = "diagnostics"))]
+    let value = std::env::var(&var);
+    let value = match value {
+        Ok(value) => value,
+        Err(_) => call.named_arg("default").map(|arg| call.string(arg)).transpose()?.ok_or_else(|| {
+            let message = format!("akin: environment variable '{}' of '&{}' is not set, a fallback can be given with 'default = \"...\"'", var, call.declared);
+            Error::new(call.path_span(), message)
+        })?,
+    };
+    let values = match call.named_arg("split").map(|arg| call.string(arg)).transpose()? {
+        Some(sep) => split_values(&value, &sep),
+        None => vec![value.trim().into()],
+    };
+    Ok(vec![(call.name().clone(), values)])
+}
+
+/// `lines("file.txt")`, the lines of a file relative to the one of the invocation.
+fn lines(call: &Call) -> Result<Declared, Error> {
+    let path = call.string_arg(0)?.ok_or_else(|| Error::new(call.span, format!("akin: expected file path in 'lines(...)' of '&{}'", call.declared)))?;
+    let (_, text) = read_file(&path, true, call.path_span())?;
+    Ok(vec![(call.name().clone(), split_values(&text, "\n"))])
+}
+
+/// `split("a, b", ",")`, the parts of a string.
+fn split(call: &Call) -> Result<Declared, Error> {
+    let text = call.string_arg(0)?.ok_or_else(|| Error::new(call.span, format!("akin: expected string to split in 'split(...)' of '&{}'", call.declared)))?;
+    let sep = call.string_arg(1)?.unwrap_or_else(|| ",".to_owned());
+    Ok(vec![(call.name().clone(), split_values(&text, &sep))])
+}
+
+/// `idents(T, 1..=16)`, identifiers made of a prefix and the numbers of a range.
+fn idents(call: &Call) -> Result<Declared, Error> {
+    let name = call.name();
+    let [prefix, range] = call.positional.as_slice() else {
+        return Err(Error::new(call.span, format!("akin: expected prefix and range like 'idents(T, 1..=16)' in '&{}'", call.declared)));
+    };
+    let prefix: String = prefix.iter().map(|tt| tt.to_string()).collect();
+    let mut range: Lookahead = range.iter().cloned().collect::<proc_macro::TokenStream>().into_iter().into();
+    let values = parse_range_expr(&name[1..], &mut range, call.vars, call.opts)?;
+    Ok(vec![(name.clone(), values.into_iter().map(|i| format!("{prefix}{i}").into()).collect())])
+}
+
+/// `concat(&a, &b)`, the values of several lists one after the other.
+fn concat(call: &Call) -> Result<Declared, Error> {
+    if call.positional.is_empty() {
+        return Err(Error::new(call.span, format!("akin: expected variables to concatenate in 'concat(...)' of '&{}'", call.declared)));
+    }
+    Ok(concat_lists(call.positional.iter().map(|arg| call.list_arg(arg)).collect::<Result<_, _>>()?))
+}
+
+/// `rev(&a)`, `sort(&a)` and `dedup(&a)`, the values of a list in another order, or without the repeated ones.
+fn reorder(call: &Call) -> Result<Declared, Error> {
+    let list = call.only_list_arg()?;
+    let values = &list[0].1;
+    let indices = match call.source.as_str() {
+        "rev" => (0..values.len()).rev().collect::<Vec<_>>(),
+        "sort" => {
+            let mut indices = (0..values.len()).collect::<Vec<_>>();
+            indices.sort_by(|&a, &b| compare_values(&values[a], &values[b]));
+            indices
+        },
+        _ => (0..values.len()).filter(|&i| !values[..i].contains(&values[i])).collect(),
+    };
+    Ok(select(list, &indices))
+}
+
+/// `zip(&a, &b)`, the values of lists of the same length, side by side.
+fn zip(call: &Call) -> Result<Declared, Error> {
+    let lists = call.list_args()?;
+    if let Some(i) = lists.iter().position(|list| list[0].1.len() != lists[0][0].1.len()) {
+        let message = format!("akin: lists in 'zip(...)' of '&{}' have different lengths, {} and {}", call.declared, lists[0][0].1.len(), lists[i][0].1.len());
+        return Err(Error::new(call.span_of(&call.positional[i]), message));
+    }
+    tuple_lists(call.names, lists, call.span)
+}
+
+/// `cross(&a, &b)`, every combination of the values of several lists.
+fn cross(call: &Call) -> Result<Declared, Error> {
+    let lists = call.list_args()?;
+    // The last list changes the fastest, like in nested loops
+    let lens = lists.iter().map(|list| list[0].1.len()).collect::<Vec<_>>();
+    let total = lens.iter().try_fold(1usize, |total, len| total.checked_mul(*len)).unwrap_or(usize::MAX);
+    check_values(total, &call.declared, call.span, call.opts)?;
+    let mut repeat = total;
+    let lists = lists
+        .into_iter()
+        .zip(&lens)
+        .map(|(list, &len)| {
+            repeat /= len.max(1);
+            let indices = (0..total).map(|i| i / repeat.max(1) % len.max(1)).collect::<Vec<_>>();
+            select(list, &indices)
+        })
+        .collect();
+    tuple_lists(call.names, lists, call.span)
+}
+
+/// `enumerate(&a)`, the values of a list along with their index.
+fn enumerate(call: &Call) -> Result<Declared, Error> {
+    let list = call.only_list_arg()?;
+    let indices = vec![(call.name().clone(), (0..list[0].1.len()).map(|i| i.to_string().into()).collect())];
+    tuple_lists(call.names, vec![indices, list], call.span)
+}
+
+/// `filter(&a, starts_with = "u")`, the values of a list that pass every predicate.
+fn filter(call: &Call) -> Result<Declared, Error> {
+    let list = call.only_list_arg()?;
+    // The predicates test the values, or the ones of a field of records with `field = "name"`
+    let field = call.named_arg("field").map(|arg| Ok::<_, Error>((call.string(arg)?, call.span_of(arg)))).transpose()?;
+    let predicates = call
+        .named
+        .iter()
+        .filter(|(key, _)| key != "field")
+        .map(|(key, value)| {
+            let test: fn(&str, &str) -> bool = match key.as_str() {
+                "starts_with" => |v, p| v.starts_with(p),
+                "ends_with" => |v, p| v.ends_with(p),
+                "contains" => |v, p| v.contains(p),
+                _ => {
+                    let message = format!("akin: unknown predicate '{}' in 'filter(...)' of '&{}', expected one of: contains, ends_with, starts_with", key, call.declared);
+                    return Err(Error::new(call.span_of(value), message));
+                },
+            };
+            Ok((test, call.string(value)?))
+        })
+        .collect::<Result<Vec<_>, Error>>()?;
+    if predicates.is_empty() {
+        return Err(Error::new(call.span, format!("akin: expected predicates like 'starts_with = \"u\"' in 'filter(...)' of '&{}'", call.declared)));
+    }
+    let values = match &field {
+        Some((field, field_span)) => {
+            let var = format!("{}.{field}", call.name());
+            let values = list.iter().find(|(v, _)| *v == var);
+            &values.ok_or_else(|| Error::new(*field_span, format!("akin: unknown field '{}' in 'filter(...)' of '&{}'", field, call.declared)))?.1
+        }
+        None => &list[0].1,
+    };
+    let indices = (0..values.len()).filter(|&i| predicates.iter().all(|(test, p)| test(&values[i], p))).collect::<Vec<_>>();
+    Ok(select(list, &indices))
+}
+
+/// `map(&a, :upper)`, the values of a list transformed by modifiers.
+fn map(call: &Call) -> Result<Declared, Error> {
+    let [list, modifiers] = call.positional.as_slice() else {
+        return Err(Error::new(call.span, format!("akin: expected variable or list and modifiers like 'map(&names, :upper)' in '&{}'", call.declared)));
+    };
+    let mut list = call.list_arg(list)?;
+    // Modifiers are applied from left to right, like after a variable
+    let mut tts = modifiers.iter().peekable();
+    let mut modifiers = Vec::new();
+    while let Some(tt) = tts.next() {
+        let (modifier, modifier_span) = match (tt, tts.next()) {
+            (TokenTree::Punct(p), Some(TokenTree::Ident(modifier))) if p.as_char() == ':' => (modifier.to_string(), modifier.span()),
+            _ => return Err(Error::new(tt.span(), format!("akin: expected modifiers like ':upper' in 'map(...)' of '&{}', got '{}'", call.declared, tt))),
+        };
+        if !MODIFIERS.contains(&modifier.as_str()) || modifier == "join" {
+            let expected = MODIFIERS.join(", ").replace("join, ", "");
+            return Err(Error::new(modifier_span, format!("akin: unknown modifier ':{}' in 'map(...)' of '&{}', expected one of: {}", modifier, call.declared, expected)));
+        }
+        let args = match tts.peek() {
+            Some(TokenTree::Group(g)) if g.delimiter() == Delimiter::Parenthesis && MODIFIERS_WITH_ARGS.contains(&modifier.as_str()) => {
+                tts.next();
+                g.stream().to_string()
+            }
+            _ => String::new(),
+        };
+        modifiers.push((modifier, args, modifier_span));
+    }
+    for value in &mut list[0].1 {
+        let mut mapped = value.trim().to_owned();
+        for (modifier, args, span) in &modifiers {
+            mapped = apply_modifier(modifier, args.trim(), &mapped).map_err(|message| Error::new(*span, message))?;
+        }
+        *value = mapped.into();
+    }
+    Ok(list)
+}
+
+/// `chunks(&a, 2)` and `windows(&a, 2)`, the groups of consecutive values of a list.
+fn chunks(call: &Call) -> Result<Declared, Error> {
+    let name = call.name();
+    let (list, size) = call.list_and_size()?;
+    let values = &list[0].1;
+    let groups: Vec<&[Value]> = match call.source.as_str() {
+        "chunks" => values.chunks(size).collect(),
+        _ => values.windows(size).collect(),
+    };
+    // Each group is written separated by commas, and its values are the fields `0`, `1`, ...
+    let mut declared = vec![(name.clone(), groups.iter().map(|group| group.join(", ").into()).collect())];
+    declared.extend((0..size).map(|i| (format!("{name}.{i}"), groups.iter().map(|group| group.get(i).cloned().unwrap_or_default()).collect())));
+    Ok(declared)
+}
+
+/// `union(&a, &b)`, `intersect(&a, &b)` and `difference(&a, &b)`, the values of lists as sets.
+fn set(call: &Call) -> Result<Declared, Error> {
+    let mut lists = call.list_args()?;
+    // The values keep the order of the lists, and only the first of the repeated ones is kept
+    let first = match call.source.as_str() {
+        "union" => concat_lists(std::mem::take(&mut lists)),
+        _ => lists.remove(0),
+    };
+    let values = &first[0].1;
+    let indices = (0..values.len())
+        .filter(|&i| !values[..i].contains(&values[i]))
+        .filter(|&i| match call.source.as_str() {
+            "intersect" => lists.iter().all(|list| list[0].1.contains(&values[i])),
+            "difference" => !lists.iter().any(|list| list[0].1.contains(&values[i])),
+            _ => true,
+        })
+        .collect::<Vec<_>>();
+    Ok(select(first, &indices))
+}
+
+/// `permutations(&a, 2)` and `combinations(&a, 2)`, every way to pick some values of a list.
+fn permutations(call: &Call) -> Result<Declared, Error> {
+    let ordered = call.source == "permutations";
+    let (list, k) = call.list_and_size()?;
+    let n = list[0].1.len();
+    let count = if k > n {
+        Some(0)
+    } else if ordered {
+        (n - k + 1..=n).try_fold(1usize, |count, i| count.checked_mul(i))
+    } else {
+        (0..k.min(n - k)).try_fold(1usize, |count, i| count.checked_mul(n - i).map(|count| count / (i + 1)))
+    };
+    check_values(count.unwrap_or(usize::MAX), &call.declared, call.span, call.opts)?;
+    let picks = arrangements(n, k, ordered);
+    let lists = (0..k).map(|i| select(list.clone(), &picks.iter().map(|pick| pick[i]).collect::<Vec<_>>())).collect();
+    tuple_lists(call.names, lists, call.span)
+}
+
+/// `file("data.toml", "key.inner")`, the elements of a JSON or TOML file, or the ones under a key.
+fn file(call: &Call) -> Result<Declared, Error> {
+    let path = call.string_arg(0)?.ok_or_else(|| Error::new(call.span, format!("akin: expected file path in 'file(...)' of '&{}'", call.declared)))?;
+    let data = read_data(&path, call.path_span())?;
+    let key = call.string_arg(1)?.unwrap_or_default();
+    let mut selected = vec![&data];
+    // Elements that lack the key get an empty value, like NONE, to stay in lockstep with the rest
+    static EMPTY: Data = Data::Value(String::new());
+    for key in key.split('.').filter(|k| !k.is_empty()) {
+        let elements: Vec<&Data> = selected.iter().flat_map(|d| d.elements()).collect();
+        if !elements.iter().any(|d| d.get(key).is_some()) {
+            let key_span = call.positional.get(1).map_or(call.span, |arg| call.span_of(arg));
+            return Err(Error::new(key_span, format!("akin: key '{}' not found in '{}'", key, path)));
+        }
+        selected = elements.into_iter().map(|d| d.get(key).unwrap_or(&EMPTY)).collect();
+    }
+
+    let elements: Vec<&Data> = selected.into_iter().flat_map(Data::elements).collect();
+    let values = elements.iter().map(|d| d.to_string()).collect();
+    let records = elements
+        .iter()
+        .map(|d| {
+            let mut fields = Vec::new();
+            d.fields("", &mut fields);
+            fields
+        })
+        .collect();
+    Ok(declare_records(call.name().clone(), values, records))
+}
+
+/// Declares each list, of the same length, as one of the variables of the tuple `names`, along with their fields,
+/// or, if `names` is a single variable, as the tuples of their values, like `(a, b)`, with the fields `0`, `1`, ...
+///
+/// The lists are declared with the name `names[0]`.
+fn tuple_lists(names: &[String], lists: Vec<Vec<(String, Vec<Value>)>>, span: proc_macro::Span) -> Result<Vec<(String, Vec<Value>)>, Error> {
+    let name = &names[0];
+    if names.len() > 1 {
+        if names.len() != lists.len() {
+            let declared = names.iter().map(|n| &n[1..]).collect::<Vec<_>>().join(", ");
+            return Err(Error::new(span, format!("akin: '&({})' declares {} variables, but {} lists are given", declared, names.len(), lists.len())));
+        }
+        return Ok(names
+            .iter()
+            .zip(lists)
+            .flat_map(|(to, list)| list.into_iter().map(move |(var, values)| (format!("{}{}", to, &var[name.len()..]), values)))
+            .collect());
+    }
+    let len = lists[0][0].1.len();
+    let values = (0..len).map(|i| format!("({})", lists.iter().map(|list| &*list[0].1[i]).collect::<Vec<_>>().join(", ")).into()).collect();
+    let mut declared = vec![(name.clone(), values)];
+    declared.extend(lists.into_iter().enumerate().map(|(i, mut list)| (format!("{name}.{i}"), list.swap_remove(0).1)));
+    Ok(declared)
+}
+
+/// Returns the indices of every way to pick `k` of `n` values, in order,
+/// each one different if `ordered`, like permutations, or only the ones in increasing order otherwise, like combinations.
+fn arrangements(n: usize, k: usize, ordered: bool) -> Vec<Vec<usize>> {
+    fn extend(n: usize, k: usize, ordered: bool, pick: &mut Vec<usize>, picks: &mut Vec<Vec<usize>>) {
+        if pick.len() == k {
+            picks.push(pick.clone());
+            return;
+        }
+        let start = if ordered { 0 } else { pick.last().map_or(0, |&last| last + 1) };
+        for i in start..n {
+            if !pick.contains(&i) {
+                pick.push(i);
+                extend(n, k, ordered, pick, picks);
+                pick.pop();
+            }
+        }
+    }
+    let mut picks = Vec::new();
+    extend(n, k, ordered, &mut Vec::with_capacity(k), &mut picks);
+    picks
+}
+
+/// Compares two values by their number if both are numeric literals, like `8` and `0x10`, or by their text otherwise.
+///
+/// Numbers go before the rest of values.
+fn compare_values(a: &str, b: &str) -> std::cmp::Ordering {
+    let number = |v: &str| parse_int(v).map(|n| n as f64).or_else(|| parse_float(v));
+    match (number(a), number(b)) {
+        (Some(a), Some(b)) => a.total_cmp(&b),
+        (Some(_), None) => std::cmp::Ordering::Less,
+        (None, Some(_)) => std::cmp::Ordering::Greater,
+        (None, None) => a.cmp(b),
+    }
+}
+
+/// Declares the columns of a value source as the fields of `names[0]`, which has the values of the first column,
+/// or, if `names` is a tuple, as one variable for each column.
+fn declare_columns(names: &[String], columns: Vec<(&str, Vec<String>)>, span: proc_macro::Span) -> Result<Vec<(String, Vec<Value>)>, Error> {
+    let columns = columns.into_iter().map(|(field, values)| (field, values.into_iter().map(Value::from).collect::<Vec<_>>())).collect::<Vec<_>>();
+    if names.len() > 1 {
+        if names.len() > columns.len() {
+            let declared = names.iter().map(|n| &n[1..]).collect::<Vec<_>>().join(", ");
+            return Err(Error::new(span, format!("akin: expected at most {} variables, got '&({})'", columns.len(), declared)));
+        }
+        return Ok(names.iter().cloned().zip(columns.into_iter().map(|(_, values)| values)).collect());
+    }
+    let mut declared = vec![(names[0].clone(), columns[0].1.clone())];
+    declared.extend(columns.into_iter().map(|(field, values)| (format!("{}.{}", names[0], field), values)));
+    Ok(declared)
+}
+
+/// Splits `text` on `sep`, trimming the values and leaving out the empty ones.
+fn split_values(text: &str, sep: &str) -> Vec<Value> {
+    text.split(sep).map(str::trim).filter(|v| !v.is_empty()).map(Value::from).collect()
+}
+
+/// Splits `stream` on the commas that aren't inside a group.
+fn split_top_tokens(stream: proc_macro::TokenStream) -> Vec<Vec<TokenTree>> {
+    let mut parts = vec![Vec::new()];
+    for tt in stream {
+        match tt {
+            TokenTree::Punct(p) if p.as_char() == ',' => parts.push(Vec::new()),
+            tt => parts.last_mut().unwrap().push(tt),
+        }
+    }
+    parts.retain(|part| !part.is_empty());
+    parts
+}
+
+/// Reads the file at `path`, relative to the crate root or, if `from_invocation` is set, to the file of the invocation.
+///
+/// `span` is the one of the path, where the errors are shown.
+fn read_file(path: &str, from_invocation: bool, span: proc_macro::Span) -> Result<(std::path::PathBuf, String), Error> {
+    let invocation_dir = proc_macro::Span::call_site().local_file().and_then(|file| Some(file.parent()?.to_owned()));
+    let crate_dir = std::path::PathBuf::from(std::env::var("CARGO_MANIFEST_DIR").unwrap_or_default());
+    let full = match invocation_dir {
+        Some(dir) if from_invocation && dir.is_dir() => dir.join(path),
+        _ => crate_dir.join(path),
+    };
+    let text = std::fs::read_to_string(&full).map_err(|e| Error::new(span, format!("akin: can't read '{}': {}", full.display(), e)))?;
+    READ_FILES.with(|files| files.borrow_mut().push(full.clone()));
+    Ok((full, text))
+}
+
+/// A value read from a JSON or TOML data file.
+enum Data {
+    /// A string, number or boolean, holding its text without quotes.
+    Value(String),
+    Array(Vec<Data>),
+    Table(Vec<(String, Data)>),
+}
+
+impl Data {
+    /// The elements of an array, or the value itself otherwise.
+    fn elements(&self) -> Vec<&Data> {
+        match self {
+            Data::Array(elements) => elements.iter().collect(),
+            d => vec![d],
+        }
+    }
+
+    fn get(&self, key: &str) -> Option<&Data> {
+        match self {
+            Data::Table(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, d)| d),
+            _ => None,
+        }
+    }
+
+    /// Pushes the fields of a table as record fields, with nested tables as `field.inner`.
+    fn fields(&self, prefix: &str, out: &mut Vec<(String, String)>) {
+        let Data::Table(entries) = self else { return };
+        for (key, value) in entries {
+            let key = format!("{prefix}{key}");
+            value.fields(&format!("{key}."), out);
+            out.push((key, value.to_string()));
+        }
+    }
+}
+
+impl std::fmt::Display for Data {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Data::Value(v) => write!(f, "{v}"),
+            Data::Array(elements) => {
+                let elements: Vec<String> = elements.iter().map(Data::to_string).collect();
+                write!(f, "{}", elements.join(", "))
+            }
+            Data::Table(entries) => {
+                let entries: Vec<String> = entries.iter().map(|(k, v)| format!("{k}: {v}")).collect();
+                write!(f, "{}", entries.join(", "))
+            }
+        }
+    }
+}
+
+/// Reads a JSON or TOML data file, relative to the crate root.
+///
+/// Malformed files are rejected rather than read as far as possible, with the line of the mistake in the error.
+fn read_data(path: &str, span: proc_macro::Span) -> Result<Data, Error> {
+    let (full, text) = read_file(path, false, span)?;
+    let mut rest = text.as_str();
+    let parsed = match full.extension().and_then(|e| e.to_str()) {
+        Some("json") => json::parse_json(&mut rest).and_then(|d| {
+            rest = rest.trim_start();
+            if rest.is_empty() { Ok(d) } else { Err(format!("expected end of file after the value, got {}", found(rest))) }
+        }),
+        Some("toml") => toml::parse_toml(&mut rest),
+        _ => return Err(Error::new(span, format!("akin: unsupported data file '{}', expected a '.json' or '.toml' file", path))),
+    };
+    parsed.map_err(|e| {
+        let line = text[..text.len() - rest.len()].matches('\n').count() + 1;
+        Error::new(span, format!("akin: can't parse '{}' at line {}: {}", path, line, e))
+    })
+}
+
+/// Describes what a data file has at the start of `text`, for its parse errors.
+fn found(text: &str) -> String {
+    match text.lines().next().map(str::trim_end) {
+        Some("") => "end of line".to_owned(),
+        Some(line) => format!("'{line}'"),
+        None => "end of file".to_owned(),
+    }
+}
+
+/// Adds `key` to the entries of a table, which can't have it already.
+fn insert(entries: &mut Vec<(String, Data)>, key: &str, value: Data) -> Result<(), String> {
+    if entries.iter().any(|(k, _)| k == key) {
+        return Err(format!("duplicate key '{key}'"));
+    }
+    entries.push((key.to_owned(), value));
+    Ok(())
+}
+
+/// Parses a string in double quotes `"..."` or single quotes `'...'`, advancing `text` past it.
+///
+/// `escapes` are the characters that can follow a `\` in double quotes, of which `u` and `U` take 4 and 8 hex digits.
+fn parse_quoted(text: &mut &str, escapes: &str) -> Result<String, String> {
+    let quote = text.chars().next().filter(|&c| c == '"' || c == '\'').ok_or_else(|| format!("expected string, got {}", found(text)))?;
+    let mut out = String::new();
+    let mut chars = text[1..].char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c == quote => {
+                *text = &text[i + 2..];
+                return Ok(out);
+            }
+            '\n' => break,
+            '\\' if quote == '"' => match chars.next().map(|(_, c)| c).filter(|c| escapes.contains(*c)) {
+                Some('n') => out.push('\n'),
+                Some('r') => out.push('\r'),
+                Some('t') => out.push('\t'),
+                Some('b') => out.push('\u{8}'),
+                Some('f') => out.push('\u{c}'),
+                Some(u @ ('u' | 'U')) => {
+                    let len = if u == 'u' { 4 } else { 8 };
+                    let hex: String = chars.by_ref().take(len).map(|(_, c)| c).collect();
+                    let mut code = u32::from_str_radix(&hex, 16).ok().filter(|_| hex.len() == len && !hex.starts_with('+'));
+                    // Characters outside the Basic Multilingual Plane are written in JSON as a pair of surrogates
+                    if let (Some(high @ 0xD800..=0xDBFF), 'u') = (code, u) {
+                        let low: String = chars.by_ref().take(6).map(|(_, c)| c).collect();
+                        let low = low.strip_prefix("\\u").and_then(|hex| u32::from_str_radix(hex, 16).ok()).filter(|low| (0xDC00..=0xDFFF).contains(low));
+                        code = low.map(|low| 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00));
+                    }
+                    out.push(code.and_then(char::from_u32).ok_or_else(|| format!("invalid escape '\\{u}{hex}' in string"))?);
+                }
+                Some(c) => out.push(c),
+                None => return Err(format!("invalid escape in string, expected one of '{escapes}' after '\\'")),
+            },
+            c => out.push(c),
+        }
+    }
+    Err("unterminated string".to_owned())
+}
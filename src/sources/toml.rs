@@ -0,0 +1,223 @@
+//! TOML data files read by `file("codes.toml", "opcodes.name")`.
+//!
+//! Only the subset of TOML used for data tables is read: tables, arrays of tables, dotted keys,
+//! and strings, numbers, booleans, dates, arrays and inline tables as values.
+//! Files that aren't valid TOML are rejected, like one with a repeated key:
+//!
+//! ```compile_fail
+//! akin::akin! {
+//!     let &name = file("tests/data/invalid/duplicate_key.toml", "opcodes.name");
+//!     fn *name() {}
+//! }
+//! ```
+//!
+//! ```compile_fail
+//! akin::akin! {
+//!     let &name = file("tests/data/invalid/two_values.toml", "opcodes.name");
+//!     fn *name() {}
+//! }
+//! ```
+
+use super::{found, insert, parse_quoted, Data};
+
+/// Characters that can follow a `\` in a TOML basic string.
+const ESCAPES: &str = "\"\\bfnrtuU";
+
+/// Parses a TOML document, advancing `text` up to the mistake if it's malformed.
+pub(super) fn parse_toml(text: &mut &str) -> Result<Data, String> {
+    let mut root = Data::Table(Vec::new());
+    let mut current: Vec<String> = Vec::new();
+    // Tables defined by a header, which can't be defined again
+    let mut defined: Vec<Vec<String>> = Vec::new();
+    loop {
+        skip_toml_space(text, true);
+        if text.is_empty() {
+            return Ok(root);
+        }
+        if let Some(header) = text.strip_prefix("[[") {
+            *text = header;
+            current = parse_toml_key(text)?;
+            *text = text.strip_prefix("]]").ok_or_else(|| format!("expected ']]' after table name, got {}", found(text)))?;
+            let (last, parent) = current.split_last().unwrap();
+            let table = toml_table(&mut root, parent)?;
+            match table.iter_mut().find(|(k, _)| k == last) {
+                Some((_, Data::Array(elements))) => elements.push(Data::Table(Vec::new())),
+                Some(_) => return Err(format!("key '{last}' is not an array of tables")),
+                None => table.push((last.clone(), Data::Array(vec![Data::Table(Vec::new())]))),
+            }
+        } else if let Some(header) = text.strip_prefix('[') {
+            *text = header;
+            current = parse_toml_key(text)?;
+            *text = text.strip_prefix(']').ok_or_else(|| format!("expected ']' after table name, got {}", found(text)))?;
+            if defined.contains(&current) {
+                return Err(format!("table '{}' is defined twice", current.join(".")));
+            }
+            defined.push(current.clone());
+            toml_table(&mut root, &current)?;
+        } else {
+            let key = parse_toml_key(text)?;
+            *text = text.strip_prefix('=').ok_or_else(|| format!("expected '=' after key '{}', got {}", key.join("."), found(text)))?;
+            let value = parse_toml_value(text)?;
+            let (last, parent) = key.split_last().unwrap();
+            let path: Vec<String> = current.iter().chain(parent).cloned().collect();
+            insert(toml_table(&mut root, &path)?, last, value)?;
+        }
+        // Every key/value pair and header is on its own line
+        skip_toml_space(text, false);
+        if !text.is_empty() && !text.starts_with(['\n', '\r']) {
+            return Err(format!("expected newline, got {}", found(text)));
+        }
+    }
+}
+
+/// Skips whitespace and comments, and newlines too if `newlines` is set.
+fn skip_toml_space(text: &mut &str, newlines: bool) {
+    loop {
+        *text = text.trim_start_matches(|c: char| c.is_whitespace() && (newlines || c != '\n'));
+        match text.strip_prefix('#') {
+            Some(comment) => *text = &comment[comment.find('\n').unwrap_or(comment.len())..],
+            None => return,
+        }
+    }
+}
+
+/// Parses a dotted key like `a."b.c".d` from the start of `text`, advancing it past the key.
+fn parse_toml_key(text: &mut &str) -> Result<Vec<String>, String> {
+    let mut parts = Vec::new();
+    loop {
+        *text = text.trim_start_matches([' ', '\t']);
+        if text.starts_with(['"', '\'']) {
+            parts.push(parse_quoted(text, ESCAPES)?);
+        } else {
+            // Bare keys are made of ASCII letters, digits, `_` and `-`
+            let end = text.find(|c: char| !c.is_ascii_alphanumeric() && c != '_' && c != '-').unwrap_or(text.len());
+            if end == 0 {
+                return Err(format!("expected key, got {}", found(text)));
+            }
+            parts.push(text[..end].to_owned());
+            *text = &text[end..];
+        }
+        *text = text.trim_start_matches([' ', '\t']);
+        match text.strip_prefix('.') {
+            Some(rest) => *text = rest,
+            None => return Ok(parts),
+        }
+    }
+}
+
+fn parse_toml_value(text: &mut &str) -> Result<Data, String> {
+    *text = text.trim_start_matches([' ', '\t']);
+    for quotes in ["\"\"\"", "'''"] {
+        if let Some(rest) = text.strip_prefix(quotes) {
+            // Multi-line strings, trimming the newline right after the opening quotes
+            let end = rest.find(quotes).ok_or("unterminated multi-line string")?;
+            let value = rest[..end].strip_prefix('\n').unwrap_or(&rest[..end]).to_owned();
+            *text = &rest[end + 3..];
+            return Ok(Data::Value(value));
+        }
+    }
+    match text.chars().next() {
+        Some('"' | '\'') => parse_quoted(text, ESCAPES).map(Data::Value),
+        Some('[') => {
+            *text = &text[1..];
+            let mut elements = Vec::new();
+            loop {
+                skip_toml_space(text, true);
+                if let Some(rest) = text.strip_prefix(']') {
+                    *text = rest;
+                    return Ok(Data::Array(elements));
+                }
+                elements.push(parse_toml_value(text)?);
+                // Arrays can span several lines, and end with a `,`
+                skip_toml_space(text, true);
+                match text.strip_prefix(',') {
+                    Some(rest) => *text = rest,
+                    None if text.starts_with(']') => {}
+                    None => return Err(format!("expected ',' or ']' in array, got {}", found(text))),
+                }
+            }
+        }
+        Some('{') => {
+            *text = &text[1..];
+            let mut table = Data::Table(Vec::new());
+            *text = text.trim_start_matches([' ', '\t']);
+            if let Some(rest) = text.strip_prefix('}') {
+                *text = rest;
+                return Ok(table);
+            }
+            loop {
+                let key = parse_toml_key(text)?;
+                *text = text.strip_prefix('=').ok_or_else(|| format!("expected '=' after key '{}', got {}", key.join("."), found(text)))?;
+                let value = parse_toml_value(text)?;
+                let (last, parent) = key.split_last().unwrap();
+                insert(toml_table(&mut table, parent)?, last, value)?;
+                // Inline tables are on a single line, without a `,` after the last entry
+                *text = text.trim_start_matches([' ', '\t']);
+                match text.chars().next() {
+                    Some(',') => *text = &text[1..],
+                    Some('}') => {
+                        *text = &text[1..];
+                        return Ok(table);
+                    }
+                    _ => return Err(format!("expected ',' or '}}' in inline table, got {}", found(text))),
+                }
+            }
+        }
+        Some(_) => {
+            let end = text.find(|c: char| c.is_whitespace() || ",]}#".contains(c)).unwrap_or(text.len());
+            let value = &text[..end];
+            if !is_bare_value(value) {
+                return Err(format!("expected value, got {}", found(text)));
+            }
+            *text = &text[end..];
+            Ok(Data::Value(value.to_owned()))
+        }
+        None => Err("unexpected end of file".to_owned()),
+    }
+}
+
+/// Whether `value` is a TOML value that isn't quoted: a boolean, a number like `0x1F` or `-1_000.5`, or a date or time.
+fn is_bare_value(value: &str) -> bool {
+    let unsigned = value.strip_prefix(['+', '-']).unwrap_or(value);
+    let digits = value.replace('_', "");
+    let radix = [("0x", 16), ("0o", 8), ("0b", 2)].into_iter().find_map(|(prefix, radix)| Some((digits.strip_prefix(prefix)?, radix)));
+    // Dates like `1979-05-27T07:32:00Z` and times like `07:32:00`
+    let is_date = (value.get(4..5) == Some("-") || value.get(2..3) == Some(":"))
+        && value.bytes().all(|b| b.is_ascii_digit() || b"-:.TZtz+".contains(&b));
+    match radix {
+        Some((digits, radix)) => u64::from_str_radix(digits, radix).is_ok() && !digits.starts_with('+'),
+        None => {
+            matches!(value, "true" | "false")
+                || matches!(unsigned, "inf" | "nan")
+                || is_date
+                || (digits.bytes().all(|b| b.is_ascii_digit() || b"+-.eE".contains(&b)) && digits.parse::<f64>().is_ok())
+        }
+    }
+}
+
+/// Returns the entries of the table at `path` from `root`, creating the missing tables.
+///
+/// Arrays of tables are entered through their last element, the one being defined.
+fn toml_table<'d>(root: &'d mut Data, path: &[String]) -> Result<&'d mut Vec<(String, Data)>, String> {
+    let mut data = root;
+    for key in path {
+        let Data::Table(entries) = data else {
+            return Err(format!("key '{key}' is not inside a table"));
+        };
+        let pos = match entries.iter().position(|(k, _)| k == key) {
+            Some(pos) => pos,
+            None => {
+                entries.push((key.clone(), Data::Table(Vec::new())));
+                entries.len() - 1
+            }
+        };
+        data = &mut entries[pos].1;
+        if let Data::Array(elements) = data {
+            data = elements.last_mut().ok_or_else(|| format!("key '{key}' is an empty array"))?;
+        }
+    }
+    match data {
+        Data::Table(entries) => Ok(entries),
+        _ => Err(format!("key '{}' is not a table", path.join("."))),
+    }
+}
@@ -1,3 +1,7 @@
+#![allow(clippy::vec_init_then_push)]
+// Some tests declare variables they don't use on purpose, which akin warns about as the use of a deprecated item
+#![allow(deprecated)]
+
 #[cfg(test)]
 use akin::akin;
 
@@ -21,6 +25,25 @@ fn list() {
     assert_eq!(res, "abcde");
 }
 
+#[test]
+fn generic_values() {
+    use std::any::type_name;
+    use std::collections::HashMap;
+
+    let mut types = Vec::new();
+    let mut cmps = Vec::new();
+    akin! {
+        let &ty = [Vec<u8>, HashMap<u8, Vec<u16>>, (u8, u16), fn(u8, u8) -> Option<u8>];
+        types.push(type_name::<*ty>());
+    }
+    akin! {
+        let &cmp = [1 < 2, 3 > 4, 2 >= 1, 1 <= 0];
+        cmps.push(*cmp);
+    }
+    assert_eq!(types, [type_name::<Vec<u8>>(), type_name::<HashMap<u8, Vec<u16>>>(), type_name::<(u8, u16)>(), type_name::<fn(u8, u8) -> Option<u8>>()]);
+    assert_eq!(cmps, [true, false, true, false]);
+}
+
 #[test]
 fn _match() {
     let test = |val| {
@@ -237,6 +260,73 @@ fn zero_tokens() {
     akin! {}
 }
 
+#[test]
+fn no_vars_used() {
+    let mut v = Vec::new();
+    let x = &3;
+    akin! {
+        let &a = [1, 2];
+        v.push(line!());
+    }
+    akin! {
+        let &a = [1, 2];
+        v.push(*x);
+    }
+    assert_eq!(v, [line!() - 6, 3]);
+}
+
+#[test]
+fn macro_rules_fragments() {
+    macro_rules! scaled {
+        ($v:ident, $ty:ty, $scale:expr, $($value:expr),*) => {
+            akin! {
+                let &value = [$($value),*];
+                $v.push(*value as $ty * $scale);
+            }
+        };
+    }
+    let mut v = Vec::new();
+    scaled!(v, u64, 10, 1, 2u8, 3);
+    assert_eq!(v, [10, 20, 30]);
+}
+
+#[test]
+fn macro_rules_repeated_names() {
+    // Both `v` are written the same, but the first is the caller's vector and the second a local of the macro
+    macro_rules! push_offset {
+        ($v:ident) => {
+            let v = 10;
+            akin! {
+                let &n = [1, 2];
+                $v.push(*n + v);
+            }
+        };
+    }
+    let mut v = Vec::new();
+    push_offset!(v);
+    assert_eq!(v, [11, 12]);
+}
+
+#[test]
+fn macro_rules_definition() {
+    akin! {
+        // `&x` is only there to check `$x` isn't substituted
+        #!allow(unused)
+        let &name = [sum, product];
+        let &op = [+, *];
+        let &init = [0, 1];
+        let &x = [a, b];
+        macro_rules! *name {
+            ($($x:expr),* $(,)?) => {{
+                let total = *init;
+                $(let total = total *op $x;)*
+                { total }
+            }};
+        }
+    }
+    assert_eq!((sum!(1, 2, 3), product!(2, 3, 4,)), (6, 24));
+}
+
 #[test]
 fn one_token() {
     let x = akin! {
@@ -245,6 +335,32 @@ fn one_token() {
     assert_eq!(x, "test");
 }
 
+#[test]
+fn spacing() {
+    let mut v = Vec::new();
+    akin! {
+        let &call = [{ .pow(2) }, { .max(u32::MAX) }, { .clamp(1, 3)? }];
+        v.push("*call");
+    }
+    assert_eq!(v, [".pow(2)", ".max(u32::MAX)", ".clamp(1, 3)?"]);
+}
+
+#[test]
+fn raw_idents() {
+    struct Keywords {
+        r#type: u8,
+        r#fn: u8,
+    }
+    let k = Keywords { r#type: 1, r#fn: 2 };
+    let mut v = Vec::new();
+    akin! {
+        let &r#field = [r#type, r#fn];
+        fn get_~*r#field(k: &Keywords) -> u8 { k.*r#field }
+        v.push((get_~*r#field(&k), stringify!(*r#field), stringify!(*r#field:upper)));
+    }
+    assert_eq!(v, [(1, "r#type", "TYPE"), (2, "r#fn", "FN")]);
+}
+
 #[test]
 fn one_token_repeated() {
     let x = akin::akin! {
@@ -252,14 +368,14 @@ fn one_token_repeated() {
         let &b = {test~*x};
         "*b"
     };
-    assert_eq!(x, " test1 test2");
+    assert_eq!(x, "test1 test2");
 }
 
 #[test]
 fn modifier_carry_over_bug() {
     akin::akin! {
         let &x = [{1~} 2 {3}];
-        assert_eq!("*x", " 12 3");
+        assert_eq!("*x", "12 3");
     };
 }
 
@@ -289,7 +405,7 @@ fn range_expression() {
         let &y = {*x};
         "*y"
     };
-    assert_eq!(x, " 1 2 3 4 5 6 7 8 9");
+    assert_eq!(x, "1 2 3 4 5 6 7 8 9");
 }
 
 #[test]
@@ -301,3 +417,1763 @@ fn range_expression_inclusive() {
     };
     assert_eq!(x, [1, 2, 3, 4, 5, 6, 7, 8, 9, 10]);
 }
+
+#[test]
+fn record() {
+    akin! {
+        let &method = [
+            {name: into_bool, ty: bool, value: true},
+            {name: into_vec, ty: Vec<u8>, value: { vec![1, 2] }}
+        ];
+        fn *method.name() -> *method.ty {
+            *method.value
+        }
+    }
+    assert!(into_bool());
+    assert_eq!(into_vec(), [1, 2]);
+}
+
+#[test]
+fn record_missing_field() {
+    let x = akin! {
+        let &v = [{a: 1, b: 2}, {a: 3}, {b: NONE}];
+        let &s = {"*v.a:*v.b",};
+        [*s]
+    };
+    assert_eq!(x, ["1:2", "3:", ":"]);
+}
+
+#[test]
+fn record_whole_value() {
+    akin! {
+        let &field = [{id: u32}, {name: String}];
+        let &fields = { *field, };
+        struct Row {
+            *fields
+        }
+        let row = Row { id: 1, name: String::new() };
+        assert_eq!(row.id, 1);
+        assert!(row.name.is_empty());
+    }
+}
+
+#[test]
+fn for_loop() {
+    struct Num<T>(T);
+
+    akin! {
+        let &ty = [u8, i32];
+        impl Num<*ty> {
+            for &op in [{f: add, t: Add}, {f: sub, t: Sub}, {f: mul, t: Mul}] {
+                fn *op.f(&self, rhs: *ty) -> *ty {
+                    std::ops::*op.t::*op.f(self.0, rhs)
+                }
+            }
+        }
+    }
+
+    assert_eq!(Num(2u8).add(3), 5);
+    assert_eq!(Num(2i32).sub(3), -1);
+    assert_eq!(Num(4i32).mul(3), 12);
+}
+
+#[test]
+fn for_loop_nested() {
+    let mut v = Vec::new();
+    akin! {
+        let &x = [1, 2];
+        for &y in [a, b] {
+            for &z in [{*x}, {*x~0}] {
+                v.push("*x*y*z");
+            }
+        }
+    }
+    assert_eq!(v, ["1a1", "1a10", "1b1", "1b10", "2a2", "2a20", "2b2", "2b20"]);
+}
+
+#[test]
+fn product() {
+    let mut v = Vec::new();
+    akin! {
+        #!product
+        let &a = [1, 2, 3];
+        let &b = [x, y];
+        v.push("*a*b");
+    }
+    assert_eq!(v, ["1x", "1y", "2x", "2y", "3x", "3y"]);
+}
+
+#[test]
+fn product_records() {
+    let mut v = Vec::new();
+    akin! {
+        #!product
+        let &t = [{name: u8, bits: 8}, {name: u16, bits: 16}];
+        let &sign = [+, -];
+        v.push("*sign*t.name = *t.bits");
+    }
+    assert_eq!(v, ["+u8 = 8", "+u16 = 16", "-u8 = 8", "-u16 = 16"]);
+}
+
+#[test]
+fn product_conversions() {
+    trait Widen<T> {
+        fn widen(self) -> T;
+    }
+
+    akin! {
+        #!product
+        let &from = [u8, u16];
+        let &to = [u32, u64];
+        impl Widen<*to> for *from {
+            fn widen(self) -> *to {
+                self.into()
+            }
+        }
+    }
+
+    assert_eq!(Widen::<u32>::widen(5u8), 5u32);
+    assert_eq!(Widen::<u64>::widen(5u8), 5u64);
+    assert_eq!(Widen::<u32>::widen(5u16), 5u32);
+    assert_eq!(Widen::<u64>::widen(5u16), 5u64);
+}
+
+#[test]
+fn builtin_index() {
+    let mut v = Vec::new();
+    akin! {
+        let &name = [a, b, c];
+        let &arm = { *INDEX => "*name", };
+        fn name_of(i: usize) -> &'static str {
+            match i {
+                *arm
+                _ => "",
+            }
+        }
+    }
+    akin! {
+        let &name = [a, b, c];
+        v.push((*INDEX, "*name"));
+    }
+    assert_eq!(v, [(0, "a"), (1, "b"), (2, "c")]);
+    assert_eq!((name_of(0), name_of(2), name_of(3)), ("a", "c", ""));
+}
+
+#[test]
+fn builtin_index_shadowed() {
+    let x = akin! {
+        let &INDEX = [7];
+        *INDEX
+    };
+    assert_eq!(x, 7);
+}
+
+#[test]
+fn builtin_count() {
+    trait Table {
+        const LEN: usize;
+        const POS: usize;
+    }
+
+    akin! {
+        let &ty = [u8, u16, u32];
+        impl Table for *ty {
+            const LEN: usize = *COUNT;
+            const POS: usize = *INDEX;
+        }
+    }
+    assert_eq!((u8::LEN, u16::LEN, u32::LEN), (3, 3, 3));
+    assert_eq!((u8::POS, u16::POS, u32::POS), (0, 1, 2));
+
+    let x = akin! {
+        #!product
+        let &a = [1, 2, 3];
+        let &b = [4, 5];
+        let &c = { (*a, *b, *COUNT), };
+        [*c]
+    };
+    assert_eq!(x, [(1, 4, 6), (1, 5, 6), (2, 4, 6), (2, 5, 6), (3, 4, 6), (3, 5, 6)]);
+}
+
+#[test]
+fn builtin_first_last() {
+    let mut out = String::new();
+    akin! {
+        let &item = [a, b, c];
+        if *FIRST {
+            out.push('[');
+        }
+        out.push_str("*item");
+        out.push_str(if *LAST { "]" } else { ", " });
+    }
+    assert_eq!(out, "[a, b, c]");
+
+    let x = akin! {
+        let &v = [NONE];
+        [(*FIRST, *LAST)]
+    };
+    assert_eq!(x, [(true, true)]);
+}
+
+#[test]
+fn expansion_match() {
+    trait Bits {
+        fn kind() -> &'static str;
+    }
+
+    akin! {
+        let &ty = [u8, u16, i32, f64, Vec<u8>];
+        impl Bits for *ty {
+            fn kind() -> &'static str {
+                @match *ty {
+                    u8 | u16 => { "small" },
+                    i32 => "signed",
+                    Vec<u8> => { "bytes" }
+                    _ => { "other" },
+                }
+            }
+        }
+    }
+
+    assert_eq!(u8::kind(), "small");
+    assert_eq!(u16::kind(), "small");
+    assert_eq!(i32::kind(), "signed");
+    assert_eq!(f64::kind(), "other");
+    assert_eq!(<Vec<u8>>::kind(), "bytes");
+}
+
+#[test]
+fn expansion_match_builtin() {
+    let x = akin! {
+        let &item = [a, b, c];
+        let &list = {
+            "*item"
+            @match *LAST {
+                false => { , },
+            }
+        };
+        [*list]
+    };
+    assert_eq!(x, ["a", "b", "c"]);
+
+    let mut v = Vec::new();
+    akin! {
+        let &n = [1, 2, 3];
+        for &m in [1, 2] {
+            @match *n {
+                2 => {},
+                _ => { v.push(*n * 10 + *m); }
+            }
+        }
+    }
+    assert_eq!(v, [11, 12, 31, 32]);
+}
+
+#[test]
+fn skip() {
+    trait Signed {
+        type Unsigned;
+    }
+
+    akin! {
+        let &ty = [i8, i16, f32, i32];
+        let &unsigned = [u8, u16, SKIP, u32];
+        impl Signed for *ty {
+            type Unsigned = *unsigned;
+        }
+    }
+
+    let _: <i16 as Signed>::Unsigned = 1u16;
+    let _: <i32 as Signed>::Unsigned = 1u32;
+
+    let x = akin! {
+        let &a = [1, 2, 3, 4];
+        let &b = [x, SKIP, y, {SKIP}];
+        let &pair = { ("*a", "*b"), };
+        [*pair]
+    };
+    assert_eq!(x, [("1", "x"), ("3", "y"), ("4", "SKIP")]);
+}
+
+#[test]
+fn strict() {
+    let mut v = Vec::new();
+    akin! {
+        #!strict
+        let &a = [1, 2, 3];
+        let &b = [4, 5, 6];
+        let &sum = { *a + *b, };
+        v.push([*a, *b, [*sum][*INDEX]]);
+    }
+    assert_eq!(v, [[1, 4, 5], [2, 5, 7], [3, 6, 9]]);
+}
+
+#[test]
+fn forward_reference() {
+    let mut v = Vec::new();
+    akin! {
+        let &msg = [{*greet, *who}];
+        let &rec = [{name: *who}];
+        let &greet = [hi, bye];
+        let &who = [*name];
+        let &name = [world];
+        v.push(("*msg", "*rec.name"));
+    }
+    assert_eq!(v, [("hi, world bye, world", "world")]);
+}
+
+#[test]
+fn redeclared_var() {
+    let mut v = Vec::new();
+    akin! {
+        let &a = [1];
+        let &a = [*a, 2];
+        v.push(*a);
+    }
+    assert_eq!(v, [1, 2]);
+}
+
+#[test]
+fn max_copies() {
+    let mut v = Vec::new();
+    akin! {
+        #!max_copies(4)
+        #!max_bytes(1_000)
+        #!product
+        let &a = [1, 2];
+        let &b = [10, 20];
+        v.push(*a + *b);
+    }
+    assert_eq!(v, [11, 21, 12, 22]);
+}
+
+#[test]
+fn repeat() {
+    let mut v = Vec::new();
+    akin! {
+        #!repeat(4)
+        let &name = [a, b];
+        v.push((*INDEX, "*name"));
+    }
+    assert_eq!(v, [(0, "a"), (1, "b"), (2, "b"), (3, "b")]);
+
+    akin! {
+        #!repeat(3)
+        fn reg_~*INDEX() -> usize {
+            *INDEX * *COUNT
+        }
+    }
+    assert_eq!((reg_0(), reg_1(), reg_2()), (0, 3, 6));
+}
+
+#[test]
+fn block_scope() {
+    let mut v = Vec::new();
+    akin! {
+        let &t = [a, b];
+        let &n = [1];
+        {
+            let &t = [x, y, z];
+            let &u = [{*n~0}];
+            v.push("*t*u");
+        }
+        v.push("*t");
+    }
+    assert_eq!(v, ["x10", "y10", "z10", "a", "x10", "y10", "z10", "b"]);
+}
+
+#[test]
+fn block_scope_rust_let() {
+    let mut v = Vec::new();
+    let r = &1;
+    akin! {
+        let &n = [2, 3];
+        {
+            let &x = r;
+            v.push(x * *n);
+        }
+    }
+    assert_eq!(v, [2, 3]);
+}
+
+#[test]
+fn block_scope_outer_values() {
+    use std::num::Wrapping;
+    trait Double {
+        fn double(self) -> Self;
+    }
+
+    akin! {
+        let &ty = [u8, u16];
+        {
+            let &ty = [{Wrapping<*ty>}, *ty];
+            impl Double for *ty {
+                fn double(self) -> Self {
+                    self + self
+                }
+            }
+        }
+    }
+
+    assert_eq!(Wrapping(200u8).double(), Wrapping(144));
+    assert_eq!(Wrapping(2u16).double(), Wrapping(4));
+    assert_eq!(3u8.double(), 6);
+    assert_eq!(3u16.double(), 6);
+}
+
+#[test]
+fn cumulative() {
+    trait Arity {
+        const ARITY: usize;
+    }
+
+    akin! {
+        let &T = cumulative [A, B, C, D];
+        let &n = 1..=4;
+        impl<*T> Arity for (*T,) {
+            const ARITY: usize = *n;
+        }
+    }
+
+    assert_eq!(<(u8,)>::ARITY, 1);
+    assert_eq!(<(u8, u16, u32)>::ARITY, 3);
+    assert_eq!(<((), (), (), ())>::ARITY, 4);
+
+    let mut v = Vec::new();
+    akin! {
+        let &sum = cumulative 1..4;
+        let &none = cumulative [1, NONE, 3];
+        v.push([*sum].iter().sum::<i32>() * 10 + [*none].len() as i32);
+    }
+    assert_eq!(v, [11, 31, 62]);
+}
+
+#[test]
+fn pairs() {
+    trait Common<T> {
+        type Output;
+    }
+
+    akin! {
+        let &(small, big) = pairs [u8, u16, u32];
+        impl Common<*small> for *big {
+            type Output = *big;
+        }
+    }
+
+    let _: <u16 as Common<u8>>::Output = 1u16;
+    let _: <u32 as Common<u32>>::Output = 1u32;
+
+    let mut v = Vec::new();
+    akin! {
+        let &(a, b) = pairs [{n: 1, s: one}, {n: 2, s: two}];
+        v.push((*a.n, "*b.s"));
+    }
+    assert_eq!(v, [(1, "one"), (1, "two"), (2, "two")]);
+}
+
+#[test]
+fn product_except() {
+    trait Convert<T> {
+        fn convert(self) -> T;
+    }
+
+    akin! {
+        #!product except [(u8, u8), (u16, u16), (u64, u64)]
+        let &from = [u8, u16];
+        let &to = [u8, u16, u64];
+        impl Convert<*to> for *from {
+            fn convert(self) -> *to {
+                self as *to
+            }
+        }
+    }
+
+    assert_eq!(Convert::<u16>::convert(3u8), 3u16);
+    assert_eq!(Convert::<u64>::convert(3u8), 3u64);
+    assert_eq!(Convert::<u8>::convert(3u16), 3u8);
+    assert_eq!(Convert::<u64>::convert(3u16), 3u64);
+
+    let mut v = Vec::new();
+    akin! {
+        #!product except [(Vec<u8>, 2)]
+        let &ty = [Vec<u8>, Vec<u16>];
+        let &n = [1, 2];
+        v.push(<*ty>::with_capacity(*n).capacity());
+    }
+    assert!(v.len() == 3 && v[0] >= 1 && v[1] >= 1 && v[2] >= 2);
+}
+
+#[test]
+fn next_value() {
+    #[derive(Debug, PartialEq)]
+    enum State {
+        Idle,
+        Running,
+        Done,
+    }
+
+    akin! {
+        let &state = [Idle, Running, Done] next Idle;
+        let &method = [from_idle, from_running, from_done];
+        impl State {
+            fn *method() -> State {
+                State::*state.next
+            }
+        }
+    }
+
+    assert_eq!(State::from_idle(), State::Running);
+    assert_eq!(State::from_running(), State::Done);
+    assert_eq!(State::from_done(), State::Idle);
+
+    let mut v = Vec::new();
+    akin! {
+        let &n = 1..4 next SKIP;
+        v.push(*n * 10 + *n.next);
+    }
+    assert_eq!(v, [12, 23]);
+}
+
+#[test]
+fn prev_value() {
+    struct V1(u8);
+    struct V2(u16);
+    struct V3(u32);
+
+    akin! {
+        let &version = [V1, V2, V3] prev SKIP;
+        impl From<*version.prev> for *version {
+            fn from(prev: *version.prev) -> Self {
+                Self(prev.0.into())
+            }
+        }
+    }
+
+    assert_eq!(V3::from(V2::from(V1(7))).0, 7);
+
+    let mut v = Vec::new();
+    akin! {
+        let &n = [1, 2, 3];
+        v.push(("*n.prev", *n));
+    }
+    assert_eq!(v, [("", 1), ("1", 2), ("2", 3)]);
+}
+
+#[test]
+fn step() {
+    let mut v = Vec::new();
+    akin! {
+        let &n = [0, 1, 2, 3, 4, 5, 6] step(2) next 0;
+        let &name = [{id: a}, {id: b}, {id: c}, {id: d}] step(3);
+        v.push((*n, *n.next, "*name.id"));
+    }
+    assert_eq!(v, [(0, 2, "a"), (2, 4, "d"), (4, 6, "d"), (6, 0, "d")]);
+}
+
+#[test]
+fn builtin_rindex() {
+    let mut v = Vec::new();
+    akin! {
+        let &bit = [a, b, c, d];
+        v.push(("*bit", 1u8 << *RINDEX, *INDEX + *RINDEX));
+    }
+    assert_eq!(v, [("a", 8, 3), ("b", 4, 3), ("c", 2, 3), ("d", 1, 3)]);
+}
+
+#[test]
+fn modifier_str() {
+    #[derive(Debug, PartialEq)]
+    enum Method {
+        Get,
+        Post,
+    }
+
+    akin! {
+        let &variant = [Get, Post];
+        let &arm = { *variant:str => Some(Method::*variant), };
+        fn parse(s: &str) -> Option<Method> {
+            match s {
+                *arm
+                _ => None,
+            }
+        }
+    }
+
+    assert_eq!(parse("Get"), Some(Method::Get));
+    assert_eq!(parse("Post"), Some(Method::Post));
+    assert_eq!(parse("Put"), None);
+
+    let mut v = Vec::new();
+    akin! {
+        let &field = [{name: id, ty: u32}, {name: tag, ty: &'static str}];
+        v.push((*field.name:str, *field.ty:str));
+    }
+    assert_eq!(v, [("id", "u32"), ("tag", "& 'static str")]);
+}
+
+#[test]
+fn modifier_struct_literal() {
+    #[derive(Debug, PartialEq)]
+    struct S {
+        a: u8,
+        b: &'static str,
+    }
+
+    // A space after ':' makes it a struct field, even if the value is named like a modifier
+    let (lower, str) = (1, "two");
+    let mut v = Vec::new();
+    akin! {
+        let &f = [a];
+        let &g = [b];
+        v.push(S { *f: lower, *g: str });
+        v.push(S { *f:lower: 3, *g: *g:str });
+    }
+    assert_eq!(v, [S { a: 1, b: "two" }, S { a: 3, b: "b" }]);
+}
+
+#[test]
+fn arithmetic() {
+    akin! {
+        let &reg = [CTRL, STATUS, DATA];
+        const *reg: usize = *{0x100 + INDEX * 4};
+    }
+    assert_eq!([CTRL, STATUS, DATA], [0x100, 0x104, 0x108]);
+
+    let mut v = Vec::new();
+    akin! {
+        let &n = [1u8, 2, 7];
+        let &shift = 1..4;
+        v.push(*{-(n << shift) % 5 + (n ^ 0b11) / 2});
+    }
+    assert_eq!(v, [-1, -3, 1]);
+}
+
+#[test]
+fn modifier_pad() {
+    akin! {
+        let &n = [7, 42, 105];
+        const REG_~*n:pad(3): i32 = *n;
+    }
+    assert_eq!([REG_007, REG_042, REG_105], [7, 42, 105]);
+
+    let mut v = Vec::new();
+    akin! {
+        let &n = [-7, 1234];
+        v.push(*n:pad(3));
+    }
+    assert_eq!(v, [-7, 1234]);
+}
+
+#[test]
+fn modifier_strip() {
+    struct Flags {
+        is_open: bool,
+        is_dirty: bool,
+        locked: bool,
+    }
+
+    akin! {
+        let &flag = [is_open, is_dirty, locked];
+        impl Flags {
+            fn *flag:strip_prefix(is_)(&self) -> bool {
+                self.*flag
+            }
+        }
+    }
+
+    akin! {
+        let &ty = [size_t, ssize_t];
+        const *ty:strip_suffix(_t): &str = "*ty";
+    }
+
+    let flags = Flags { is_open: true, is_dirty: false, locked: true };
+    assert!(flags.open() && !flags.dirty() && flags.locked());
+    assert_eq!([size, ssize], ["size_t", "ssize_t"]);
+}
+
+#[test]
+fn modifier_join() {
+    akin! {
+        let &ty = [u8, u16, NONE, u32];
+        type All = (*ty:join(", "));
+    }
+    let all: All = (1, 2, 3);
+    assert_eq!(all, (1u8, 2u16, 3u32));
+
+    let mut v = Vec::new();
+    akin! {
+        let &n = [1, 2, 3];
+        let &name = [a, b];
+        v.push(("*name", [*n:join(,)], *n:join(+)));
+    }
+    assert_eq!(v, [("a", [1, 2, 3], 6), ("b", [1, 2, 3], 6)]);
+}
+
+#[test]
+fn var_len() {
+    akin! {
+        let &ty = [u8, u16, u32];
+        let &size = { std::mem::size_of::<*ty>(), };
+        const SIZES: [usize; *ty.len] = [*size];
+    }
+    assert_eq!(SIZES, [1, 2, 4]);
+
+    let mut v = Vec::new();
+    akin! {
+        let &n = 0..10 step(4);
+        v.push(*n * 10 + *n.len);
+    }
+    assert_eq!(v, [3, 43, 83]);
+}
+
+#[test]
+fn indexed_access() {
+    trait Canonical {
+        type Canon;
+    }
+
+    akin! {
+        let &ty = [u32, u8, u16];
+        impl Canonical for *ty {
+            type Canon = *ty.[0];
+        }
+    }
+    let _: <u8 as Canonical>::Canon = 1u32;
+
+    const A: [i32; 2] = [1, 2];
+    const B: [i32; 2] = [3, 4];
+    let mut v = Vec::new();
+    akin! {
+        let &array = [A, B];
+        let &rec = [{id: 10}, {id: 20}];
+        v.push((*array[1], *rec.id.[1]));
+    }
+    assert_eq!(v, [(2, 20), (4, 20)]);
+}
+
+#[test]
+fn first_last() {
+    #[allow(dead_code)]
+    #[derive(Debug, PartialEq)]
+    enum Level {
+        Trace,
+        Info,
+        Error,
+    }
+
+    akin! {
+        let &level = [Trace, Info, Error];
+        impl Level {
+            const MIN: Level = Level::*level.first;
+            const MAX: Level = Level::*level.last;
+        }
+    }
+    assert_eq!((Level::MIN, Level::MAX), (Level::Trace, Level::Error));
+
+    let mut v = Vec::new();
+    akin! {
+        let &n = 1..=3 step(2);
+        v.push((*n, *n.last));
+    }
+    assert_eq!(v, [(1, 3), (3, 3)]);
+}
+
+#[test]
+fn accessor_method_calls() {
+    trait Prev {
+        fn prev(&self) -> i32;
+    }
+    impl Prev for i32 {
+        fn prev(&self) -> i32 {
+            self - 1
+        }
+    }
+
+    let a = [1, 2, 3];
+    let b = [4];
+    let (mut ia, mut ib) = (a.iter(), b.iter());
+    let mut v = Vec::new();
+    akin! {
+        let &arr = [a, b];
+        let &it = [ia, ib];
+        let &n = [5i32, 10i32];
+        v.push((*arr.len(), *it.next().copied(), *arr.first().copied(), *arr.last().copied(), *n.prev(), *arr.len));
+    }
+    assert_eq!(v, [(3, Some(1), Some(1), Some(3), 4, 2), (1, Some(4), Some(4), Some(4), 9, 2)]);
+}
+
+#[test]
+fn joint_left() {
+    akin! {
+        let &name = [foo, bar];
+        let &name_impl = [unused];
+        fn *name~_impl() -> &'static str {
+            "*name"
+        }
+    }
+
+    akin! {
+        let &code = { foo };
+        fn get_~*code~_code() -> u8 {
+            1
+        }
+    }
+
+    assert_eq!((foo_impl(), bar_impl()), ("foo", "bar"));
+    assert_eq!(get_foo_code(), 1);
+}
+
+#[test]
+fn ident_boundary() {
+    let mut v = Vec::new();
+    akin! {
+        let &foo = [a, b];
+        let &foobar = [c, d];
+        v.push(("*foo *foobar *foo_extra *foo2", "*foo~_extra"));
+    }
+    assert_eq!(v, [("a c *foo_extra *foo2", "a_extra"), ("b d *foo_extra *foo2", "b_extra")]);
+}
+
+#[test]
+fn escaped_var() {
+    let mut v = Vec::new();
+    let name = &5;
+    akin! {
+        let &name = [1, 2];
+        let &code = { *~name * 10 };
+        v.push(*name + *code + *~name);
+    }
+    assert_eq!(v, [56, 57]);
+}
+
+#[test]
+fn sigil() {
+    let mut v = Vec::new();
+    let x = &100;
+    akin! {
+        #!sigil($)
+        let &x = [1, 2, 3];
+        let &rec = [{n: 10}];
+        let &code = { *x * $rec.n };
+        v.push($code + ${x * 2} + $rec.n + $x:pad(2) + $x.[0] - [$x:join(,)].len());
+    }
+    assert_eq!(v, [1000 + 2 + 10 + 1 - 2, 1000 + 4 + 10 + 2 - 2, 1000 + 6 + 10 + 3 - 2]);
+}
+
+#[test]
+fn modifier_chain() {
+    akin! {
+        let &ty = [RawHTTPClient, RawFileServer2, raw_io_error];
+        const *ty:strip_prefix(Raw):snake:upper: &str = *ty:camel:str;
+    }
+    assert_eq!([HTTP_CLIENT, FILE_SERVER2, RAW_IO_ERROR], ["RawHttpClient", "RawFileServer2", "RawIoError"]);
+
+    let mut v = Vec::new();
+    akin! {
+        let &name = [is_open, is_dirty];
+        v.push(*name:strip_prefix(is_):upper:str:join(", "):str);
+    }
+    assert_eq!(v, ["\"OPEN\", \"DIRTY\""]);
+}
+
+#[test]
+fn doc_comment() {
+    let mut v = Vec::new();
+    akin! {
+        let &ty = [u8, u16];
+        let &name = ["byte", word];
+        v.push(stringify!(
+            /// Reads a *name, a `*ty`, number *INDEX.
+            fn read() {}
+        ));
+    }
+    assert_eq!(v, [
+        r#"#[doc = " Reads a \"byte\", a `u8`, number 0."] fn read(){}"#,
+        r#"#[doc = " Reads a word, a `u16`, number 1."] fn read(){}"#,
+    ]);
+}
+
+#[test]
+fn identical_values() {
+    let mut v = Vec::new();
+    akin! {
+        let &n = [10, 20];
+        let &list = [{ *n, }, { *n, }, { stringify!(*GENSYM), }, { stringify!(*GENSYM), }];
+        let &m = [30, 40];
+        let &other = [{ *m, }, { *m, }, {0}, {0}];
+        v.push(format!("{:?} {:?}", [*list], [*other]));
+    }
+    assert_eq!(v[..2], ["[10, 20] [30, 40]", "[10, 20] [30, 40]"]);
+    assert_ne!(v[2], v[3]);
+
+    // Expressions name their variables without `*`, and must still see the new values
+    let mut v = Vec::new();
+    akin! {
+        let &n = [1, 2];
+        let &a = [{ *{n * 10}, }];
+        let &n = [3, 4];
+        let &b = [{ *{n * 10}, }];
+        v.push(format!("{:?} {:?}", [*a], [*b]));
+    }
+    assert_eq!(v, ["[10, 20] [30, 40]"]);
+}
+
+#[test]
+fn builtin_gensym() {
+    let mut v = Vec::new();
+    akin! {
+        let &n = [1, 2];
+        const *GENSYM: i32 = *n;
+        const *GENSYM(double): i32 = *n * 2;
+        v.push((stringify!(*GENSYM), *GENSYM + *GENSYM(double)));
+    }
+    akin! {
+        #!repeat(2)
+        v.push((stringify!(*GENSYM), 0));
+    }
+
+    let names: std::collections::HashSet<_> = v.iter().map(|(name, _)| *name).collect();
+    assert_eq!(names.len(), 4);
+    assert!(names.iter().all(|name| name.starts_with("__akin_")));
+    assert_eq!(v[..2].iter().map(|(_, n)| *n).collect::<Vec<_>>(), [3, 6]);
+}
+
+#[test]
+fn modifier_radix() {
+    let mut v = Vec::new();
+    akin! {
+        let &n = [31, 0x10u8, -5];
+        v.push((stringify!(*n:hex), stringify!(*n:bin(8)), *n:hex(4)));
+    }
+    assert_eq!(v, [
+        ("0x1F", "0b00011111", 0x1F),
+        ("0x10", "0b00010000", 0x10),
+        ("-0x5", "-0b00000101", -5),
+    ]);
+}
+
+#[test]
+fn modifier_capitalize() {
+    #[derive(Debug, PartialEq)]
+    enum Value {
+        Bool(bool),
+        Array(Vec<Value>),
+    }
+
+    akin! {
+        let &kind = [bool, array];
+        let &ty = [bool, Vec<Value>];
+        impl Value {
+            fn into_~*kind(self) -> Option<*ty> {
+                match self {
+                    Value::*kind:capitalize(v) => Some(v),
+                    _ => None,
+                }
+            }
+        }
+    }
+
+    assert_eq!(Value::Bool(true).into_bool(), Some(true));
+    assert_eq!(Value::Array(vec![]).into_bool(), None);
+    assert_eq!(Value::Array(vec![Value::Bool(false)]).into_array(), Some(vec![Value::Bool(false)]));
+}
+
+#[test]
+fn modifier_ordinal_words() {
+    let mut v = Vec::new();
+    akin! {
+        let &n = [1, 2, 3, 11, 12, 22, 101, 113, -40, 1_000_021];
+        v.push((*n:ordinal:str, *n:words:str));
+    }
+    assert_eq!(v, [
+        ("1st", "one"),
+        ("2nd", "two"),
+        ("3rd", "three"),
+        ("11th", "eleven"),
+        ("12th", "twelve"),
+        ("22nd", "twenty-two"),
+        ("101st", "one hundred one"),
+        ("113th", "one hundred thirteen"),
+        ("-40th", "minus forty"),
+        ("1000021st", "one million twenty-one"),
+    ]);
+}
+
+#[test]
+fn arithmetic_between_vars() {
+    akin! {
+        let &field = [{name: LEN, offset: 0, size: 2}, {name: KIND, offset: 2, size: 1}, {name: DATA, offset: 3, size: 4}];
+        let &align = [2, 1, 4];
+        const *field.name: [usize; 2] = [*{field.offset + field.size}, *{(field.offset + align - 1) / align * align}];
+    }
+    assert_eq!([LEN, KIND, DATA], [[2, 0], [3, 2], [7, 4]]);
+
+    let mut v = Vec::new();
+    akin! {
+        let &n = [1, 2, 3] next 0 prev 0;
+        v.push(*{n * n.next - n.prev});
+    }
+    assert_eq!(v, [2, 5, -2]);
+}
+
+#[test]
+fn modifier_hash() {
+    let mut v = Vec::new();
+    akin! {
+        let &op = [+, -, <=];
+        v.push(*op:hash:str);
+    }
+    assert_eq!(v, ["29623686", "296226d4", "bcc1e4f5"]);
+
+    akin! {
+        let &op = [+, -];
+        fn check_~*op:hash(a: i32, b: i32) -> i32 {
+            a *op b
+        }
+    }
+    assert_eq!(check_29623686(3, 2), 5);
+    assert_eq!(check_296226d4(3, 2), 1);
+}
+
+#[test]
+fn data_file() {
+    akin! {
+        let &op = file("tests/data/opcodes.toml", "opcodes");
+        const *op.name: u8 = *op.code;
+    }
+    assert_eq!([NOP, PUSH, ADD], [0x00, 0x01, 0x10]);
+
+    let mut v = Vec::new();
+    akin! {
+        let &name = file("tests/data/opcodes.toml", "opcodes.name");
+        let &count = file("tests/data/opcodes.toml", "opcodes.args.count");
+        v.push((*name:str, *count:str));
+    }
+    assert_eq!(v, [("NOP", ""), ("PUSH", "1"), ("ADD", "")]);
+
+    let mut v = Vec::new();
+    akin! {
+        let &error = file("tests/data/errors.json", "errors");
+        let &level = file("tests/data/errors.json", "levels");
+        v.push((*error.code, *error.name:snake:str, *error.message:str, *level:str));
+    }
+    assert_eq!(v, [(404, "not_found", "not found", "Debug"), (418, "teapot", "I'm a teapot", "Info"), (500, "internal", "", "Warn")]);
+}
+
+#[test]
+fn csv_file() {
+    akin! {
+        let &(name, code) = csv("tests/data/errors.csv", columns = [0, 2], header = true);
+        const *name: u16 = *code;
+    }
+    assert_eq!([NotFound, Teapot, Internal], [404, 418, 500]);
+
+    let mut v = Vec::new();
+    akin! {
+        let &error = csv("tests/data/errors.csv", columns = ["code", "message"], header = true);
+        let &code = csv("tests/data/errors.csv", columns = [2], header = true);
+        v.push((*error.code, *error.message:str, *code));
+    }
+    assert_eq!(v, [
+        (404, "not found", 404),
+        (418, "I'm a \"teapot\", short and stout", 418),
+        (500, "", 500),
+    ]);
+}
+
+#[test]
+fn env_var() {
+    let mut v = Vec::new();
+    akin! {
+        let &part = env("CARGO_PKG_VERSION", split = ".");
+        let &name = env("CARGO_PKG_NAME");
+        v.push((*part:str, *name:str));
+    }
+    assert_eq!(v.iter().map(|(part, _)| *part).collect::<Vec<_>>().join("."), env!("CARGO_PKG_VERSION"));
+    assert!(v.iter().all(|(_, name)| *name == "akin"));
+
+    let mut v = Vec::new();
+    akin! {
+        let &feat = env("AKIN_TEST_UNSET_FEATURES", split = ",", default = "json, csv,,toml");
+        v.push(*feat:str);
+    }
+    assert_eq!(v, ["json", "csv", "toml"]);
+}
+
+#[test]
+fn lines_file() {
+    let mut v = Vec::new();
+    akin! {
+        let &keyword = lines("data/keywords.txt");
+        v.push(*keyword:str);
+    }
+    assert_eq!(v, ["as", "break", "const", "continue"]);
+
+    akin! {
+        let &keyword = lines("data/keywords.txt");
+        let &variants = { *keyword:camel, };
+        #[derive(Debug)]
+        enum Keyword {
+            *variants
+        }
+    }
+    assert_eq!(format!("{:?}", Keyword::Continue), "Continue");
+
+    // The file is tracked wherever the code is written, like in the methods of an impl or in an expression
+    struct Keywords;
+    impl Keywords {
+        akin! {
+            let &keyword = lines("data/keywords.txt");
+            fn keyword_~*keyword() -> &'static str {
+                *keyword:str
+            }
+        }
+    }
+    let all = akin! {
+        #!repeat(1)
+        let &keyword = lines("data/keywords.txt");
+        [*keyword:str:join(,)]
+    };
+    assert_eq!((Keywords::keyword_break(), all.len()), ("break", 4));
+}
+
+#[test]
+fn variants_of() {
+    let mut v = Vec::new();
+    akin! {
+        let &variant = variants_of(emit) {
+            #[derive(Clone, Copy)]
+            enum Shape {
+                #[allow(dead_code)]
+                Point,
+                Circle(f32),
+                Rect { w: f32, h: f32 },
+            }
+        };
+        v.push(("*variant", "*variant.pattern"));
+    }
+    assert_eq!(v, [("Point", "Point"), ("Circle", "Circle(..)"), ("Rect", "Rect { .. }")]);
+
+    fn name(shape: Shape) -> &'static str {
+        akin! {
+            let &variant = variants_of {
+                enum Shape { Point, Circle(f32), Rect { w: f32, h: f32 } }
+            };
+            let &arms = { Shape::*variant.pattern => *variant:lower:str, };
+            match shape {
+                *arms
+            }
+        }
+    }
+    assert_eq!(name(Shape::Circle(1.0)), "circle");
+    assert_eq!(name(Shape::Rect { w: 1.0, h: 2.0 }), "rect");
+}
+
+#[test]
+fn fields_of() {
+    akin! {
+        let &(field, ty) = fields_of(emit) {
+            #[derive(Default)]
+            pub struct Config {
+                pub name: String,
+                #[allow(dead_code)]
+                pub(crate) retries: u8,
+                hooks: Vec<fn(u8) -> u8>,
+                limits: std::collections::HashMap<String, (u32, u32)>,
+            }
+        };
+        let &getters = {
+            fn *field(&self) -> &*ty {
+                &self.*field
+            }
+        };
+        impl Config {
+            *getters
+        }
+    }
+    let config = Config::default();
+    assert_eq!(config.retries(), &0);
+    assert!(config.hooks().is_empty() && config.limits().is_empty() && config.name().is_empty());
+
+    let mut v = Vec::new();
+    akin! {
+        let &field = fields_of { struct Pair(pub u8, Option<(i8, i8)>); };
+        v.push((*field.name:str, "*field.ty"));
+    }
+    assert_eq!(v, [("0", "u8"), ("1", "Option < (i8, i8) >")]);
+}
+
+#[test]
+fn methods_of() {
+    struct Inner(Vec<u8>);
+    struct Wrapper {
+        inner: Inner,
+    }
+
+    akin! {
+        let &method = methods_of(emit) {
+            trait Store {
+                const NAME: &'static str;
+                fn len(&self) -> usize;
+                fn push(&mut self, value: u8);
+                fn get<I: Into<usize>>(&self, index: I) -> Option<&u8> where I: Copy;
+                fn describe(&self, mut prefix: String) -> String {
+                    prefix.push_str(Self::NAME);
+                    prefix
+                }
+            }
+        };
+        let &delegated = {
+            fn *method.name *method.generics(*method.params) -> *method.ret *method.where {
+                self.inner.*method.name(*method.args)
+            }
+        };
+
+        impl Store for Inner {
+            const NAME: &'static str = "inner";
+            fn len(&self) -> usize {
+                self.0.len()
+            }
+            fn push(&mut self, value: u8) {
+                self.0.push(value)
+            }
+            fn get<I: Into<usize>>(&self, index: I) -> Option<&u8> where I: Copy {
+                self.0.get(index.into())
+            }
+        }
+
+        impl Store for Wrapper {
+            const NAME: &'static str = "wrapper";
+            *delegated
+        }
+    }
+
+    let mut wrapper = Wrapper { inner: Inner(Vec::new()) };
+    wrapper.push(7);
+    assert_eq!((wrapper.len(), wrapper.get(0usize)), (1, Some(&7)));
+    assert_eq!(wrapper.describe("store: ".to_owned()), "store: inner");
+
+    let mut v = Vec::new();
+    akin! {
+        let &(name, params, args, ret) = methods_of { trait Store { fn push(&mut self, value: u8); } };
+        v.push(("*name", "*params", "*args", "*ret"));
+    }
+    assert_eq!(v, [("push", "&mut self, value: u8", "value", "()")]);
+}
+
+#[test]
+fn cfg_values() {
+    trait Bits {
+        const BITS: u32;
+    }
+
+    akin! {
+        let &int = [u8, #[cfg(any())] u16, #[cfg(all())] u32];
+        impl Bits for *int {
+            const BITS: u32 = *int::BITS;
+        }
+    }
+    assert_eq!((u8::BITS, u32::BITS), (<u8 as Bits>::BITS, <u32 as Bits>::BITS));
+
+    let mut v = Vec::new();
+    akin! {
+        let &int = [{name: u8}, #[cfg(any())] {name: u16}, #[cfg(all())] {name: u32}];
+        let &size = [1, 2, #[cfg(not(any()))] 4];
+        v.push((*int.name::MAX as u64, *size));
+    }
+    assert_eq!(v, [(255, 1), (u32::MAX as u64, 4)]);
+
+    let mut v = Vec::new();
+    akin! {
+        let &int = [u8, #[cfg(any())] u16];
+        v.push(*int:join(,):str);
+    }
+    assert_eq!(v, ["u8,u16"]);
+}
+
+#[test]
+fn alias() {
+    let mut v = Vec::new();
+    akin! {
+        let &supported_integer_types = [{name: u8, bits: 8}, {name: i16, bits: 16}];
+        let &int = &supported_integer_types;
+        let &back = &int step(2) prev Start;
+        v.push(("*int.name", *int.bits, "*back.name", "*back.prev"));
+    }
+    assert_eq!(v, [("u8", 8, "u8", "Start"), ("i16", 16, "u8", "Start")]);
+
+    let mut v = Vec::new();
+    akin! {
+        let &n = [1, 2, 3] next 0;
+        let &m = &n;
+        v.push((*n, *m.next, *m.len));
+    }
+    assert_eq!(v, [(1, 2, 3), (2, 3, 3), (3, 0, 3)]);
+}
+
+#[test]
+fn concat() {
+    let mut v = Vec::new();
+    akin! {
+        let &signed = [i8, i16];
+        let &unsigned = [{name: u8, max: 255}, {name: u16, max: 65535}];
+        let &all = concat(&signed, &unsigned.name, [f32]);
+        let &bounded = concat(&unsigned, [{name: i8, max: 127}]);
+        v.push(("*all", *all.len, "*bounded.name", *bounded.max));
+    }
+    assert_eq!(v, [("i8", 5, "u8", 255), ("i16", 5, "u16", 65535), ("u8", 5, "i8", 127), ("u16", 5, "i8", 127), ("f32", 5, "i8", 127)]);
+}
+
+#[test]
+fn repeated_value() {
+    let mut v = Vec::new();
+    akin! {
+        let &name = [a, b, c, d];
+        let &pad = [NONE; 3];
+        let &zero = [0u8; 2];
+        v.push(("*name*pad", *zero));
+    }
+    assert_eq!(v, [("a", 0), ("b", 0), ("c", 0), ("d", 0)]);
+
+    let mut v = Vec::new();
+    akin! {
+        let &field = [{ty: u8, default: 1}; 2];
+        v.push((*field.default as *field.ty, *field.len));
+    }
+    assert_eq!(v, [(1, 2), (1, 2)]);
+}
+
+#[test]
+fn numbered_idents() {
+    trait Arity {
+        const ARITY: usize;
+    }
+
+    akin! {
+        let &T = cumulative idents(T, 1..=4);
+        impl<*T> Arity for (*T,) {
+            const ARITY: usize = *INDEX + 1;
+        }
+    }
+    assert_eq!(<(u8,) as Arity>::ARITY, 1);
+    assert_eq!(<(u8, u16, u32, u64) as Arity>::ARITY, 4);
+
+    let mut v = Vec::new();
+    akin! {
+        let &field = idents(field_, 0..3);
+        v.push("*field");
+    }
+    assert_eq!(v, ["field_0", "field_1", "field_2"]);
+}
+
+#[test]
+fn split_string() {
+    let mut v = Vec::new();
+    akin! {
+        let &perm = split("read,write, execute");
+        let &mode = split("0o4 | 0o2 | 0o1", "|");
+        v.push((*perm:str, *mode));
+    }
+    assert_eq!(v, [("read", 4), ("write", 2), ("execute", 1)]);
+}
+
+#[test]
+fn char_range() {
+    let mut v = Vec::new();
+    akin! {
+        let &c = 'a'..='e';
+        v.push(*c);
+    }
+    assert_eq!(v, ['a', 'b', 'c', 'd', 'e']);
+
+    fn hex_digit(c: char) -> Option<u32> {
+        akin! {
+            let &digit = '0'..='9';
+            let &letter = 'a'..'g';
+            let &digits = { *digit => Some(*INDEX), };
+            let &letters = { *letter => Some(*INDEX + 10), };
+            match c {
+                *digits
+                *letters
+                _ => None,
+            }
+        }
+    }
+    assert_eq!(hex_digit('7'), Some(7));
+    assert_eq!(hex_digit('f'), Some(15));
+    assert_eq!(hex_digit('\''), None);
+
+    let mut v = Vec::new();
+    akin! {
+        let &quote = '\''..='(';
+        v.push(*quote);
+    }
+    assert_eq!(v, ['\'', '(']);
+}
+
+#[test]
+fn negative_range() {
+    let mut v = Vec::new();
+    akin! {
+        let &n = -3..=-1;
+        let &m = -1..2;
+        v.push((*n, *m));
+    }
+    assert_eq!(v, [(-3, -1), (-2, 0), (-1, 1)]);
+
+    let mut v: Vec<u64> = Vec::new();
+    akin! {
+        let &n = 18446744073709551614..=18446744073709551615;
+        v.push(*n);
+    }
+    assert_eq!(v, [u64::MAX - 1, u64::MAX]);
+}
+
+#[test]
+fn range_step() {
+    let mut v = Vec::new();
+    akin! {
+        let &offset = 0..=64 step 16;
+        let &port = 8000..8010.step_by(4);
+        let &c = 'a'..='z' step 12;
+        v.push((*offset, *port, *c));
+    }
+    assert_eq!(v, [(0, 8000, 'a'), (16, 8004, 'm'), (32, 8008, 'y'), (48, 8008, 'y'), (64, 8008, 'y')]);
+}
+
+#[test]
+fn float_range() {
+    let mut v = Vec::new();
+    akin! {
+        let &x = 0.0..=1.0 step 0.25;
+        let &y = -0.5..0.4.step_by(0.3);
+        v.push((*x, *y));
+    }
+    assert_eq!(v, [(0.0, -0.5), (0.25, -0.2), (0.5, 0.1), (0.75, 0.1), (1.0, 0.1)]);
+
+    let mut v = Vec::new();
+    akin! {
+        let &x = 0f32..1.0 step 0.1;
+        v.push("*x");
+    }
+    assert_eq!(v, ["0.0f32", "0.1f32", "0.2f32", "0.3f32", "0.4f32", "0.5f32", "0.6f32", "0.7f32", "0.8f32", "0.9f32"]);
+}
+
+#[test]
+fn len_range_bound() {
+    let mut v = Vec::new();
+    akin! {
+        let &ty = [u8, u16, u32];
+        let &i = 0..len(&ty);
+        let &n = 1..=len(&ty);
+        v.push((*i, *n, *ty::BITS));
+    }
+    assert_eq!(v, [(0, 1, 8), (1, 2, 16), (2, 3, 32)]);
+}
+
+#[test]
+fn hex_range() {
+    let mut v = Vec::new();
+    akin! {
+        let &opcode = 0x00..=0xFF step 0x40 as hex;
+        v.push((stringify!(*opcode), *opcode));
+    }
+    assert_eq!(v, [("0x00", 0), ("0x40", 64), ("0x80", 128), ("0xC0", 192)]);
+}
+
+#[test]
+fn suffixed_literals() {
+    let mut v = Vec::new();
+    akin! {
+        let &n = 0u8..3;
+        let &x = 1.50f32..=2.0 step 0.25;
+        let &h = 0x0u16..=0x20 step 0x10 as hex;
+        v.push((stringify!(*n), stringify!(*x), stringify!(*h)));
+    }
+    assert_eq!(v, [("0u8", "1.50f32", "0x00u16"), ("1u8", "1.75f32", "0x10u16"), ("2u8", "2.00f32", "0x20u16")]);
+
+    // Literals are kept whole whatever is written after them, like the `.` of a method call or a range
+    let mut f = Vec::new();
+    akin! {
+        let &x = [2., 0.5f32, 1e3];
+        f.push((*x.to_string(), (*x..*x + 1.).contains(&2.)));
+    }
+    assert_eq!(f, [("2".to_owned(), true), ("0.5".to_owned(), false), ("1000".to_owned(), false)]);
+}
+
+#[test]
+fn rev() {
+    let mut v = Vec::new();
+    akin! {
+        let &op = [{name: add, sym: "+"}, {name: sub, sym: "-"}, {name: mul, sym: "*"}];
+        let &back = rev(&op);
+        v.push((stringify!(*back.name), *back.sym));
+    }
+    assert_eq!(v, [("mul", "*"), ("sub", "-"), ("add", "+")]);
+}
+
+#[test]
+fn sort() {
+    let mut v = Vec::new();
+    akin! {
+        let &size = [{n: 128, ty: u128}, {n: 8, ty: u8}, {n: 0x20, ty: u32}, {n: 16, ty: u16}];
+        let &size = sort(&size.n);
+        v.push(*size);
+    }
+    assert_eq!(v, [8, 16, 32, 128]);
+
+    let mut v = Vec::new();
+    akin! {
+        let &ty = sort([u8, 2.5, b, 1, a]);
+        v.push(stringify!(*ty));
+    }
+    assert_eq!(v, ["1", "2.5", "a", "b", "u8"]);
+}
+
+#[test]
+fn dedup() {
+    trait Integer {
+        fn bits() -> u32;
+    }
+    akin! {
+        let &ty = concat([u8, usize], [u64, usize, u8]);
+        let &ty = dedup(&ty);
+        impl Integer for *ty {
+            fn bits() -> u32 {
+                *ty::BITS
+            }
+        }
+    }
+    assert_eq!(<u8 as Integer>::bits(), 8);
+    assert_eq!(<u64 as Integer>::bits(), 64);
+    assert_eq!(<usize as Integer>::bits(), usize::BITS);
+}
+
+#[test]
+fn zip() {
+    let mut v = Vec::new();
+    akin! {
+        let &status = [{name: ok, code: 200}, {name: not_found, code: 404}];
+        let &(status, reason) = zip(&status, ["OK", "Not Found"]);
+        v.push((*status.code, *reason));
+    }
+    assert_eq!(v, [(200, "OK"), (404, "Not Found")]);
+
+    let pairs = akin! {
+        let &pair = zip([1, 2, 3], [a, b, c]);
+        let &all = { stringify!(*pair), };
+        [*all]
+    };
+    assert_eq!(pairs, ["(1, a)", "(2, b)", "(3, c)"]);
+}
+
+#[test]
+fn cross() {
+    let mut v = Vec::new();
+    akin! {
+        let &(from, to) = cross([u8, u16], [u32, u64]);
+        let &n = 0..len(&from);
+        v.push((*n, *from::BITS, *to::BITS));
+    }
+    assert_eq!(v, [(0, 8, 32), (1, 8, 64), (2, 16, 32), (3, 16, 64)]);
+}
+
+#[test]
+fn enumerate() {
+    let mut v = Vec::new();
+    akin! {
+        let &ty = [{name: u8, min: 0}, {name: i8, min: -128}];
+        let &(i, ty) = enumerate(&ty);
+        v.push((*i, stringify!(*ty.name), *ty.min));
+    }
+    assert_eq!(v, [(0, "u8", 0), (1, "i8", -128)]);
+
+    let x = akin! {
+        let &e = enumerate([a, b]);
+        let &s = { stringify!(*e), };
+        [*s]
+    };
+    assert_eq!(x, ["(0, a)", "(1, b)"]);
+}
+
+#[test]
+fn slice() {
+    let mut v = Vec::new();
+    akin! {
+        let &reg = [r0, r1, r2, r3, r4, r5];
+        let &arg = &reg[1..=3];
+        v.push((stringify!(*arg), stringify!(*arg.prev), stringify!(*arg.next)));
+    }
+    assert_eq!(v, [("r1", "r0", "r2"), ("r2", "r1", "r3"), ("r3", "r2", "r4")]);
+
+    let mut v = Vec::new();
+    akin! {
+        let &n = [{a: 1}, {a: 2}, {a: 3}, {a: 4}];
+        let &tail = &n.a[2..];
+        let &head = rev(&n[..2]);
+        v.push((*tail, *head.a));
+    }
+    assert_eq!(v, [(3, 2), (4, 1)]);
+}
+
+#[test]
+fn filter() {
+    let mut v = Vec::new();
+    akin! {
+        let &int = [{name: i8, min: -128}, {name: i64, min: 0}, {name: u8, min: 0}, {name: u64, min: 0}];
+        let &unsigned = filter(&int, field = "name", starts_with = "u");
+        let &wide = filter(&int.name, ends_with = "64");
+        v.push((stringify!(*unsigned.name), *unsigned.min, stringify!(*wide)));
+    }
+    assert_eq!(v, [("u8", 0, "i64"), ("u64", 0, "u64")]);
+}
+
+#[test]
+fn map() {
+    let mut v = Vec::new();
+    akin! {
+        let &name = [{id: get_user, code: 1}, {id: list_users, code: 2}];
+        let &Name = map(&name.id, :camel);
+        let &padded = map([7, 42], :pad(3):str);
+        v.push((stringify!(*Name), *padded));
+    }
+    assert_eq!(v, [("GetUser", "007"), ("ListUsers", "042")]);
+}
+
+#[test]
+fn chunks_windows() {
+    let mut v = Vec::new();
+    akin! {
+        let &lane = chunks([1, 2, 3, 4, 5, 6, 7], 3);
+        v.push(([*lane].len(), *lane.0));
+    }
+    assert_eq!(v, [(3, 1), (3, 4), (1, 7)]);
+
+    let mut v = Vec::new();
+    akin! {
+        let &stage = [parse, check, emit];
+        let &step = windows(&stage, 2);
+        v.push(concat!(stringify!(*step.0), "->", stringify!(*step.1)));
+    }
+    assert_eq!(v, ["parse->check", "check->emit"]);
+}
+
+#[test]
+fn append() {
+    let mut v = Vec::new();
+    akin! {
+        let &ty = [{name: u8, bits: 8}];
+        let &ty += [{name: u16, bits: 16}, {name: u32}];
+        let &ty += [{name: u64, bits: 64}];
+        v.push((stringify!(*ty.name), "*ty.bits"));
+    }
+    assert_eq!(v, [("u8", "8"), ("u16", "16"), ("u32", ""), ("u64", "64")]);
+
+    let mut v = Vec::new();
+    akin! {
+        let &n = [1, 2] next 0;
+        let &n += 3..=4;
+        v.push((*n, *n.next));
+    }
+    assert_eq!(v, [(1, 2), (2, 3), (3, 4), (4, 0)]);
+}
+
+#[test]
+fn set_operations() {
+    let mut v = Vec::new();
+    akin! {
+        let &a = [{ty: u8, n: 1}, {ty: u16, n: 2}, {ty: u32, n: 3}];
+        let &b = [u16, u64, u32, u16];
+        let &union = union(&a.ty, &b);
+        let &common = intersect(&b, &a.ty);
+        let &only_a = difference(&a.ty, [u16]);
+        v.push((*union::BITS, *common::BITS, *only_a::BITS));
+    }
+    assert_eq!(v, [(8, 16, 8), (16, 32, 32), (32, 32, 32), (64, 32, 32)]);
+}
+
+#[test]
+fn permutations_combinations() {
+    let mut v = Vec::new();
+    akin! {
+        let &(from, to) = permutations([u8, u16, u32], 2);
+        v.push((*from::BITS, *to::BITS));
+    }
+    assert_eq!(v, [(8, 16), (8, 32), (16, 8), (16, 32), (32, 8), (32, 16)]);
+
+    let x = akin! {
+        let &pick = combinations([a, b, c, d], 3);
+        let &s = { stringify!(*pick), };
+        [*s]
+    };
+    assert_eq!(x, ["(a, b, c)", "(a, b, d)", "(a, c, d)", "(b, c, d)"]);
+}
+
+#[test]
+fn mismatch_policy() {
+    let mut v = Vec::new();
+    akin! {
+        #!mismatch(cycle)
+        let &n = [1, 2, 3, 4, 5];
+        let &m = [10, 20];
+        v.push(*n + *m);
+    }
+    assert_eq!(v, [11, 22, 13, 24, 15]);
+
+    let mut v = Vec::new();
+    akin! {
+        let &n = [1, 2, 3];
+        let &name = [one] mismatch(pad);
+        let &last = [a, b];
+        v.push((*n, "*name", "*last"));
+    }
+    assert_eq!(v, [(1, "one", "a"), (2, "", "b"), (3, "", "b")]);
+
+    let mut v = Vec::new();
+    akin! {
+        #!mismatch(pad)
+        #!repeat(3)
+        let &name = [a] mismatch(last);
+        let &other = [b];
+        v.push(("*name", "*other"));
+    }
+    assert_eq!(v, [("a", "b"), ("a", ""), ("a", "")]);
+}
+
+#[test]
+fn large_expansion() {
+    // Over a MiB of copies, so they're written by several threads
+    akin! {
+        let &table = [first, second, third, fourth];
+        let &n = 0..50000;
+        const *table:upper~_TABLE: &[u32] = &[*n:join(,)];
+    }
+    assert_eq!(FIRST_TABLE.len(), 50000);
+    assert_eq!(SECOND_TABLE[1234], 1234);
+    assert_eq!(FOURTH_TABLE.last(), Some(&49999));
+}
+
+#[test]
+fn misspelled_names() {
+    // `*iter` looks like `*item`, but is a dereference
+    let mut out = Vec::new();
+    let v = [10, 20];
+    for iter in v.iter() {
+        akin! {
+            #!allow(misspelled)
+            let &item = [1, 2];
+            out.push(*iter + *item);
+        }
+    }
+    assert_eq!(out, [11, 12, 21, 22]);
+
+    let mut out = Vec::new();
+    for iter in v.iter() {
+        akin! {
+            let &item = [1, 2];
+            out.push(*~iter + *item);
+        }
+    }
+    assert_eq!(out, [11, 12, 21, 22]);
+}
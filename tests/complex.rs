@@ -53,3 +53,403 @@ fn replace() {
 }
 
 // tests for the akin! macro
+
+#[test]
+fn deref_forwarding() {
+    use std::ops::{Deref, DerefMut};
+
+    trait Shape {
+        fn area(&self) -> f64;
+        fn scale(&mut self, by: f64);
+    }
+
+    trait Named {
+        fn name(&self) -> String;
+        fn rename(&mut self, to: &str) -> bool;
+    }
+
+    struct Square {
+        side: f64,
+        name: String,
+    }
+
+    impl Shape for Square {
+        fn area(&self) -> f64 {
+            self.side * self.side
+        }
+        fn scale(&mut self, by: f64) {
+            self.side *= by;
+        }
+    }
+
+    impl Named for Square {
+        fn name(&self) -> String {
+            self.name.clone()
+        }
+        fn rename(&mut self, to: &str) -> bool {
+            let changed = self.name != to;
+            self.name = to.to_owned();
+            changed
+        }
+    }
+
+    struct Wrapper<T> {
+        inner: T,
+    }
+
+    impl<T> Deref for Wrapper<T> {
+        type Target = T;
+        fn deref(&self) -> &T {
+            &self.inner
+        }
+    }
+
+    impl<T> DerefMut for Wrapper<T> {
+        fn deref_mut(&mut self) -> &mut T {
+            &mut self.inner
+        }
+    }
+
+    akin! {
+        let &shape_method = [area, scale];
+        let &shape_recv = [{&self}, {&mut self}];
+        let &shape_params = [NONE, {by: f64}];
+        let &shape_args = [NONE, by];
+        let &shape_ret = [f64, ()];
+        let &shape_fwd = {
+            fn *shape_method(*shape_recv, *shape_params) -> *shape_ret {
+                (**self).*shape_method(*shape_args)
+            }
+        };
+
+        let &named_method = [name, rename];
+        let &named_recv = [{&self}, {&mut self}];
+        let &named_params = [NONE, {to: &str}];
+        let &named_args = [NONE, to];
+        let &named_ret = [String, bool];
+        let &named_fwd = {
+            fn *named_method(*named_recv, *named_params) -> *named_ret {
+                (**self).*named_method(*named_args)
+            }
+        };
+
+        let &traits = [Shape, Named];
+        let &forwarded = [{*shape_fwd}, {*named_fwd}];
+
+        impl<T: *traits> *traits for Wrapper<T> {
+            *forwarded
+        }
+    }
+
+    fn area_of(s: &impl Shape) -> f64 {
+        s.area()
+    }
+
+    let mut w = Wrapper { inner: Square { side: 2., name: "sq".to_owned() } };
+    assert_eq!(area_of(&w), 4.);
+    Shape::scale(&mut w, 1.5);
+    assert_eq!(Shape::area(&w), 9.);
+    assert_eq!(Named::name(&w), "sq");
+    assert!(Named::rename(&mut w, "big"));
+    assert!(!Named::rename(&mut w, "big"));
+    assert_eq!(w.inner.name, "big");
+}
+
+#[test]
+fn enum_str_conversion() {
+    use std::str::FromStr;
+
+    akin! {
+        let &variant = [Red, Green, Blue];
+        // The first name of each list is the canonical one, the rest are aliases.
+        let &names = [["red", "r"], ["green"], ["blue", "b", "navy"]];
+
+        let &variants = { *variant, };
+        let &as_str_arm = { Self::*variant => *names[0], };
+        let &from_str_arm = { s if *names.contains(&s) => Ok(Self::*variant), };
+        let &entry = { (*names[0], Color::*variant), };
+
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        enum Color {
+            *variants
+        }
+
+        impl Color {
+            const ALL: [(&'static str, Color); [*entry].len()] = [*entry];
+
+            fn as_str(self) -> &'static str {
+                match self {
+                    *as_str_arm
+                }
+            }
+        }
+
+        impl FromStr for Color {
+            type Err = String;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s {
+                    *from_str_arm
+                    _ => Err(format!("unknown color '{s}'")),
+                }
+            }
+        }
+    }
+
+    assert_eq!(Color::ALL.len(), 3);
+    for (name, color) in Color::ALL {
+        assert_eq!(color.as_str(), name);
+        assert_eq!(Color::from_str(name), Ok(color));
+    }
+    assert_eq!("r".parse(), Ok(Color::Red));
+    assert_eq!("navy".parse(), Ok(Color::Blue));
+    assert_eq!("b".parse(), Ok(Color::Blue));
+    assert!("purple".parse::<Color>().is_err());
+}
+
+#[test]
+fn subcommand_dispatch() {
+    akin! {
+        let &cmd = [Add, Remove, List];
+        let &name = ["add", "rm", "ls"];
+        let &args = [{ path: String, force: bool }, { path: String }, NONE];
+        let &handler = [
+            { format!("add {} (force: {})", args.path, args.force) },
+            { format!("rm {}", args.path) },
+            { String::from("ls") }
+        ];
+
+        let &structs = {
+            #[doc = concat!("Arguments of the `", *name, "` subcommand.")]
+            #[derive(Debug, Default, Clone, PartialEq)]
+            struct *cmd~Args {
+                *args
+            }
+        };
+        let &variants = { #[doc = *name] *cmd(*cmd~Args), };
+        let &parse_arm = { *name => Some(Command::*cmd(Default::default())), };
+        let &dispatch_arm = {
+            Command::*cmd(args) => {
+                #[allow(unused_variables)]
+                let args: *cmd~Args = args;
+                *handler
+            }
+        };
+
+        *structs
+
+        #[derive(Debug, Clone, PartialEq)]
+        enum Command {
+            *variants
+        }
+
+        impl Command {
+            fn parse(name: &str) -> Option<Command> {
+                match name {
+                    *parse_arm
+                    _ => None,
+                }
+            }
+
+            fn run(self) -> String {
+                match self {
+                    *dispatch_arm
+                }
+            }
+        }
+    }
+
+    assert_eq!(Command::parse("ls"), Some(Command::List(ListArgs {})));
+    assert_eq!(Command::parse("mv"), None);
+    assert_eq!(Command::parse("ls").unwrap().run(), "ls");
+    assert_eq!(Command::parse("rm").unwrap().run(), "rm ");
+
+    let add = Command::Add(AddArgs { path: "a.txt".into(), force: true });
+    assert_eq!(add.clone().run(), "add a.txt (force: true)");
+    assert_ne!(add, Command::Add(AddArgs::default()));
+    assert_eq!(RemoveArgs::default(), RemoveArgs { path: String::new() });
+}
+
+#[test]
+fn sql_column_binding() {
+    #[derive(Debug, Clone, PartialEq)]
+    enum Value {
+        Int(i64),
+        Text(String),
+    }
+
+    #[derive(Default)]
+    struct Query {
+        sql: String,
+        binds: Vec<Value>,
+    }
+
+    impl Query {
+        fn bind(mut self, v: Value) -> Self {
+            self.binds.push(v);
+            self
+        }
+    }
+
+    akin! {
+        let &column = [user_id, user_name, age];
+        let &field = [id, name, age];
+        let &ty = [i64, String, i64];
+        let &sql_type = [Int, Text, Int];
+        let &idx = 0..3;
+        let &param = 1..=3;
+
+        let &sep = [NONE, {", ",}];
+        let &select_col = { *sep "*column", };
+        let &field_decl = { *field: *ty, };
+        let &from_row = {
+            *field: match &row[*idx] {
+                Value::*sql_type(v) => v.clone(),
+                v => return Err(format!("column *column: unexpected {v:?}")),
+            },
+        };
+        let &bind = { .bind(Value::*sql_type(self.*field.clone())) };
+        let &placeholder = { *sep "$*param", };
+
+        #[derive(Debug, PartialEq)]
+        struct User {
+            *field_decl
+        }
+
+        impl User {
+            const COLUMNS: &'static str = concat!(*select_col);
+
+            fn from_row(row: &[Value]) -> Result<Self, String> {
+                Ok(User { *from_row })
+            }
+
+            fn insert(&self) -> Query {
+                let sql = concat!("INSERT INTO users (", *select_col ") VALUES (", *placeholder ")");
+                Query { sql: sql.to_owned(), binds: Vec::new() } *bind
+            }
+        }
+    }
+
+    assert_eq!(User::COLUMNS, "user_id, user_name, age");
+
+    let row = [Value::Int(7), Value::Text("ana".into()), Value::Int(30)];
+    let user = User::from_row(&row).unwrap();
+    assert_eq!(user, User { id: 7, name: "ana".into(), age: 30 });
+    assert!(User::from_row(&[Value::Text("7".into()), row[1].clone(), row[2].clone()]).is_err());
+
+    let q = user.insert();
+    assert_eq!(q.sql, "INSERT INTO users (user_id, user_name, age) VALUES ($1, $2, $3)");
+    assert_eq!(q.binds, row);
+}
+
+#[test]
+fn log_level_methods() {
+    mod tracing {
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        #[allow(clippy::upper_case_acronyms)]
+        pub enum Level {
+            TRACE,
+            DEBUG,
+            INFO,
+            WARN,
+            ERROR,
+        }
+
+        macro_rules! event {
+            ($sink:expr, $level:expr, $($arg:tt)+) => {
+                $sink.push(($level, format!($($arg)+)))
+            };
+        }
+        pub(crate) use event;
+    }
+
+    #[derive(Default)]
+    struct Logger {
+        events: Vec<(tracing::Level, String)>,
+    }
+
+    // Every level is crossed with every event, the level gives both the method prefix and the `Level` variant
+    akin! {
+        #!product
+        let &lvl = [trace, debug, info, warn, error];
+        let &event = [connect, disconnect];
+
+        impl Logger {
+            fn *lvl~_~*event(&mut self, peer: &str) {
+                tracing::event!(self.events, tracing::Level::*lvl:upper, "*event {}", peer);
+            }
+        }
+    }
+
+    let mut log = Logger::default();
+    log.trace_connect("a");
+    log.info_disconnect("b");
+    log.error_connect("c");
+    log.warn_disconnect("d");
+    log.debug_connect("e");
+
+    use tracing::Level;
+    assert_eq!(
+        log.events,
+        [
+            (Level::TRACE, "connect a".to_owned()),
+            (Level::INFO, "disconnect b".to_owned()),
+            (Level::ERROR, "connect c".to_owned()),
+            (Level::WARN, "disconnect d".to_owned()),
+            (Level::DEBUG, "connect e".to_owned()),
+        ]
+    );
+}
+
+#[test]
+fn bitfield_accessors() {
+    #[derive(Default)]
+    struct Flags(u32);
+
+    akin! {
+        let &field = [mode, level, ready, channel];
+        let &offset = [0, 2, 7, 8];
+        let &width = [2, 5, 1, 4];
+
+        let &accessors = {
+            fn *field(&self) -> u32 {
+                (self.0 >> *offset) & Self::mask(*width)
+            }
+
+            fn set_~*field(&mut self, v: u32) {
+                assert!(v <= Self::mask(*width), "value out of range for field '*field'");
+                self.0 = (self.0 & !(Self::mask(*width) << *offset)) | (v << *offset);
+            }
+        };
+        let &check = {
+            assert!(*width <= u32::BITS - *offset, "field '*field' does not fit in 32 bits");
+            assert!(used & (Flags::mask(*width) << *offset) == 0, "field '*field' overlaps a previous field");
+            used |= Flags::mask(*width) << *offset;
+        };
+
+        impl Flags {
+            const fn mask(width: u32) -> u32 {
+                ((1u64 << width) - 1) as u32
+            }
+
+            *accessors
+        }
+
+        const _: () = {
+            let mut used = 0u32;
+            *check
+            let _ = used;
+        };
+    }
+
+    let mut f = Flags::default();
+    f.set_mode(3);
+    f.set_level(17);
+    f.set_ready(1);
+    f.set_channel(9);
+    assert_eq!((f.mode(), f.level(), f.ready(), f.channel()), (3, 17, 1, 9));
+    assert_eq!(f.0, 3 | (17 << 2) | (1 << 7) | (9 << 8));
+
+    f.set_level(0);
+    assert_eq!((f.mode(), f.level(), f.ready(), f.channel()), (3, 0, 1, 9));
+}
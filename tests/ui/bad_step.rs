@@ -0,0 +1,8 @@
+use akin::akin;
+
+fn main() {
+    akin! {
+        let &n = 0..10 step x;
+        let _ = *n;
+    }
+}
@@ -0,0 +1,8 @@
+use akin::akin;
+
+fn main() {
+    akin! {
+        let &n = [1, 0];
+        let _ = *{10 / n};
+    }
+}
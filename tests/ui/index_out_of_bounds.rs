@@ -0,0 +1,8 @@
+use akin::akin;
+
+fn main() {
+    akin! {
+        let &n = [1, 2];
+        let _ = *n.[2];
+    }
+}
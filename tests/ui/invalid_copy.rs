@@ -0,0 +1,10 @@
+use akin::akin;
+
+fn main() {
+    let x = 1;
+    let y = 2;
+    akin! {
+        let &part = split("x), y", ",");
+        let _ = [100, 101, 102, 103, 104, 105, 106, 107, 108, 109, *part, 200, 201, 202, 203, 204, 205, 206, 207, 208, 209];
+    }
+}
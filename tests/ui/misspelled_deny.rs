@@ -0,0 +1,13 @@
+use akin::akin;
+
+fn main() {
+    let mut out = Vec::new();
+    let v = [10, 20];
+    for iter in v.iter() {
+        akin! {
+            #!deny(misspelled)
+            let &item = [1, 2];
+            out.push(*iter + *item);
+        }
+    }
+}
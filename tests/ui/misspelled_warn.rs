@@ -0,0 +1,17 @@
+#![deny(deprecated)]
+
+use akin::akin;
+
+akin! {
+    let &item = [1, 2];
+    struct Add~*item;
+    impl Add~*item {
+        fn add(iter: &i32) -> i32 {
+            *iter + *item
+        }
+    }
+}
+
+fn main() {
+    let _ = Add1::add(&1) + Add2::add(&2);
+}
@@ -0,0 +1,8 @@
+use akin::akin;
+
+fn main() {
+    akin! {
+        let &value = [1, two, 3];
+        let _ = *value:hex;
+    }
+}
@@ -0,0 +1,8 @@
+use akin::akin;
+
+fn main() {
+    akin! {
+        let &pad = [NONE; 1_000_000_000_000];
+        let _ = *pad;
+    }
+}
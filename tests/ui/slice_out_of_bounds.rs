@@ -0,0 +1,9 @@
+use akin::akin;
+
+fn main() {
+    akin! {
+        let &a = [1, 2, 3];
+        let &b = &a[1..7];
+        let _ = *b;
+    }
+}
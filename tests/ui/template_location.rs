@@ -0,0 +1,13 @@
+use akin::akin;
+
+fn main() {
+    akin! {
+        let &ty = [u8, u16];
+        let &v = [[0u8; 2][0], "two"];
+
+        let x: *ty =
+            [*v][0];
+        let _: bool =
+            x;
+    }
+}
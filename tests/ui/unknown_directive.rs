@@ -0,0 +1,9 @@
+use akin::akin;
+
+fn main() {
+    akin! {
+        #![shuffle]
+        let &n = [1, 2];
+        let _ = *n;
+    }
+}
@@ -0,0 +1,9 @@
+use akin::akin;
+
+fn main() {
+    akin! {
+        let &names = ["a", "b"];
+        let &upper = map(&names, :shout);
+        let _ = *upper;
+    }
+}
@@ -0,0 +1,8 @@
+use akin::akin;
+
+fn main() {
+    akin! {
+        let &n = shuffle(1, 2, 3);
+        let _ = *n;
+    }
+}
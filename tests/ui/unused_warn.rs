@@ -0,0 +1,26 @@
+#![deny(deprecated)]
+
+use akin::akin;
+
+akin! {
+    let &n = [1, 2];
+    let &stale = [3, 4, 5];
+    fn get_~*n() -> i32 {
+        *n
+    }
+}
+
+akin! {
+    let &name = [A, B];
+    let &stale = [3, 4, 5];
+    const *name: i32 = 0;
+}
+
+fn main() {
+    let x = akin! {
+        let &n = [1];
+        let &stale = [3, 4, 5];
+        *n + 1
+    };
+    let _ = get_1() + get_2() + A + B + x;
+}
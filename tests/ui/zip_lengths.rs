@@ -0,0 +1,8 @@
+use akin::akin;
+
+fn main() {
+    akin! {
+        let &pairs = zip([1, 2, 3], [4, 5]);
+        let _ = *pairs;
+    }
+}